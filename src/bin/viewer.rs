@@ -12,15 +12,15 @@ use tokio::sync::{mpsc, oneshot, watch};
 use tracing::{debug, error, info, warn};
 use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalPosition;
-use winit::event::{KeyEvent, WindowEvent};
+use winit::event::{DeviceEvent, DeviceId, KeyEvent, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
 use winit::keyboard::PhysicalKey;
 use winit::monitor::MonitorHandle;
-use winit::window::{Fullscreen, Window, WindowId};
+use winit::window::{CursorGrabMode, Fullscreen, Window, WindowId};
 
 use jetkvm_client::jetkvm_rpc_client::{JetKvmRpcClient, SignalingMethod};
 use jetkvm_client::keyboard::rpc_keyboard_report;
-use jetkvm_client::mouse::{rpc_abs_mouse_report, rpc_wheel_report};
+use jetkvm_client::mouse::{rpc_abs_mouse_report, rpc_rel_mouse_report, scroll};
 use jetkvm_client::system::rpc_set_edid;
 use jetkvm_client::viewer::edid::{
     build_safe_edid_hex, jetkvm_default_edid_hex, try_build_edid_hex,
@@ -89,6 +89,11 @@ struct Args {
     /// 0 = maximum sharpening, ~2 = no sharpening. Default 0.0.
     #[arg(long, default_value_t = 0.0)]
     sharpness: f32,
+    /// Start in relative mouse mode: the local cursor is grabbed and raw
+    /// motion is sent as relative reports, for games, BIOS screens and OSes
+    /// without an absolute-pointer driver. Ctrl+Alt+G toggles the mode.
+    #[arg(long)]
+    relative_mouse: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -116,6 +121,9 @@ struct App {
     mouse: MouseState,
     last_cursor: PhysicalPosition<f64>,
     sharpness: f32,
+    /// Whether relative mouse mode is wanted. Survives reconnects, unlike
+    /// `MouseState`, and is applied to the window whenever it is shown.
+    relative_mouse: bool,
     /// Tracks the desired visibility state. Drives the render loop: while
     /// `false`, `RedrawRequested` short-circuits and does not re-queue
     /// itself, so we stop calling `get_current_texture()` on a hidden
@@ -129,6 +137,7 @@ impl App {
         input_tx: mpsc::UnboundedSender<InputEvent>,
         monitor_tx: oneshot::Sender<(u32, u32, u32)>,
         sharpness: f32,
+        relative_mouse: bool,
     ) -> Self {
         Self {
             window: None,
@@ -140,10 +149,32 @@ impl App {
             mouse: MouseState::default(),
             last_cursor: PhysicalPosition::new(0.0, 0.0),
             sharpness,
+            relative_mouse,
             visible: false,
         }
     }
 
+    /// Grabs the local cursor for relative mode or releases it. Falls back
+    /// to confining the cursor on platforms that cannot lock it in place;
+    /// if neither works, relative mode is left off.
+    fn set_relative_mouse(&mut self, relative: bool) {
+        let Some(window) = &self.window else {
+            return;
+        };
+        let grabbed = if relative {
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+                .map_err(|e| warn!("cannot grab cursor for relative mouse mode: {e}"))
+                .is_ok()
+        } else {
+            let _ = window.set_cursor_grab(CursorGrabMode::None);
+            false
+        };
+        info!("relative mouse mode {}", if grabbed { "on" } else { "off" });
+        self.mouse.relative = grabbed;
+    }
+
     fn create_window_for_monitor(
         &mut self,
         event_loop: &ActiveEventLoop,
@@ -224,10 +255,12 @@ impl ApplicationHandler<UserEvent> for App {
                     w.set_visible(true);
                     w.request_redraw();
                 }
+                self.set_relative_mouse(self.relative_mouse);
             }
             UserEvent::Hide => {
                 self.visible = false;
                 self.keyboard = KeyboardState::default();
+                self.set_relative_mouse(false);
                 self.mouse = MouseState::default();
                 if let Some(w) = &self.window {
                     w.set_visible(false);
@@ -276,6 +309,9 @@ impl ApplicationHandler<UserEvent> for App {
             }
             WindowEvent::CursorMoved { position, .. } => {
                 self.last_cursor = position;
+                if self.mouse.relative {
+                    return;
+                }
                 if let Some(win) = &self.window {
                     let size = win.inner_size();
                     let ev = self.mouse.handle_motion(
@@ -317,6 +353,15 @@ impl ApplicationHandler<UserEvent> for App {
                     debug!("dropping unidentified physical key");
                     return;
                 };
+                if self.keyboard.is_relative_mode_hotkey(code, state) {
+                    // Ctrl and Alt have already reached the target.
+                    if self.input_tx.send(self.keyboard.release_all()).is_err() {
+                        warn!("input channel closed; tokio side likely exited");
+                    }
+                    self.relative_mouse = !self.mouse.relative;
+                    self.set_relative_mouse(self.relative_mouse);
+                    return;
+                }
                 if let Some(ev) = self.keyboard.handle(code, state) {
                     if self.input_tx.send(ev).is_err() {
                         warn!("input channel closed; tokio side likely exited");
@@ -330,16 +375,27 @@ impl ApplicationHandler<UserEvent> for App {
                 if !focused {
                     // Release all held keys when focus is lost so the remote
                     // host doesn't see a stuck modifier.
-                    self.keyboard = KeyboardState::default();
-                    let _ = self.input_tx.send(InputEvent::Keyboard {
-                        modifier: 0,
-                        keys: vec![],
-                    });
+                    let _ = self.input_tx.send(self.keyboard.release_all());
+                    // Give the cursor back to the host; the grab is restored
+                    // when focus returns if relative mode is still wanted.
+                    self.set_relative_mouse(false);
+                } else if self.relative_mouse && !self.mouse.relative {
+                    self.set_relative_mouse(true);
                 }
             }
             _ => {}
         }
     }
+
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        // Raw device motion is unaffected by the cursor grab and keeps
+        // arriving when the locked cursor cannot move.
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            if let Some(ev) = self.mouse.handle_device_motion(dx, dy) {
+                let _ = self.input_tx.send(ev);
+            }
+        }
+    }
 }
 
 fn install_logging(debug: bool) {
@@ -424,8 +480,9 @@ fn main() -> AnyResult<()> {
         })?;
 
     let sharpness = args.sharpness;
+    let relative_mouse = args.relative_mouse;
     let _ = (args, proxy); // moved into tokio thread / handled there
-    let mut app = App::new(frame_rx, input_tx, monitor_tx, sharpness);
+    let mut app = App::new(frame_rx, input_tx, monitor_tx, sharpness, relative_mouse);
     event_loop.run_app(&mut app)?;
     Ok(())
 }
@@ -538,10 +595,10 @@ async fn run_session(
     let mut track_rx = client.video_track_watcher();
 
     info!("waiting for video track (HDMI source must be connected to JetKVM)...");
-    let track = tokio::time::timeout(Duration::from_secs(30), async {
+    tokio::time::timeout(Duration::from_secs(30), async {
         loop {
-            if let Some(t) = track_rx.borrow().clone() {
-                return Ok::<_, anyhow::Error>(t);
+            if track_rx.borrow().is_some() {
+                return Ok::<_, anyhow::Error>(());
             }
             track_rx
                 .changed()
@@ -561,21 +618,15 @@ async fn run_session(
     let pipeline = Arc::new(StreamingPipeline::new(frame_tx.clone())?);
 
     let pipeline_for_pump = pipeline.clone();
-    let track_for_pump = track.clone();
+    // The client already reads the track; take packets from its fan-out so
+    // screenshots and recordings on this connection still get theirs.
+    let mut rtp_packets = client.video_capture.subscribe_rtp(Duration::ZERO).await?;
     let rtp_pump = tokio::spawn(async move {
-        loop {
-            match track_for_pump.read_rtp().await {
-                Ok((rtp, _)) => {
-                    let bytes = serialize_rtp(&rtp);
-                    if let Err(e) = pipeline_for_pump.push_rtp(bytes) {
-                        warn!("pipeline push failed: {e}");
-                        break;
-                    }
-                }
-                Err(e) => {
-                    debug!("read_rtp failed: {e}");
-                    break;
-                }
+        while let Some(rtp) = rtp_packets.recv().await {
+            let bytes = serialize_rtp(&rtp);
+            if let Err(e) = pipeline_for_pump.push_rtp(bytes) {
+                warn!("pipeline push failed: {e}");
+                break;
             }
         }
         debug!("RTP pump exiting");
@@ -607,6 +658,9 @@ async fn run_session(
     //   2. Peer state — detect drop via Failed/Closed.
     //   3. Watchdog — fallback drop signal when peer state wedges.
     //   4. Input events — dispatch RPCs serially (key ordering matters).
+    // Cleared once the firmware answers horizontal scrolling with an error;
+    // firmware that ignores wheelX silently cannot be detected.
+    let mut horizontal_wheel = true;
     let session_result = loop {
         tokio::select! {
             r = frame_watch.changed() => {
@@ -661,9 +715,18 @@ async fn run_session(
                             warn!("abs mouse report failed: {e}");
                         }
                     }
-                    InputEvent::Wheel { dy } => {
-                        if let Err(e) = rpc_wheel_report(&client, dy).await {
-                            warn!("wheel report failed: {e}");
+                    InputEvent::RelMouse { dx, dy, buttons } => {
+                        if let Err(e) =
+                            rpc_rel_mouse_report(&client, dx, dy, buttons as u64).await
+                        {
+                            warn!("rel mouse report failed: {e}");
+                        }
+                    }
+                    InputEvent::Wheel { dy, dx } => {
+                        let dx = if horizontal_wheel { dx } else { 0 };
+                        match scroll(&client, dy, dx).await {
+                            Ok(supported) => horizontal_wheel &= supported,
+                            Err(e) => warn!("wheel report failed: {e}"),
                         }
                     }
                 }
@@ -671,7 +734,7 @@ async fn run_session(
         }
     };
 
-    // Tear down. Aborting the RTP pump first releases its borrow on the
+    // Tear down. Aborting the RTP pump first drops its subscription to the
     // track. Then explicitly close the peer connection — webrtc-rs's Drop
     // alone leaves the ICE agent running until lazy GC, which is what
     // produced the "agent is closed" warnings before. `pipeline` Drop runs
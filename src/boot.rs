@@ -0,0 +1,313 @@
+//! Getting into a BIOS setup or boot menu.
+//!
+//! `boot_into` triggers a power action and then keeps pressing a key (F2,
+//! F12, Delete, Esc, ...) at a fixed cadence until the screen changes, the
+//! video input reaches a given resolution, or the timeout expires. The key
+//! is written in the `key_sequence` syntax, so chords such as `shift+F10`
+//! work too.
+
+use crate::jetkvm_rpc_client::JetKvmRpcClient;
+use crate::key_sequence::plan_key_sequence;
+use crate::keyboard::{rpc_keyboard_report, send_report_steps};
+use crate::keyboard_layout::layouts;
+use crate::keyboard_state::ReportStep;
+use crate::power::{get_atx_state, rpc_set_atx_power_action, rpc_set_dc_power_state};
+use crate::screen_wait::{screen_signature, signature_difference};
+use crate::screenshot::frame_image;
+use crate::video::{get_video_state, FrameFreshness};
+use anyhow::{anyhow, bail, Result as AnyResult};
+use clap::ValueEnum;
+use image::GrayImage;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::time::{sleep, Duration, Instant};
+use tracing::debug;
+
+pub const DEFAULT_PRESS_INTERVAL_MS: u64 = 200;
+pub const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+pub const DEFAULT_DC_OFF_MS: u64 = 5_000;
+/// Mean per-pixel difference (0.0..=1.0) that counts as a new screen; see
+/// `screen_wait::signature_difference`.
+pub const DEFAULT_SCREEN_CHANGE_THRESHOLD: f64 = 0.1;
+
+/// How long the boot key is held on each press.
+const KEY_HOLD_MS: u64 = 50;
+/// Pause between two screen or video state checks.
+const CHECK_INTERVAL_MS: u64 = 1_000;
+/// Pause between two video state checks while waiting for the input to
+/// drop after a power action; a reset may blank it only briefly.
+const VIDEO_RESTART_POLL_MS: u64 = 200;
+
+/// What to do to the target before pressing the boot key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootPowerAction {
+    /// Short press of the ATX power button. Refused if the power LED shows
+    /// the target is already on, since the press would turn it off.
+    #[default]
+    AtxPower,
+    /// Press the ATX reset button.
+    AtxReset,
+    /// Switch DC power off and back on.
+    DcCycle,
+    /// Start pressing the key right away.
+    None,
+}
+
+/// When to stop pressing the boot key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootUntil {
+    /// Keep pressing until the timeout; reaching it is not an error.
+    Timeout,
+    /// Stop when the screen differs from the first frame decoded after the
+    /// power action restarted the video input by more than `threshold`
+    /// (see `screen_wait::signature_difference`).
+    ScreenChange { threshold: f64 },
+    /// Stop when the video input reports this resolution.
+    Resolution { width: u32, height: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BootOptions {
+    /// Key to press, in the `key_sequence` syntax (e.g. `F2`, `Delete`).
+    pub key: String,
+    pub power: BootPowerAction,
+    pub press_interval_ms: u64,
+    pub timeout_ms: u64,
+    pub until: BootUntil,
+    /// How long DC power stays off for `BootPowerAction::DcCycle`.
+    pub dc_off_ms: u64,
+}
+
+impl BootOptions {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            power: BootPowerAction::default(),
+            press_interval_ms: DEFAULT_PRESS_INTERVAL_MS,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            until: BootUntil::ScreenChange {
+                threshold: DEFAULT_SCREEN_CHANGE_THRESHOLD,
+            },
+            dc_off_ms: DEFAULT_DC_OFF_MS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BootOutcome {
+    /// Whether the stop condition was seen (always false for `Timeout`).
+    pub condition_met: bool,
+    pub presses: usize,
+    pub elapsed_ms: u64,
+}
+
+/// Runs the power action, then presses the boot key until `options.until`
+/// is met. Fails if a condition is set and the timeout expires first.
+pub async fn boot_into(client: &JetKvmRpcClient, options: &BootOptions) -> AnyResult<BootOutcome> {
+    let press = plan_key_sequence(&options.key, &layouts::EN_US, KEY_HOLD_MS)?;
+    let presses_keys = press.iter().any(|step| match step {
+        ReportStep::Report(report) => report.modifier != 0 || !report.keys.is_empty(),
+        ReportStep::Sleep(_) => false,
+    });
+    if !presses_keys {
+        bail!("Boot key sequence {:?} presses no keys", options.key);
+    }
+
+    let start = Instant::now();
+    run_power_action(client, options.power, options.dc_off_ms).await?;
+
+    let presses = AtomicUsize::new(0);
+    let deadline = start + Duration::from_millis(options.timeout_ms);
+    let result = tokio::select! {
+        result = press_repeatedly(client, &press, options.press_interval_ms, &presses) => {
+            result.map(|_| false)
+        }
+        result = wait_for_condition(client, &options.until, options.power) => {
+            result.map(|_| true)
+        }
+        _ = tokio::time::sleep_until(deadline) => Ok(false),
+    };
+    // The pressing future may have been dropped between a press and its
+    // release, which would leave the key held on the target.
+    let released = rpc_keyboard_report(client, 0, vec![]).await;
+    let condition_met = result?;
+    released?;
+
+    let outcome = BootOutcome {
+        condition_met,
+        presses: presses.load(Ordering::Relaxed),
+        elapsed_ms: start.elapsed().as_millis() as u64,
+    };
+    if !condition_met && options.until != BootUntil::Timeout {
+        bail!(
+            "Timed out after {} ms and {} key presses waiting for {:?}",
+            outcome.elapsed_ms,
+            outcome.presses,
+            options.until
+        );
+    }
+    Ok(outcome)
+}
+
+/// Sends `press` every `interval_ms` until an error occurs.
+async fn press_repeatedly(
+    client: &JetKvmRpcClient,
+    press: &[ReportStep],
+    interval_ms: u64,
+    presses: &AtomicUsize,
+) -> AnyResult<()> {
+    loop {
+        send_report_steps(client, press).await?;
+        presses.fetch_add(1, Ordering::Relaxed);
+        sleep(Duration::from_millis(interval_ms)).await;
+    }
+}
+
+async fn run_power_action(
+    client: &JetKvmRpcClient,
+    action: BootPowerAction,
+    dc_off_ms: u64,
+) -> AnyResult<()> {
+    match action {
+        BootPowerAction::AtxPower => {
+            if get_atx_state(client).await?.power {
+                bail!(
+                    "Target is already powered on; a power button press would turn it off \
+                     (use --power atx-reset, dc-cycle or none)"
+                );
+            }
+            rpc_set_atx_power_action(client, "power-short".to_string()).await?;
+        }
+        BootPowerAction::AtxReset => {
+            rpc_set_atx_power_action(client, "reset".to_string()).await?;
+        }
+        BootPowerAction::DcCycle => {
+            rpc_set_dc_power_state(client, false).await?;
+            sleep(Duration::from_millis(dc_off_ms)).await;
+            rpc_set_dc_power_state(client, true).await?;
+        }
+        BootPowerAction::None => {}
+    }
+    Ok(())
+}
+
+/// Resolves once `until` is met; never resolves for `BootUntil::Timeout`.
+async fn wait_for_condition(
+    client: &JetKvmRpcClient,
+    until: &BootUntil,
+    power: BootPowerAction,
+) -> AnyResult<()> {
+    match until {
+        BootUntil::Timeout => std::future::pending().await,
+        BootUntil::Resolution { width, height } => loop {
+            let state = get_video_state(client).await?;
+            if state.ready && state.width == *width && state.height == *height {
+                return Ok(());
+            }
+            sleep(Duration::from_millis(CHECK_INTERVAL_MS)).await;
+        },
+        BootUntil::ScreenChange { threshold } => {
+            // Frames from before the power action still show the old
+            // screen, so the baseline is only taken once the video input
+            // has dropped and come back.
+            let mut restart = VideoRestart::new(power != BootPowerAction::None);
+            while !restart.observe(get_video_state(client).await?.ready) {
+                sleep(Duration::from_millis(VIDEO_RESTART_POLL_MS)).await;
+            }
+            let baseline = loop {
+                match frame_signature(client, FrameFreshness::Next).await {
+                    Ok(signature) => break signature,
+                    Err(e) => {
+                        debug!("No frame yet: {}", e);
+                        sleep(Duration::from_millis(CHECK_INTERVAL_MS)).await;
+                    }
+                }
+            };
+            loop {
+                sleep(Duration::from_millis(CHECK_INTERVAL_MS)).await;
+                // The device may stop sending frames while nothing changes,
+                // so later frames are taken however old they are.
+                match frame_signature(client, FrameFreshness::Latest).await {
+                    Ok(signature) => {
+                        let difference = signature_difference(&baseline, &signature);
+                        debug!("Screen difference from first frame: {:.3}", difference);
+                        if difference > *threshold {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => debug!("No frame yet: {}", e),
+                }
+            }
+        }
+    }
+}
+
+/// Signature of the latest decoded frame that satisfies `freshness`.
+async fn frame_signature(
+    client: &JetKvmRpcClient,
+    freshness: FrameFreshness,
+) -> AnyResult<GrayImage> {
+    let frame = client.video_capture.latest_frame(freshness).await?;
+    screen_signature(&frame_image(&frame)?, None)
+}
+
+/// Follows the video input through the blackout a power action causes.
+struct VideoRestart {
+    dropped: bool,
+}
+
+impl VideoRestart {
+    /// With `expect_drop` false the input counts as restarted as soon as it
+    /// is ready.
+    fn new(expect_drop: bool) -> Self {
+        Self {
+            dropped: !expect_drop,
+        }
+    }
+
+    /// Feeds one video state; true once the input is ready after having
+    /// been not ready.
+    fn observe(&mut self, ready: bool) -> bool {
+        self.dropped |= !ready;
+        ready && self.dropped
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` resolution such as `1024x768`.
+pub fn parse_resolution(s: &str) -> AnyResult<(u32, u32)> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| anyhow!("Invalid resolution {:?}, expected WIDTHxHEIGHT", s))?;
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<u32>()
+            .map_err(|_| anyhow!("Invalid resolution {:?}, expected WIDTHxHEIGHT", s))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_restart_waits_for_the_input_to_drop() {
+        let mut restart = VideoRestart::new(true);
+        assert!(!restart.observe(true));
+        assert!(!restart.observe(false));
+        assert!(!restart.observe(false));
+        assert!(restart.observe(true));
+
+        let mut restart = VideoRestart::new(false);
+        assert!(restart.observe(true));
+    }
+
+    #[test]
+    fn test_parse_resolution() {
+        assert_eq!(parse_resolution("1024x768").unwrap(), (1024, 768));
+        assert_eq!(parse_resolution("800X600").unwrap(), (800, 600));
+        assert!(parse_resolution("1024").is_err());
+        assert!(parse_resolution("axb").is_err());
+    }
+}
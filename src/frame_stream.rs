@@ -0,0 +1,569 @@
+//! Decoded video frames as an async `Stream`.
+//!
+//! `FrameStream` reads the WebRTC video track, decodes it and yields
+//! `VideoFrame`s in NV12 or RGBA. Two decoders are available, each behind
+//! a cargo feature of the same name:
+//!
+//! - `gstreamer` (default): the system GStreamer with `avdec_h264`,
+//!   `appsrc -> rtpjitterbuffer -> rtph264depay -> h264parse -> avdec_h264 -> videoconvert -> appsink`.
+//! - `openh264`: `h264::H264Depacketizer` in Rust feeding openh264 on a
+//!   decoder thread. It builds from source and needs no system libraries.
+//!
+//! With `FrameDelivery::Latest` a slow consumer only ever sees the newest
+//! frame; with `FrameDelivery::Every` the decoder waits for the consumer
+//! once `buffer` frames are queued, so no frame is skipped. A keyframe is
+//! requested when the stream starts and after packet loss.
+//!
+//! The stream takes its packets from an `RtpSubscription`, so it can run
+//! alongside recordings, screenshots and other streams.
+
+use crate::video::VideoFrameCapture;
+use anyhow::Result as AnyResult;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+/// How long `FrameStream::start` waits for the video track to appear.
+const TRACK_WAIT: Duration = Duration::from_secs(10);
+
+/// Pixel layout of a `VideoFrame`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// Y plane followed by the interleaved half-resolution UV plane.
+    #[default]
+    Nv12,
+    /// Four bytes per pixel.
+    Rgba,
+}
+
+/// What happens when frames arrive faster than they are consumed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameDelivery {
+    /// Skip to the newest frame.
+    #[default]
+    Latest,
+    /// Deliver every frame, holding up decoding while the buffer is full.
+    Every,
+}
+
+/// Which decoder turns the H.264 stream into frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecoderBackend {
+    /// GStreamer with `avdec_h264`.
+    #[cfg(feature = "gstreamer")]
+    #[cfg_attr(feature = "gstreamer", default)]
+    GStreamer,
+    /// openh264 with depacketisation in Rust; no system libraries needed.
+    #[cfg(feature = "openh264")]
+    #[cfg_attr(not(feature = "gstreamer"), default)]
+    OpenH264,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameStreamOptions {
+    pub format: FrameFormat,
+    pub delivery: FrameDelivery,
+    /// Frames queued for `FrameDelivery::Every`.
+    pub buffer: usize,
+    pub backend: DecoderBackend,
+}
+
+impl Default for FrameStreamOptions {
+    fn default() -> Self {
+        Self {
+            format: FrameFormat::Nv12,
+            delivery: FrameDelivery::Latest,
+            buffer: 8,
+            backend: DecoderBackend::default(),
+        }
+    }
+}
+
+/// One decoded frame with tightly packed rows (no stride padding).
+#[derive(Clone, Debug)]
+pub struct VideoFrame {
+    pub width: u32,
+    pub height: u32,
+    pub format: FrameFormat,
+    /// Presentation time relative to the start of the stream.
+    pub pts: Option<Duration>,
+    /// When the frame left the decoder.
+    pub decoded_at: Instant,
+    pub data: Vec<u8>,
+}
+
+impl VideoFrame {
+    /// The luma plane of an NV12 frame, or the pixels of an RGBA frame.
+    pub fn y_plane(&self) -> &[u8] {
+        match self.format {
+            FrameFormat::Nv12 => &self.data[..(self.width * self.height) as usize],
+            FrameFormat::Rgba => &self.data,
+        }
+    }
+
+    /// The interleaved chroma plane of an NV12 frame; empty for RGBA.
+    pub fn uv_plane(&self) -> &[u8] {
+        match self.format {
+            FrameFormat::Nv12 => &self.data[(self.width * self.height) as usize..],
+            FrameFormat::Rgba => &[],
+        }
+    }
+}
+
+/// Copies `rows` rows of `row_bytes` bytes out of a plane with `stride`
+/// bytes per row.
+pub fn pack_plane(plane: &[u8], stride: usize, row_bytes: usize, rows: usize) -> Vec<u8> {
+    if stride == row_bytes {
+        return plane[..row_bytes * rows].to_vec();
+    }
+    let mut out = Vec::with_capacity(row_bytes * rows);
+    for row in plane.chunks(stride).take(rows) {
+        out.extend_from_slice(&row[..row_bytes]);
+    }
+    out
+}
+
+/// The decoder's end of the frame channel.
+enum FrameSink {
+    Latest(watch::Sender<Option<VideoFrame>>),
+    Every(mpsc::Sender<VideoFrame>),
+}
+
+impl FrameSink {
+    /// Hands a frame to the stream. Called from a decoder thread; blocks
+    /// while an `Every` buffer is full. Returns false once the stream is gone.
+    fn deliver(&self, frame: VideoFrame) -> bool {
+        match self {
+            FrameSink::Latest(tx) => tx.send(Some(frame)).is_ok(),
+            FrameSink::Every(tx) => tx.blocking_send(frame).is_ok(),
+        }
+    }
+}
+
+fn frame_channel(options: &FrameStreamOptions) -> (FrameSink, BoxStream<'static, VideoFrame>) {
+    match options.delivery {
+        FrameDelivery::Latest => {
+            let (tx, rx) = watch::channel(None::<VideoFrame>);
+            let frames = stream::unfold(rx, |mut rx| async move {
+                loop {
+                    rx.changed().await.ok()?;
+                    let frame = rx.borrow_and_update().clone();
+                    if let Some(frame) = frame {
+                        return Some((frame, rx));
+                    }
+                }
+            });
+            (FrameSink::Latest(tx), frames.boxed())
+        }
+        FrameDelivery::Every => {
+            let (tx, rx) = mpsc::channel(options.buffer.max(1));
+            let frames = stream::unfold(rx, |mut rx| async move {
+                let frame = rx.recv().await?;
+                Some((frame, rx))
+            });
+            (FrameSink::Every(tx), frames.boxed())
+        }
+    }
+}
+
+/// A running decode of the video track. Dropping it stops the decoder.
+pub struct FrameStream {
+    frames: BoxStream<'static, VideoFrame>,
+    feed_task: JoinHandle<()>,
+    #[cfg(feature = "gstreamer")]
+    pipeline: Option<gstreamer::Pipeline>,
+}
+
+impl FrameStream {
+    /// Starts decoding `capture`'s video track, waiting briefly for the
+    /// track if the connection is still coming up.
+    pub async fn start(
+        capture: &VideoFrameCapture,
+        options: FrameStreamOptions,
+    ) -> AnyResult<Self> {
+        let rtp = capture.subscribe_rtp(TRACK_WAIT).await?;
+        let track = Arc::clone(rtp.track());
+        let keyframes = capture.keyframe_requester();
+        let (sink, frames) = frame_channel(&options);
+
+        let stream = match options.backend {
+            #[cfg(feature = "gstreamer")]
+            DecoderBackend::GStreamer => {
+                let (pipeline, appsrc) =
+                    gst_backend::start(options.format, options.delivery, sink)?;
+                Self {
+                    frames,
+                    feed_task: gst_backend::feed(rtp, appsrc, keyframes.clone())?,
+                    pipeline: Some(pipeline),
+                }
+            }
+            #[cfg(feature = "openh264")]
+            DecoderBackend::OpenH264 => Self {
+                frames,
+                feed_task: openh264_backend::start(rtp, keyframes.clone(), options.format, sink)?,
+                #[cfg(feature = "gstreamer")]
+                pipeline: None,
+            },
+        };
+        debug!(
+            "Frame stream started ({:?}, {:?}, {:?})",
+            options.backend, options.format, options.delivery
+        );
+        if let Err(e) = keyframes.request(&track).await {
+            debug!("Keyframe request failed: {}", e);
+        }
+        Ok(stream)
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = VideoFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<VideoFrame>> {
+        self.frames.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        // Close the frame channel first: a decoder blocked handing over a
+        // frame would otherwise never return, and stopping the pipeline
+        // waits for its streaming thread.
+        self.frames = stream::empty().boxed();
+        self.feed_task.abort();
+        #[cfg(feature = "gstreamer")]
+        if let Some(pipeline) = &self.pipeline {
+            use gstreamer::prelude::*;
+            if let Err(e) = pipeline.set_state(gstreamer::State::Null) {
+                tracing::warn!("Failed to stop frame stream cleanly: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gstreamer")]
+mod gst_backend {
+    use super::{pack_plane, FrameDelivery, FrameFormat, FrameSink, VideoFrame};
+    use crate::keyframe::{KeyframeRequester, SequenceTracker};
+    use crate::video::RtpSubscription;
+    use crate::viewer::pipeline::serialize_rtp;
+    use anyhow::{anyhow, Result as AnyResult};
+    use gstreamer as gst;
+    use gstreamer::prelude::*;
+    use gstreamer_app as gst_app;
+    use gstreamer_video as gst_video;
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+    use tokio::time::{Duration, Instant};
+    use tracing::debug;
+
+    impl FrameFormat {
+        fn caps_name(self) -> &'static str {
+            match self {
+                FrameFormat::Nv12 => "NV12",
+                FrameFormat::Rgba => "RGBA",
+            }
+        }
+    }
+
+    /// RTP bytes queued in the appsrc before `push_buffer` blocks the push
+    /// thread, so a decoder held up by `FrameDelivery::Every` does not buffer
+    /// without bound. The feed task then waits on the packet queue and its
+    /// subscription lags and skips packets instead.
+    const APPSRC_MAX_BYTES: u64 = 2 * 1024 * 1024;
+    /// RTP packets queued between the feed task and the push thread.
+    const PACKET_BUFFER: usize = 64;
+
+    /// Builds and starts the decode pipeline; frames go to `sink`.
+    pub(super) fn start(
+        format: FrameFormat,
+        delivery: FrameDelivery,
+        sink: FrameSink,
+    ) -> AnyResult<(gst::Pipeline, gst_app::AppSrc)> {
+        gst::init()?;
+
+        let pipeline = gst::Pipeline::new();
+        let appsrc = gst::ElementFactory::make("appsrc")
+            .name("rtpsrc")
+            .property("format", gst::Format::Time)
+            .property("is-live", true)
+            .property("do-timestamp", true)
+            .property_from_str("stream-type", "stream")
+            .property("max-bytes", APPSRC_MAX_BYTES)
+            .property("block", true)
+            .build()?;
+        let caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90000)
+            .field("encoding-name", "H264")
+            .field("payload", 102)
+            .build();
+        appsrc.set_property("caps", &caps);
+
+        let jitterbuffer = gst::ElementFactory::make("rtpjitterbuffer")
+            .property("latency", 20u32)
+            .build()?;
+        let depay = gst::ElementFactory::make("rtph264depay").build()?;
+        let parse = gst::ElementFactory::make("h264parse").build()?;
+        let dec = gst::ElementFactory::make("avdec_h264").build()?;
+        let convert = gst::ElementFactory::make("videoconvert").build()?;
+        let raw_caps = gst::Caps::builder("video/x-raw")
+            .field("format", format.caps_name())
+            .build();
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .property("caps", &raw_caps)
+            .build()?;
+        let appsink = gst::ElementFactory::make("appsink")
+            .property("sync", false)
+            .property("max-buffers", 2u32)
+            .property("drop", delivery == FrameDelivery::Latest)
+            .build()?;
+
+        pipeline.add_many([
+            &appsrc,
+            &jitterbuffer,
+            &depay,
+            &parse,
+            &dec,
+            &convert,
+            &capsfilter,
+            &appsink,
+        ])?;
+        gst::Element::link_many([
+            &appsrc,
+            &jitterbuffer,
+            &depay,
+            &parse,
+            &dec,
+            &convert,
+            &capsfilter,
+            &appsink,
+        ])?;
+
+        let appsrc = appsrc
+            .dynamic_cast::<gst_app::AppSrc>()
+            .map_err(|_| anyhow!("appsrc is not an AppSrc"))?;
+        let appsink = appsink
+            .dynamic_cast::<gst_app::AppSink>()
+            .map_err(|_| anyhow!("appsink is not an AppSink"))?;
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Error)?;
+                    if let Some(frame) = to_video_frame(&sample, format) {
+                        // Runs on a GStreamer streaming thread, so blocking
+                        // in `deliver` is what applies backpressure.
+                        if !sink.deliver(frame) {
+                            return Err(gst::FlowError::Eos);
+                        }
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+
+        pipeline.set_state(gst::State::Playing)?;
+        Ok((pipeline, appsrc))
+    }
+
+    /// Starts the thread pushing RTP packets into the pipeline and returns
+    /// the task feeding it. `push_buffer` blocks while the appsrc is full,
+    /// so it runs on its own thread rather than a runtime worker; stopping
+    /// the pipeline makes it return.
+    pub(super) fn feed(
+        mut rtp: RtpSubscription,
+        appsrc: gst_app::AppSrc,
+        keyframes: KeyframeRequester,
+    ) -> AnyResult<JoinHandle<()>> {
+        let (buffer_tx, buffer_rx) = mpsc::channel(PACKET_BUFFER);
+        std::thread::Builder::new()
+            .name("gst-appsrc".into())
+            .spawn(move || push(buffer_rx, appsrc))
+            .map_err(|e| anyhow!("Failed to start appsrc thread: {}", e))?;
+
+        Ok(tokio::spawn(async move {
+            let mut sequence = SequenceTracker::default();
+            while let Some(packet) = rtp.recv().await {
+                if sequence.observe(packet.header.sequence_number) > 0 {
+                    if let Err(e) = keyframes.request_after_loss(rtp.track()).await {
+                        debug!("Keyframe request failed: {}", e);
+                    }
+                }
+                let buffer = gst::Buffer::from_slice(serialize_rtp(&packet));
+                if buffer_tx.send(buffer).await.is_err() {
+                    return;
+                }
+            }
+        }))
+    }
+
+    /// Pushes buffers until the feed task or the pipeline goes away.
+    fn push(mut buffers: mpsc::Receiver<gst::Buffer>, appsrc: gst_app::AppSrc) {
+        while let Some(buffer) = buffers.blocking_recv() {
+            if appsrc.push_buffer(buffer).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn to_video_frame(sample: &gst::Sample, format: FrameFormat) -> Option<VideoFrame> {
+        let buffer = sample.buffer()?;
+        let info = gst_video::VideoInfo::from_caps(sample.caps()?).ok()?;
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &info).ok()?;
+        let (width, height) = (info.width(), info.height());
+        let strides = frame.plane_stride();
+
+        let data = match format {
+            FrameFormat::Nv12 => {
+                let mut data = pack_plane(
+                    frame.plane_data(0).ok()?,
+                    strides[0] as usize,
+                    width as usize,
+                    height as usize,
+                );
+                data.extend(pack_plane(
+                    frame.plane_data(1).ok()?,
+                    strides[1] as usize,
+                    width.div_ceil(2) as usize * 2,
+                    height.div_ceil(2) as usize,
+                ));
+                data
+            }
+            FrameFormat::Rgba => pack_plane(
+                frame.plane_data(0).ok()?,
+                strides[0] as usize,
+                width as usize * 4,
+                height as usize,
+            ),
+        };
+
+        Some(VideoFrame {
+            width,
+            height,
+            format,
+            pts: buffer.pts().map(|pts| Duration::from_nanos(pts.nseconds())),
+            decoded_at: Instant::now(),
+            data,
+        })
+    }
+}
+
+#[cfg(feature = "openh264")]
+mod openh264_backend {
+    use super::{FrameFormat, FrameSink, VideoFrame};
+    use crate::h264::{i420_to_nv12, AccessUnit, H264Depacketizer};
+    use crate::keyframe::KeyframeRequester;
+    use crate::video::RtpSubscription;
+    use anyhow::{anyhow, Result as AnyResult};
+    use openh264::decoder::Decoder;
+    use openh264::formats::YUVSource;
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+    use tokio::time::{Duration, Instant};
+    use tracing::{debug, warn};
+
+    /// Access units queued between the depacketizer and the decoder thread.
+    const UNIT_BUFFER: usize = 16;
+    /// RTP clock rate of H.264 video.
+    const RTP_CLOCK_RATE: u64 = 90_000;
+
+    /// Starts the decoder thread and returns the task feeding it.
+    pub(super) fn start(
+        mut rtp: RtpSubscription,
+        keyframes: KeyframeRequester,
+        format: FrameFormat,
+        sink: FrameSink,
+    ) -> AnyResult<JoinHandle<()>> {
+        let (unit_tx, unit_rx) = mpsc::channel(UNIT_BUFFER);
+        std::thread::Builder::new()
+            .name("openh264".into())
+            .spawn(move || decode(unit_rx, format, sink))
+            .map_err(|e| anyhow!("Failed to start decoder thread: {}", e))?;
+
+        Ok(tokio::spawn(async move {
+            let mut depacketizer = H264Depacketizer::new();
+            while let Some(packet) = rtp.recv().await {
+                let units = depacketizer.push(
+                    packet.header.sequence_number,
+                    packet.header.timestamp,
+                    packet.header.marker,
+                    &packet.payload,
+                );
+                if depacketizer.needs_keyframe() {
+                    if let Err(e) = keyframes.request_after_loss(rtp.track()).await {
+                        debug!("Keyframe request failed: {}", e);
+                    }
+                }
+                for unit in units {
+                    if unit_tx.send(unit).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Decodes access units until the feed task or the stream goes away.
+    fn decode(mut units: mpsc::Receiver<AccessUnit>, format: FrameFormat, sink: FrameSink) {
+        let mut decoder = match Decoder::new() {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                warn!("Failed to create openh264 decoder: {}", e);
+                return;
+            }
+        };
+        let mut first_timestamp = None;
+        while let Some(unit) = units.blocking_recv() {
+            let yuv = match decoder.decode(&unit.data) {
+                Ok(Some(yuv)) => yuv,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("openh264 could not decode frame: {}", e);
+                    continue;
+                }
+            };
+            let (width, height) = yuv.dimensions();
+            let data = match format {
+                FrameFormat::Nv12 => {
+                    i420_to_nv12((yuv.y(), yuv.u(), yuv.v()), yuv.strides(), width, height)
+                }
+                FrameFormat::Rgba => {
+                    let mut rgba = vec![0; width * height * 4];
+                    yuv.write_rgba8(&mut rgba);
+                    rgba
+                }
+            };
+            let first = *first_timestamp.get_or_insert(unit.timestamp);
+            let ticks = unit.timestamp.wrapping_sub(first) as u64;
+            let frame = VideoFrame {
+                width: width as u32,
+                height: height as u32,
+                format,
+                pts: Some(Duration::from_micros(ticks * 1_000_000 / RTP_CLOCK_RATE)),
+                decoded_at: Instant::now(),
+                data,
+            };
+            if !sink.deliver(frame) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_plane_drops_stride_padding() {
+        let plane = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 0];
+        assert_eq!(pack_plane(&plane, 4, 3, 3), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(pack_plane(&plane, 4, 3, 2), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(pack_plane(&plane[..6], 3, 3, 2), plane[..6].to_vec());
+    }
+}
@@ -0,0 +1,313 @@
+//! H.264 RTP depacketisation (RFC 6184) for decoders outside GStreamer.
+//!
+//! `H264Depacketizer` turns RTP payloads into Annex B access units: single
+//! NAL unit packets are passed through, STAP-A aggregates are split and FU-A
+//! fragments are reassembled. An access unit ends at the RTP marker bit, or
+//! when the timestamp moves on if the marker packet was lost.
+//!
+//! The device sends SPS and PPS only ahead of some keyframes, so the last
+//! ones seen are cached and put in front of every IDR frame that lacks them.
+//! After packet loss, and at the start, access units are held back until the
+//! next IDR frame; `needs_keyframe` tells the caller to ask for one.
+
+use crate::keyframe::SequenceTracker;
+
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_STAP_A: u8 = 24;
+const NAL_FU_A: u8 = 28;
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// One complete, decodable frame in Annex B format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessUnit {
+    pub data: Vec<u8>,
+    /// RTP timestamp (90 kHz).
+    pub timestamp: u32,
+    pub keyframe: bool,
+}
+
+pub struct H264Depacketizer {
+    sequence: SequenceTracker,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    nals: Vec<Vec<u8>>,
+    timestamp: Option<u32>,
+    fragment: Option<Vec<u8>>,
+    damaged: bool,
+    waiting_for_keyframe: bool,
+}
+
+impl Default for H264Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl H264Depacketizer {
+    pub fn new() -> Self {
+        Self {
+            sequence: SequenceTracker::default(),
+            sps: None,
+            pps: None,
+            nals: Vec::new(),
+            timestamp: None,
+            fragment: None,
+            damaged: false,
+            waiting_for_keyframe: true,
+        }
+    }
+
+    /// Whether frames are being held back until the next IDR frame.
+    pub fn needs_keyframe(&self) -> bool {
+        self.waiting_for_keyframe
+    }
+
+    /// Feeds one RTP packet and returns the access units it completed.
+    /// Late and duplicate packets are dropped.
+    pub fn push(
+        &mut self,
+        sequence: u16,
+        timestamp: u32,
+        marker: bool,
+        payload: &[u8],
+    ) -> Vec<AccessUnit> {
+        if self.sequence.is_late(sequence) {
+            return Vec::new();
+        }
+        let mut done = Vec::new();
+        if self.sequence.observe(sequence) > 0 {
+            self.fragment = None;
+            self.damaged = true;
+        }
+        if self.timestamp.is_some_and(|current| current != timestamp) {
+            done.extend(self.finish());
+        }
+        self.timestamp = Some(timestamp);
+
+        self.depacketize(payload);
+        if marker {
+            done.extend(self.finish());
+        }
+        done
+    }
+
+    fn depacketize(&mut self, payload: &[u8]) {
+        let Some(&header) = payload.first() else {
+            return;
+        };
+        match header & 0x1F {
+            1..=23 => self.add_nal(payload.to_vec()),
+            NAL_STAP_A => {
+                let mut rest = &payload[1..];
+                while rest.len() >= 2 {
+                    let size = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                    if size == 0 || rest.len() < 2 + size {
+                        self.damaged = true;
+                        return;
+                    }
+                    self.add_nal(rest[2..2 + size].to_vec());
+                    rest = &rest[2 + size..];
+                }
+            }
+            NAL_FU_A => {
+                let Some(&fu_header) = payload.get(1) else {
+                    self.damaged = true;
+                    return;
+                };
+                let start = fu_header & 0x80 != 0;
+                let end = fu_header & 0x40 != 0;
+                if start {
+                    let mut nal = vec![(header & 0xE0) | (fu_header & 0x1F)];
+                    nal.extend_from_slice(&payload[2..]);
+                    self.fragment = Some(nal);
+                } else if let Some(fragment) = self.fragment.as_mut() {
+                    fragment.extend_from_slice(&payload[2..]);
+                } else {
+                    // The start of this fragment was lost.
+                    self.damaged = true;
+                    return;
+                }
+                if end {
+                    if let Some(nal) = self.fragment.take() {
+                        self.add_nal(nal);
+                    }
+                }
+            }
+            // STAP-B, MTAP and FU-B are not used in packetization mode 1.
+            _ => {}
+        }
+    }
+
+    fn add_nal(&mut self, nal: Vec<u8>) {
+        match nal[0] & 0x1F {
+            NAL_SPS => self.sps = Some(nal.clone()),
+            NAL_PPS => self.pps = Some(nal.clone()),
+            _ => {}
+        }
+        self.nals.push(nal);
+    }
+
+    /// Ends the current access unit, returning it if it can be decoded.
+    fn finish(&mut self) -> Option<AccessUnit> {
+        let nals = std::mem::take(&mut self.nals);
+        let timestamp = self.timestamp?;
+        let damaged = std::mem::replace(&mut self.damaged, false);
+        self.fragment = None;
+        if damaged {
+            self.waiting_for_keyframe = true;
+            return None;
+        }
+        if nals.is_empty() {
+            return None;
+        }
+
+        let has_type = |kind: u8| nals.iter().any(|nal| nal[0] & 0x1F == kind);
+        let keyframe = has_type(NAL_IDR);
+        if self.waiting_for_keyframe && !keyframe {
+            return None;
+        }
+
+        let mut parameter_sets = Vec::new();
+        if keyframe {
+            if !has_type(NAL_SPS) {
+                parameter_sets.extend(self.sps.clone());
+            }
+            if !has_type(NAL_PPS) {
+                parameter_sets.extend(self.pps.clone());
+            }
+            if self.sps.is_none() || self.pps.is_none() {
+                // An IDR frame is useless before the first SPS/PPS.
+                return None;
+            }
+        }
+        self.waiting_for_keyframe = false;
+
+        let mut data = Vec::new();
+        for nal in parameter_sets.iter().chain(&nals) {
+            data.extend_from_slice(&START_CODE);
+            data.extend_from_slice(nal);
+        }
+        Some(AccessUnit {
+            data,
+            timestamp,
+            keyframe,
+        })
+    }
+}
+
+/// Repacks planar I420 (as produced by software decoders) into tightly
+/// packed NV12: the Y plane followed by interleaved U/V.
+pub fn i420_to_nv12(
+    (y, u, v): (&[u8], &[u8], &[u8]),
+    (stride_y, stride_u, stride_v): (usize, usize, usize),
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut out = Vec::with_capacity(width * height + 2 * chroma_width * chroma_height);
+    for row in 0..height {
+        out.extend_from_slice(&y[row * stride_y..row * stride_y + width]);
+    }
+    for row in 0..chroma_height {
+        let u_row = &u[row * stride_u..row * stride_u + chroma_width];
+        let v_row = &v[row * stride_v..row * stride_v + chroma_width];
+        for (&u, &v) in u_row.iter().zip(v_row) {
+            out.push(u);
+            out.push(v);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1F];
+    const PPS: &[u8] = &[0x68, 0xCE, 0x3C, 0x80];
+
+    fn annex_b(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| START_CODE.iter().chain(nal.iter()).copied())
+            .collect()
+    }
+
+    fn stap_a(nals: &[&[u8]]) -> Vec<u8> {
+        let mut payload = vec![0x78];
+        for nal in nals {
+            payload.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            payload.extend_from_slice(nal);
+        }
+        payload
+    }
+
+    #[test]
+    fn test_stap_a_and_fu_a_reassembly() {
+        let mut depay = H264Depacketizer::new();
+        assert!(depay.push(1, 1000, false, &stap_a(&[SPS, PPS])).is_empty());
+        // IDR (nal_ref_idc 3, type 5) split over three FU-A packets.
+        assert!(depay.push(2, 1000, false, &[0x7C, 0x85, 1, 2]).is_empty());
+        assert!(depay.push(3, 1000, false, &[0x7C, 0x05, 3]).is_empty());
+        let units = depay.push(4, 1000, true, &[0x7C, 0x45, 4]);
+        assert_eq!(
+            units,
+            vec![AccessUnit {
+                data: annex_b(&[SPS, PPS, &[0x65, 1, 2, 3, 4]]),
+                timestamp: 1000,
+                keyframe: true,
+            }]
+        );
+        assert!(!depay.needs_keyframe());
+
+        let units = depay.push(5, 4000, true, &[0x41, 9, 9]);
+        assert_eq!(units[0].data, annex_b(&[&[0x41, 9, 9]]));
+        assert!(!units[0].keyframe);
+    }
+
+    #[test]
+    fn test_waits_for_keyframe_after_loss_and_reuses_parameter_sets() {
+        let mut depay = H264Depacketizer::new();
+        // Nothing before the first keyframe.
+        assert!(depay.push(1, 1000, true, &[0x41, 1]).is_empty());
+        depay.push(2, 2000, true, &stap_a(&[SPS, PPS]));
+        assert_eq!(depay.push(3, 3000, true, &[0x65, 1]).len(), 1);
+
+        // Packet 5 is lost: the frame it belonged to and the next P frame
+        // are dropped until an IDR arrives.
+        assert!(depay.push(4, 4000, false, &[0x7C, 0x81, 1]).is_empty());
+        assert!(depay.push(6, 4000, true, &[0x7C, 0x41, 3]).is_empty());
+        assert!(depay.needs_keyframe());
+        assert!(depay.push(7, 5000, true, &[0x41, 2]).is_empty());
+
+        // The IDR comes without SPS/PPS; the cached ones are prepended.
+        let units = depay.push(8, 6000, true, &[0x65, 2]);
+        assert_eq!(units[0].data, annex_b(&[SPS, PPS, &[0x65, 2]]));
+        // Late packets are ignored.
+        assert!(depay.push(7, 5000, true, &[0x41, 2]).is_empty());
+    }
+
+    #[test]
+    fn test_missing_marker_ends_frame_on_new_timestamp() {
+        let mut depay = H264Depacketizer::new();
+        depay.push(1, 1000, false, &stap_a(&[SPS, PPS]));
+        assert!(depay.push(2, 1000, false, &[0x65, 1]).is_empty());
+        let units = depay.push(3, 4000, true, &[0x41, 2]);
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].timestamp, 1000);
+        assert_eq!(units[1].timestamp, 4000);
+    }
+
+    #[test]
+    fn test_i420_to_nv12() {
+        // 2x2 image with one padding byte per row in every plane.
+        let y = [1, 2, 0, 3, 4, 0];
+        let u = [5, 0];
+        let v = [6, 0];
+        assert_eq!(
+            i420_to_nv12((&y, &u, &v), (3, 2, 2), 2, 2),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+    }
+}
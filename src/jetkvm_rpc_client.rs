@@ -1,10 +1,13 @@
 use crate::auth;
+use crate::frame_stream::{FrameStream, FrameStreamOptions};
+use crate::keyboard_state::KeyboardLedState;
 use crate::rpc_client::RpcClient;
 use crate::signaling::{legacy, websocket};
 use crate::video::VideoFrameCapture;
-use anyhow::{anyhow, Result as AnyResult};
+use anyhow::{anyhow, bail, Result as AnyResult};
 use clap::ValueEnum;
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use std::sync::Arc;
 use tokio::sync::{watch, Mutex};
@@ -15,6 +18,18 @@ use webrtc::data_channel::RTCDataChannel;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::track::track_remote::TrackRemote;
 
+/// The `result` of an RPC response (`null` when it has none), or an error
+/// naming `method` if the device answered with one.
+pub fn rpc_result(method: &str, mut response: Value) -> AnyResult<Value> {
+    if let Some(error) = response.get("error") {
+        bail!("{} failed: {}", method, error);
+    }
+    Ok(response
+        .get_mut("result")
+        .map(Value::take)
+        .unwrap_or(Value::Null))
+}
+
 #[derive(Clone, Debug, Default, ValueEnum)]
 pub enum SignalingMethod {
     #[default]
@@ -38,12 +53,19 @@ pub struct JetKvmRpcClient {
     pub serial_client: Option<Arc<RTCDataChannel>>,
     pub peer_connection: Option<Arc<RTCPeerConnection>>,
     pub screen_size: Arc<Mutex<Option<(u32, u32)>>>,
+    /// Position of the last absolute mouse report sent, in `absMouseReport`
+    /// coordinates.
+    pub abs_mouse_position: Arc<Mutex<Option<(i64, i64)>>>,
     pub signaling_method: SignalingMethod,
     pub video_capture: Arc<VideoFrameCapture>,
     video_track_tx: watch::Sender<Option<Arc<TrackRemote>>>,
     video_track_rx: watch::Receiver<Option<Arc<TrackRemote>>>,
     peer_state_tx: watch::Sender<RTCPeerConnectionState>,
     peer_state_rx: watch::Receiver<RTCPeerConnectionState>,
+    keyboard_led_tx: watch::Sender<Option<KeyboardLedState>>,
+    keyboard_led_rx: watch::Receiver<Option<KeyboardLedState>>,
+    #[cfg(test)]
+    test_rpc: Option<test_rpc::TestRpc>,
 }
 
 impl JetKvmRpcClient {
@@ -58,6 +80,7 @@ impl JetKvmRpcClient {
         debug!("Initializing JetKvmRpcClient with host: {}", host);
         let (video_track_tx, video_track_rx) = watch::channel(None);
         let (peer_state_tx, peer_state_rx) = watch::channel(RTCPeerConnectionState::New);
+        let (keyboard_led_tx, keyboard_led_rx) = watch::channel(None);
         Self {
             host,
             password,
@@ -69,12 +92,17 @@ impl JetKvmRpcClient {
             serial_client: None,
             peer_connection: None,
             screen_size: Arc::new(Mutex::new(None)),
+            abs_mouse_position: Arc::new(Mutex::new(None)),
             signaling_method,
             video_capture: Arc::new(VideoFrameCapture::new()),
             video_track_tx,
             video_track_rx,
             peer_state_tx,
             peer_state_rx,
+            keyboard_led_tx,
+            keyboard_led_rx,
+            #[cfg(test)]
+            test_rpc: None,
         }
     }
 
@@ -94,6 +122,25 @@ impl JetKvmRpcClient {
         self.peer_state_rx.clone()
     }
 
+    /// Returns a `watch::Receiver` holding the latest keyboard LED state pushed
+    /// by the device in `keyboardLedState` notifications. It stays `None`
+    /// until the first notification arrives.
+    pub fn keyboard_led_watcher(&self) -> watch::Receiver<Option<KeyboardLedState>> {
+        self.keyboard_led_rx.clone()
+    }
+
+    /// Starts decoding the remote video into a `Stream` of frames. See
+    /// `frame_stream` for the pixel formats and delivery modes.
+    pub async fn frame_stream(&self, options: FrameStreamOptions) -> AnyResult<FrameStream> {
+        FrameStream::start(&self.video_capture, options).await
+    }
+
+    /// Asks the device for a video keyframe (RTCP PLI and FIR), so a
+    /// decoder can start without waiting for the encoder's next IDR frame.
+    pub async fn request_keyframe(&self) -> AnyResult<()> {
+        self.video_capture.request_keyframe().await
+    }
+
     /// Connects the client to the JetKVM service.
     pub async fn connect(&mut self) -> AnyResult<()> {
         debug!("Connecting to JetKVM...");
@@ -126,6 +173,9 @@ impl JetKvmRpcClient {
             }
         };
 
+        self.video_capture
+            .keyframe_requester()
+            .set_peer_connection(&peer_connection);
         let video_capture = Arc::clone(&self.video_capture);
         let video_track_tx = self.video_track_tx.clone();
         peer_connection.on_track(Box::new(move |track, _, _| {
@@ -150,7 +200,18 @@ impl JetKvmRpcClient {
             })
         }));
 
-        let rpc_client = RpcClient::new(rpc_channel);
+        let mut rpc_client = RpcClient::new(rpc_channel);
+        let keyboard_led_tx = self.keyboard_led_tx.clone();
+        rpc_client.set_notification_callback(move |method, params| {
+            if method == "keyboardLedState" {
+                match serde_json::from_value::<KeyboardLedState>(params.clone()) {
+                    Ok(state) => {
+                        let _ = keyboard_led_tx.send(Some(state));
+                    }
+                    Err(e) => debug!("Invalid keyboardLedState notification: {}", e),
+                }
+            }
+        });
         rpc_client.install_message_handler();
         self.rpc_client = Some(rpc_client);
         self.peer_connection = Some(peer_connection);
@@ -161,6 +222,10 @@ impl JetKvmRpcClient {
 
     /// Sends an RPC request if the client is connected.
     pub async fn send_rpc(&self, method: &str, params: Value) -> AnyResult<Value> {
+        #[cfg(test)]
+        if let Some(test_rpc) = &self.test_rpc {
+            return Ok(test_rpc.answer(method, params));
+        }
         match &self.rpc_client {
             Some(rpc) => rpc.send_rpc(method, params).await,
             None => Err(anyhow!(
@@ -169,6 +234,13 @@ impl JetKvmRpcClient {
         }
     }
 
+    /// Sends an RPC request and deserializes its result. Fails if the device
+    /// answers with an error or the result does not have the shape of `T`.
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> AnyResult<T> {
+        let result = rpc_result(method, self.send_rpc(method, params).await?)?;
+        serde_json::from_value(result).map_err(|e| anyhow!("Unexpected {} result: {}", method, e))
+    }
+
     /// Waits for the WebRTC DataChannel to be open.
     pub async fn wait_for_channel_open(&self) -> AnyResult<()> {
         if let Some(rpc_client) = &self.rpc_client {
@@ -257,3 +329,81 @@ impl Drop for JetKvmRpcClient {
         tracing::info!("JetKvmRpcClient dropped.");
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test_rpc {
+    use super::*;
+
+    /// The RPCs a test client has sent, as `(method, params)`.
+    pub(crate) type Calls = Arc<std::sync::Mutex<Vec<(String, Value)>>>;
+
+    type Responder = Box<dyn Fn(&str, &Value) -> Value + Send + Sync>;
+
+    /// Answers RPCs in unit tests instead of a device, and records them.
+    pub(crate) struct TestRpc {
+        responder: Responder,
+        calls: Calls,
+    }
+
+    impl TestRpc {
+        pub(crate) fn answer(&self, method: &str, params: Value) -> Value {
+            let response = (self.responder)(method, &params);
+            self.calls
+                .lock()
+                .unwrap()
+                .push((method.to_string(), params));
+            response
+        }
+    }
+
+    /// A client whose RPCs are answered by `responder` and recorded.
+    pub(crate) fn client(
+        responder: impl Fn(&str, &Value) -> Value + Send + Sync + 'static,
+    ) -> (JetKvmRpcClient, Calls) {
+        let calls = Calls::default();
+        let mut client = JetKvmRpcClient::new(
+            "test".to_string(),
+            String::new(),
+            String::new(),
+            false,
+            SignalingMethod::default(),
+        );
+        client.test_rpc = Some(TestRpc {
+            responder: Box::new(responder),
+            calls: Arc::clone(&calls),
+        });
+        (client, calls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_call_reads_typed_results_and_errors() {
+        let (client, _) = test_rpc::client(|method, _| match method {
+            "getNumbers" => json!({ "result": [1, 2, 3] }),
+            "getNothing" => json!({}),
+            _ => json!({ "error": { "code": -32601, "message": "method not found" } }),
+        });
+        let numbers: Vec<u32> = client.call("getNumbers", json!({})).await.unwrap();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        let nothing: Option<Vec<u32>> = client.call("getNothing", json!({})).await.unwrap();
+        assert_eq!(nothing, None);
+
+        let error = client
+            .call::<Value>("getMissing", json!({}))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().starts_with("getMissing failed:"));
+        let error = client
+            .call::<bool>("getNumbers", json!({}))
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Unexpected getNumbers result:"));
+    }
+}
@@ -1,6 +1,23 @@
-use crate::jetkvm_rpc_client::JetKvmRpcClient;
-use anyhow::Result as AnyResult;
+//! Mouse jiggler: raw RPCs, a typed `JigglerConfig` with the web UI's
+//! presets, and a client-side scheduler that switches the jiggler on and off
+//! during configured time windows.
+//!
+//! The device runs the jiggler from a cron expression with a seconds field
+//! (`*/30 * * * * *`), evaluated in the config's timezone. The schedule here
+//! works one level up: it only decides when the jiggler is enabled at all.
+
+use crate::jetkvm_rpc_client::{rpc_result, JetKvmRpcClient};
+use crate::system::get_timezones;
+use anyhow::{anyhow, bail, Result as AnyResult};
+use chrono::{DateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, Duration, Instant};
+use tracing::info;
 
 pub async fn rpc_get_jiggler_state(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getJigglerState", json!({})).await
@@ -19,3 +36,593 @@ pub async fn rpc_set_jiggler_config(client: &JetKvmRpcClient, jiggler_config: Va
     let params = json!({ "jigglerConfig": jiggler_config });
     client.send_rpc("setJigglerConfig", params).await
 }
+
+/// Jiggler settings as stored by the device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JigglerConfig {
+    /// Only jiggle after this many seconds without user input.
+    pub inactivity_limit_seconds: u64,
+    /// Random delay added to each run, in percent of the interval.
+    #[serde(default)]
+    pub jitter_percentage: u32,
+    /// When to jiggle: a cron expression with a seconds field, e.g.
+    /// `0 */5 * * * *`. Five-field expressions without seconds are accepted
+    /// too.
+    pub schedule_cron_tab: String,
+    /// IANA timezone the cron expression is evaluated in; UTC, the device's
+    /// clock, when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// The jiggler presets offered by the web UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum JigglerPreset {
+    /// Every 30 seconds.
+    Frequent,
+    /// Every minute.
+    Standard,
+    /// Every 5 minutes.
+    Light,
+}
+
+impl JigglerConfig {
+    pub fn preset(preset: JigglerPreset) -> Self {
+        let (inactivity_limit_seconds, schedule_cron_tab) = match preset {
+            JigglerPreset::Frequent => (30, "*/30 * * * * *"),
+            JigglerPreset::Standard => (60, "0 * * * * *"),
+            JigglerPreset::Light => (300, "0 */5 * * * *"),
+        };
+        Self {
+            inactivity_limit_seconds,
+            jitter_percentage: 25,
+            schedule_cron_tab: schedule_cron_tab.to_string(),
+            timezone: None,
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.timezone = Some(timezone.into());
+        self
+    }
+
+    /// Checks the config. `timezones` is the device's list (see
+    /// `system::get_timezones`); the timezone is only checked against it
+    /// when given.
+    pub fn validate(&self, timezones: Option<&[String]>) -> AnyResult<()> {
+        if self.inactivity_limit_seconds == 0 {
+            bail!("Jiggler inactivity limit must be at least one second");
+        }
+        if self.jitter_percentage > 100 {
+            bail!(
+                "Jiggler jitter must be 0-100%, got {}%",
+                self.jitter_percentage
+            );
+        }
+        validate_cron_expression(&self.schedule_cron_tab)?;
+        if let (Some(timezone), Some(timezones)) = (&self.timezone, timezones) {
+            if !timezones.iter().any(|known| known == timezone) {
+                bail!("Unknown timezone {:?}", timezone);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks a cron expression with five fields (minute hour day month
+/// weekday) or six (with seconds first). Fields may be `*`, `?` (day and
+/// weekday only), numbers, ranges `a-b`, steps `*/n` or `a-b/n`, and
+/// comma-separated lists of those.
+pub fn validate_cron_expression(expr: &str) -> AnyResult<()> {
+    const SECONDS: (&str, u32, u32) = ("second", 0, 59);
+    const FIELDS: [(&str, u32, u32); 5] = [
+        ("minute", 0, 59),
+        ("hour", 0, 23),
+        ("day of month", 1, 31),
+        ("month", 1, 12),
+        ("day of week", 0, 7),
+    ];
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let limits: Vec<(&str, u32, u32)> = match fields.len() {
+        5 => FIELDS.to_vec(),
+        6 => std::iter::once(SECONDS).chain(FIELDS).collect(),
+        n => bail!(
+            "Cron expression {:?} has {} fields, expected 5 or 6",
+            expr,
+            n
+        ),
+    };
+    for (field, (name, min, max)) in fields.iter().zip(limits) {
+        let wildcard_ok = name.starts_with("day");
+        validate_cron_field(field, min, max, wildcard_ok)
+            .map_err(|e| anyhow!("Invalid {} field {:?} in {:?}: {}", name, field, expr, e))?;
+    }
+    Ok(())
+}
+
+fn validate_cron_field(field: &str, min: u32, max: u32, question_ok: bool) -> AnyResult<()> {
+    let number = |s: &str| -> AnyResult<u32> {
+        let n: u32 = s.parse().map_err(|_| anyhow!("{:?} is not a number", s))?;
+        if !(min..=max).contains(&n) {
+            bail!("{} is outside {}-{}", n, min, max);
+        }
+        Ok(n)
+    };
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        if let Some(step) = step {
+            match step.parse::<u32>() {
+                Ok(n) if n > 0 => {}
+                _ => bail!("step {:?} must be a positive number", step),
+            }
+        }
+        match range {
+            "*" => {}
+            "?" if question_ok && step.is_none() => {}
+            _ => match range.split_once('-') {
+                Some((from, to)) => {
+                    if number(from)? > number(to)? {
+                        bail!("range {:?} is reversed", range);
+                    }
+                }
+                None => {
+                    number(range)?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Reads whether the jiggler is enabled.
+pub async fn get_jiggler_enabled(client: &JetKvmRpcClient) -> AnyResult<bool> {
+    client.call("getJigglerState", json!({})).await
+}
+
+/// Enables or disables the jiggler.
+pub async fn set_jiggler_enabled(client: &JetKvmRpcClient, enabled: bool) -> AnyResult<()> {
+    rpc_result(
+        "setJigglerState",
+        rpc_set_jiggler_state(client, enabled).await?,
+    )?;
+    Ok(())
+}
+
+/// Reads the jiggler configuration as a typed value.
+pub async fn get_jiggler_config(client: &JetKvmRpcClient) -> AnyResult<JigglerConfig> {
+    client.call("getJigglerConfig", json!({})).await
+}
+
+/// Validates `config` (checking its timezone against the device's list)
+/// and stores it on the device.
+pub async fn set_jiggler_config(client: &JetKvmRpcClient, config: &JigglerConfig) -> AnyResult<()> {
+    let timezones = match config.timezone {
+        Some(_) => Some(get_timezones(client).await?),
+        None => None,
+    };
+    config.validate(timezones.as_deref())?;
+    let res = rpc_set_jiggler_config(client, serde_json::to_value(config)?).await?;
+    rpc_result("setJigglerConfig", res)?;
+    Ok(())
+}
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A weekly time window, e.g. `Mon-Fri 09:00-17:30`. A window whose end is
+/// before its start runs overnight into the next day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JigglerWindow {
+    /// Days the window starts on; bit 0 is Monday.
+    pub days: u8,
+    /// Minutes after midnight.
+    pub start_minute: u32,
+    /// Minutes after midnight, up to 1440 (`24:00`).
+    pub end_minute: u32,
+}
+
+impl JigglerWindow {
+    /// Whether the window covers `minute` (after midnight) on `weekday`
+    /// (0 = Monday).
+    pub fn contains(&self, weekday: u32, minute: u32) -> bool {
+        let on = |day: u32| self.days & (1 << (day % 7)) != 0;
+        if self.start_minute < self.end_minute {
+            on(weekday) && (self.start_minute..self.end_minute).contains(&minute)
+        } else {
+            (on(weekday) && minute >= self.start_minute)
+                || (on(weekday + 6) && minute < self.end_minute)
+        }
+    }
+}
+
+impl FromStr for JigglerWindow {
+    type Err = anyhow::Error;
+
+    /// Parses `[DAYS ]HH:MM-HH:MM`, where DAYS is `daily`, `weekdays`,
+    /// `weekends`, a day (`Mon`), a range (`Mon-Fri`) or a comma-separated
+    /// list of those. Without DAYS the window applies every day.
+    fn from_str(s: &str) -> AnyResult<Self> {
+        let s = s.trim();
+        let (days, times) = match s.rsplit_once(' ') {
+            Some((days, times)) => (parse_days(days.trim())?, times),
+            None => (0x7F, s),
+        };
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid time range {:?}, expected HH:MM-HH:MM", times))?;
+        let (start_minute, end_minute) = (parse_clock(start)?, parse_clock(end)?);
+        if start_minute == end_minute || start_minute == MINUTES_PER_DAY {
+            bail!("Time range {:?} is empty", times);
+        }
+        Ok(Self {
+            days,
+            start_minute,
+            end_minute,
+        })
+    }
+}
+
+fn parse_days(s: &str) -> AnyResult<u8> {
+    let day = |name: &str| -> AnyResult<u32> {
+        let name = name.trim().to_lowercase();
+        WEEKDAYS
+            .iter()
+            .position(|day| name.starts_with(day))
+            .map(|i| i as u32)
+            .ok_or_else(|| anyhow!("Unknown day {:?}", name))
+    };
+    let mut mask = 0u8;
+    for part in s.split(',') {
+        mask |= match part.trim().to_lowercase().as_str() {
+            "daily" => 0x7F,
+            "weekdays" => 0x1F,
+            "weekends" => 0x60,
+            part => match part.split_once('-') {
+                Some((from, to)) => {
+                    let (from, to) = (day(from)?, day(to)?);
+                    let mut mask = 0u8;
+                    let mut d = from;
+                    loop {
+                        mask |= 1 << d;
+                        if d == to {
+                            break mask;
+                        }
+                        d = (d + 1) % 7;
+                    }
+                }
+                None => 1 << day(part)?,
+            },
+        };
+    }
+    Ok(mask)
+}
+
+fn parse_clock(s: &str) -> AnyResult<u32> {
+    let invalid = || anyhow!("Invalid time {:?}, expected HH:MM", s);
+    let (hours, minutes) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 || hours * 60 + minutes > MINUTES_PER_DAY {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Parses a UTC offset such as `+02:00`, `-0530`, `+1` or `UTC`, in minutes.
+pub fn parse_utc_offset(s: &str) -> AnyResult<i32> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") {
+        return Ok(0);
+    }
+    let invalid = || anyhow!("Invalid UTC offset {:?}, expected e.g. +02:00", s);
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 14 || minutes >= 60 {
+        return Err(invalid());
+    }
+    Ok(sign * (hours * 60 + minutes))
+}
+
+/// The clock a `JigglerSchedule` reads its windows in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleZone {
+    /// A fixed offset from UTC, in minutes.
+    Offset(i32),
+    /// An IANA timezone such as `Europe/Madrid`, following its daylight
+    /// saving changes.
+    Named(Tz),
+}
+
+impl Default for ScheduleZone {
+    fn default() -> Self {
+        Self::Offset(0)
+    }
+}
+
+impl ScheduleZone {
+    /// Offset from UTC in minutes in effect at `unix_secs`.
+    pub fn offset_minutes_at(&self, unix_secs: i64) -> i32 {
+        match self {
+            Self::Offset(minutes) => *minutes,
+            Self::Named(tz) => DateTime::from_timestamp(unix_secs, 0)
+                .map(|utc| tz.offset_from_utc_datetime(&utc.naive_utc()).fix())
+                .map(|offset| offset.local_minus_utc() / 60)
+                .unwrap_or(0),
+        }
+    }
+}
+
+impl FromStr for ScheduleZone {
+    type Err = anyhow::Error;
+
+    /// Parses an IANA timezone name or a UTC offset (see `parse_utc_offset`).
+    fn from_str(s: &str) -> AnyResult<Self> {
+        let s = s.trim();
+        if let Ok(tz) = s.parse::<Tz>() {
+            return Ok(Self::Named(tz));
+        }
+        parse_utc_offset(s).map(Self::Offset).map_err(|_| {
+            anyhow!(
+                "Unknown timezone {:?}, expected an IANA name such as Europe/Madrid \
+                 or a UTC offset such as +02:00",
+                s
+            )
+        })
+    }
+}
+
+/// The zone the device evaluates the jiggler in: the jiggler config's
+/// timezone, or UTC (the device's clock) when it has none.
+pub async fn device_schedule_zone(client: &JetKvmRpcClient) -> AnyResult<ScheduleZone> {
+    match get_jiggler_config(client).await?.timezone {
+        Some(timezone) => timezone.parse(),
+        None => Ok(ScheduleZone::default()),
+    }
+}
+
+/// When the jiggler should be enabled: inside any of `windows`, read in
+/// `zone`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct JigglerSchedule {
+    pub windows: Vec<JigglerWindow>,
+    pub zone: ScheduleZone,
+}
+
+impl JigglerSchedule {
+    /// Whether the jiggler should be enabled at `unix_secs`.
+    pub fn is_active_at(&self, unix_secs: i64) -> bool {
+        let offset = self.zone.offset_minutes_at(unix_secs) as i64;
+        let local_minutes = unix_secs.div_euclid(60) + offset;
+        let days = local_minutes.div_euclid(MINUTES_PER_DAY as i64);
+        let minute = local_minutes.rem_euclid(MINUTES_PER_DAY as i64) as u32;
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 3).rem_euclid(7) as u32;
+        self.windows
+            .iter()
+            .any(|window| window.contains(weekday, minute))
+    }
+}
+
+pub const DEFAULT_SCHEDULE_CHECK_SECS: u64 = 30;
+
+/// A change made by `run_jiggler_schedule`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JigglerTransition {
+    pub unix_secs: i64,
+    pub enabled: bool,
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Enables the jiggler while `schedule` is active and disables it otherwise,
+/// checking every `check_interval`. Runs for `run_for`, or forever when
+/// `None`, and returns the changes it made.
+pub async fn run_jiggler_schedule(
+    client: &JetKvmRpcClient,
+    schedule: &JigglerSchedule,
+    check_interval: Duration,
+    run_for: Option<Duration>,
+) -> AnyResult<Vec<JigglerTransition>> {
+    let deadline = run_for.map(|run_for| Instant::now() + run_for);
+    let mut enabled = get_jiggler_enabled(client).await?;
+    let mut transitions = Vec::new();
+    loop {
+        let now = unix_now();
+        let wanted = schedule.is_active_at(now);
+        if enabled != wanted {
+            info!(
+                "Schedule turns the jiggler {}",
+                if wanted { "on" } else { "off" }
+            );
+            set_jiggler_enabled(client, wanted).await?;
+            enabled = wanted;
+            transitions.push(JigglerTransition {
+                unix_secs: now,
+                enabled: wanted,
+            });
+        }
+        let pause = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Ok(transitions);
+                }
+                check_interval.min(left)
+            }
+            None => check_interval,
+        };
+        sleep(pause).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_are_valid() {
+        for preset in [
+            JigglerPreset::Frequent,
+            JigglerPreset::Standard,
+            JigglerPreset::Light,
+        ] {
+            JigglerConfig::preset(preset).validate(None).unwrap();
+        }
+        let config = JigglerConfig::preset(JigglerPreset::Light).with_timezone("Europe/Madrid");
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            json!({
+                "inactivity_limit_seconds": 300,
+                "jitter_percentage": 25,
+                "schedule_cron_tab": "0 */5 * * * *",
+                "timezone": "Europe/Madrid",
+            })
+        );
+        let timezones = vec!["UTC".to_string(), "Europe/Madrid".to_string()];
+        config.validate(Some(&timezones)).unwrap();
+        assert!(config
+            .with_timezone("Mars/Olympus")
+            .validate(Some(&timezones))
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_cron_expression() {
+        for ok in [
+            "*/30 * * * * *",
+            "0 */5 * * * *",
+            "0 9-17 * * 1-5",
+            "0,30 8,12 ? * 0-6/2",
+        ] {
+            validate_cron_expression(ok).unwrap();
+        }
+        for bad in [
+            "* * * *",
+            "60 * * * * *",
+            "0 * 25 * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "? * * * *",
+            "@every 1m",
+        ] {
+            assert!(validate_cron_expression(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_windows_and_offsets() {
+        let window: JigglerWindow = "Mon-Fri 09:00-17:30".parse().unwrap();
+        assert_eq!(window.days, 0x1F);
+        assert_eq!((window.start_minute, window.end_minute), (540, 1050));
+        assert_eq!(
+            "weekends 00:00-24:00"
+                .parse::<JigglerWindow>()
+                .unwrap()
+                .days,
+            0x60
+        );
+        assert_eq!(
+            "Fri-Mon 10:00-11:00".parse::<JigglerWindow>().unwrap().days,
+            0x71
+        );
+        assert_eq!(
+            "Sat,sunday 10:00-11:00"
+                .parse::<JigglerWindow>()
+                .unwrap()
+                .days,
+            0x60
+        );
+        assert_eq!("22:00-06:00".parse::<JigglerWindow>().unwrap().days, 0x7F);
+        for bad in [
+            "Mon 09:00",
+            "Mon 09:00-09:00",
+            "Foo 09:00-10:00",
+            "25:00-26:00",
+        ] {
+            assert!(bad.parse::<JigglerWindow>().is_err(), "{}", bad);
+        }
+
+        assert_eq!(parse_utc_offset("UTC").unwrap(), 0);
+        assert_eq!(parse_utc_offset("+02:00").unwrap(), 120);
+        assert_eq!(parse_utc_offset("-0530").unwrap(), -330);
+        assert_eq!(parse_utc_offset("+1").unwrap(), 60);
+        assert!(parse_utc_offset("02:00").is_err());
+    }
+
+    #[test]
+    fn test_schedule_is_active_at() {
+        // 2024-01-01 00:00 UTC was a Monday.
+        let monday = 1_704_067_200;
+        let hour = 3600;
+        let office = JigglerSchedule {
+            windows: vec!["Mon-Fri 09:00-17:00".parse().unwrap()],
+            zone: ScheduleZone::Offset(0),
+        };
+        assert!(!office.is_active_at(monday + 8 * hour));
+        assert!(office.is_active_at(monday + 9 * hour));
+        assert!(!office.is_active_at(monday + 17 * hour));
+        assert!(!office.is_active_at(monday + 5 * 24 * hour + 10 * hour));
+
+        // The same window two hours east of UTC starts at 07:00 UTC.
+        let east = JigglerSchedule {
+            zone: ScheduleZone::Offset(120),
+            ..office.clone()
+        };
+        assert!(east.is_active_at(monday + 7 * hour));
+
+        // Overnight from Sunday into Monday.
+        let night = JigglerSchedule {
+            windows: vec!["Sun 22:00-06:00".parse().unwrap()],
+            zone: ScheduleZone::Offset(0),
+        };
+        assert!(night.is_active_at(monday + 5 * hour));
+        assert!(!night.is_active_at(monday + 22 * hour));
+        assert!(night.is_active_at(monday - hour));
+    }
+
+    #[test]
+    fn test_schedule_follows_daylight_saving() {
+        let zone: ScheduleZone = "Europe/Madrid".parse().unwrap();
+        assert!(matches!(zone, ScheduleZone::Named(_)));
+        assert_eq!(
+            "+02:00".parse::<ScheduleZone>().unwrap(),
+            ScheduleZone::Offset(120)
+        );
+        assert!("Mars/Olympus".parse::<ScheduleZone>().is_err());
+
+        // Madrid moved from UTC+1 to UTC+2 on 2024-03-31 (a Sunday), so a
+        // 09:00 start is 08:00 UTC on the Friday before and 07:00 UTC on
+        // the Monday after.
+        let friday = 1_711_670_400;
+        let monday = 1_711_929_600;
+        let hour = 3600;
+        let office = JigglerSchedule {
+            windows: vec!["Mon-Fri 09:00-17:00".parse().unwrap()],
+            zone,
+        };
+        assert_eq!(zone.offset_minutes_at(friday), 60);
+        assert_eq!(zone.offset_minutes_at(monday), 120);
+        assert!(!office.is_active_at(friday + 7 * hour));
+        assert!(office.is_active_at(friday + 8 * hour));
+        assert!(office.is_active_at(monday + 7 * hour));
+        assert!(!office.is_active_at(monday + 15 * hour));
+    }
+}
@@ -0,0 +1,552 @@
+//! Human-readable key sequence DSL.
+//!
+//! Parses an xdotool-like syntax into keyboard report sequences so shortcuts
+//! can be written without hand-crafting HID bytes. Tokens are separated by
+//! whitespace:
+//!
+//! - `ctrl+alt+Delete`, `super+r`, `Return` — a chord, pressed then released.
+//! - `<F12>x5` — a chord in angle brackets, optionally repeated `xN` times.
+//! - `{wait 500}` — pause for the given number of milliseconds.
+//! - `{hold shift}` … `{release shift}` — keep a modifier or key pressed for
+//!   every step in between.
+//! - `"literal text"` — typed through the selected `KeyboardLayout`. Supports
+//!   `\"`, `\\`, `\n` and `\t` escapes.
+//!
+//! Key names are resolved case-insensitively against
+//! `keyboard_mappings::KEYS` / `MODIFIERS`, plus the usual xdotool aliases
+//! (`ctrl`, `super`, `esc`, `Return`, `Up`, `Page_Down`, …) and single
+//! letters or digits.
+
+use crate::keyboard_layout::KeyboardLayout;
+use crate::keyboard_mappings::{key_name_to_hid, modifier_name_to_mask, KEYS};
+use crate::keyboard_state::{HidKeyboardState, ReportStep, MAX_KEYS, RELEASE_DELAY_MS};
+use crate::text_to_macro::{text_to_macro_steps, MacroStep};
+use anyhow::{anyhow, bail, Result};
+
+/// A resolved key name: either a modifier bit or a regular HID usage code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRef {
+    Modifier(u8),
+    Key(u8),
+}
+
+/// One parsed token of a key sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    /// Press `modifier` + `keys` together, then release. Repeated `repeat` times.
+    Chord {
+        modifier: u8,
+        keys: Vec<u8>,
+        repeat: u32,
+    },
+    /// Pause for the given number of milliseconds.
+    Wait(u64),
+    /// Keep a key or modifier pressed for all following steps.
+    Hold(KeyRef),
+    /// Stop holding a key or modifier previously passed to `Hold`.
+    Release(KeyRef),
+    /// Literal text typed through the keyboard layout.
+    Text(String),
+}
+
+/// xdotool-style names that don't match an entry in `KEYS`/`MODIFIERS`
+/// directly. Looked up by lowercase name before anything else so that e.g.
+/// `Return` maps to Enter rather than the HID "Return" usage (0x9e).
+const ALIASES: &[(&str, &str)] = &[
+    ("ctrl", "ControlLeft"),
+    ("control", "ControlLeft"),
+    ("lctrl", "ControlLeft"),
+    ("rctrl", "ControlRight"),
+    ("shift", "ShiftLeft"),
+    ("lshift", "ShiftLeft"),
+    ("rshift", "ShiftRight"),
+    ("alt", "AltLeft"),
+    ("lalt", "AltLeft"),
+    ("ralt", "AltRight"),
+    ("altgr", "AltRight"),
+    ("super", "MetaLeft"),
+    ("lsuper", "MetaLeft"),
+    ("rsuper", "MetaRight"),
+    ("win", "MetaLeft"),
+    ("meta", "MetaLeft"),
+    ("cmd", "MetaLeft"),
+    ("command", "MetaLeft"),
+    ("return", "Enter"),
+    ("ret", "Enter"),
+    ("esc", "Escape"),
+    ("del", "Delete"),
+    ("ins", "Insert"),
+    ("up", "ArrowUp"),
+    ("down", "ArrowDown"),
+    ("left", "ArrowLeft"),
+    ("right", "ArrowRight"),
+    ("page_up", "PageUp"),
+    ("pgup", "PageUp"),
+    ("page_down", "PageDown"),
+    ("pgdn", "PageDown"),
+    ("next", "PageDown"),
+    ("caps_lock", "CapsLock"),
+    ("num_lock", "NumLock"),
+    ("scroll_lock", "ScrollLock"),
+    ("print", "PrintScreen"),
+    ("prtsc", "PrintScreen"),
+    ("menu", "ContextMenu"),
+];
+
+/// Resolves a key name from the DSL into a modifier bit or HID key code.
+pub fn resolve_key(name: &str) -> Option<KeyRef> {
+    let lower = name.to_ascii_lowercase();
+    let canonical = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lower)
+        .map(|(_, canonical)| *canonical)
+        .unwrap_or(name);
+
+    if let Some(mask) = modifier_name_to_mask(canonical) {
+        return Some(KeyRef::Modifier(mask));
+    }
+    if let Some(hid) = key_name_to_hid(canonical) {
+        return Some(KeyRef::Key(hid));
+    }
+    if let Some((_, &hid)) = KEYS.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)) {
+        return Some(KeyRef::Key(hid));
+    }
+
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => {
+            key_name_to_hid(&format!("Key{}", c.to_ascii_uppercase())).map(KeyRef::Key)
+        }
+        (Some(c), None) if c.is_ascii_digit() => {
+            key_name_to_hid(&format!("Digit{}", c)).map(KeyRef::Key)
+        }
+        _ => None,
+    }
+}
+
+fn resolve_key_or_err(name: &str) -> Result<KeyRef> {
+    resolve_key(name).ok_or_else(|| anyhow!("Unknown key name: '{}'", name))
+}
+
+/// Parses a `+`-separated chord such as `ctrl+alt+Delete`.
+fn parse_chord(chord: &str, repeat: u32) -> Result<KeyAction> {
+    let mut modifier = 0u8;
+    let mut keys = Vec::new();
+
+    for part in chord.split('+') {
+        if part.is_empty() {
+            bail!("Empty key name in chord '{}'", chord);
+        }
+        match resolve_key_or_err(part)? {
+            KeyRef::Modifier(mask) => modifier |= mask,
+            KeyRef::Key(hid) => {
+                if !keys.contains(&hid) {
+                    keys.push(hid);
+                }
+            }
+        }
+    }
+
+    if keys.len() > MAX_KEYS {
+        bail!(
+            "Chord '{}' has {} keys; at most {} can be pressed at once",
+            chord,
+            keys.len(),
+            MAX_KEYS
+        );
+    }
+
+    Ok(KeyAction::Chord {
+        modifier,
+        keys,
+        repeat,
+    })
+}
+
+/// Parses a `{...}` directive body (without the braces).
+fn parse_directive(body: &str) -> Result<KeyAction> {
+    let mut words = body.split_whitespace();
+    let action = match (words.next(), words.next()) {
+        (Some("wait" | "sleep"), Some(ms)) => KeyAction::Wait(
+            ms.parse()
+                .map_err(|_| anyhow!("Invalid wait duration '{}'", ms))?,
+        ),
+        (Some("hold"), Some(name)) => KeyAction::Hold(resolve_key_or_err(name)?),
+        (Some("release"), Some(name)) => KeyAction::Release(resolve_key_or_err(name)?),
+        _ => bail!("Unknown directive '{{{}}}'", body),
+    };
+    if words.next().is_some() {
+        bail!("Too many arguments in directive '{{{}}}'", body);
+    }
+    Ok(action)
+}
+
+/// Parses a key sequence into a list of actions without resolving text
+/// against a layout.
+pub fn parse_key_sequence(input: &str) -> Result<Vec<KeyAction>> {
+    let mut actions = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        match c {
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(other) => text.push(other),
+                            None => bail!("Unterminated escape in quoted text"),
+                        },
+                        Some(other) => text.push(other),
+                        None => bail!("Unterminated quoted text"),
+                    }
+                }
+                actions.push(KeyAction::Text(text));
+            }
+            '{' => {
+                chars.next();
+                let mut body = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(other) => body.push(other),
+                        None => bail!("Unterminated '{{{}'", body),
+                    }
+                }
+                actions.push(parse_directive(&body)?);
+            }
+            '<' => {
+                chars.next();
+                let mut chord = String::new();
+                loop {
+                    match chars.next() {
+                        Some('>') => break,
+                        Some(other) => chord.push(other),
+                        None => bail!("Unterminated '<{}'", chord),
+                    }
+                }
+                let mut repeat = 1;
+                if chars.peek() == Some(&'x') {
+                    chars.next();
+                    let mut digits = String::new();
+                    while let Some(&d) = chars.peek() {
+                        if !d.is_ascii_digit() {
+                            break;
+                        }
+                        digits.push(d);
+                        chars.next();
+                    }
+                    repeat = digits
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid repeat count after '<{}>'", chord))?;
+                    if repeat == 0 {
+                        bail!("Repeat count for '<{}>' must be at least 1", chord);
+                    }
+                }
+                actions.push(parse_chord(chord.trim(), repeat)?);
+            }
+            _ => {
+                let mut chord = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    chord.push(c);
+                    chars.next();
+                }
+                actions.push(parse_chord(&chord, 1)?);
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+fn check_held(state: &HidKeyboardState) -> Result<()> {
+    if state.is_rolled_over() {
+        bail!("Too many keys held at once (max {})", MAX_KEYS);
+    }
+    Ok(())
+}
+
+/// Presses `step` on top of `state` for its `delay_ms`, then releases it
+/// again, leaving `held_keys` and held modifiers down.
+fn press_step(
+    state: &mut HidKeyboardState,
+    step: &MacroStep,
+    held_keys: &[u8],
+    steps: &mut Vec<ReportStep>,
+) -> Result<()> {
+    state.press_modifiers(step.modifier);
+    for &key in &step.keys {
+        state.press_key(key);
+    }
+    check_held(state)?;
+    steps.push(ReportStep::Report(state.report()));
+    steps.push(ReportStep::Sleep(step.delay_ms));
+    state.release_modifiers(step.modifier);
+    for key in step.keys.iter().filter(|k| !held_keys.contains(k)) {
+        state.release_key(*key);
+    }
+    steps.push(ReportStep::Report(state.report()));
+    steps.push(ReportStep::Sleep(RELEASE_DELAY_MS));
+    Ok(())
+}
+
+/// Plans the report sequence for parsed actions. Every chord and typed
+/// character is pressed for `delay_ms` on top of whatever is held, then
+/// released for `RELEASE_DELAY_MS`. Held keys and modifiers stay in every
+/// report until their `{release}`; anything still held at the end is
+/// released by a final all-up report.
+pub fn plan_key_actions(
+    actions: &[KeyAction],
+    layout: &KeyboardLayout,
+    delay_ms: u64,
+) -> Result<Vec<ReportStep>> {
+    let mut state = HidKeyboardState::new();
+    let mut held_modifier = 0u8;
+    let mut held_keys: Vec<u8> = Vec::new();
+    let mut steps = Vec::new();
+
+    for action in actions {
+        match action {
+            KeyAction::Chord {
+                modifier,
+                keys,
+                repeat,
+            } => {
+                let step = MacroStep::new(keys.clone(), *modifier, delay_ms);
+                for _ in 0..*repeat {
+                    press_step(&mut state, &step, &held_keys, &mut steps)?;
+                }
+            }
+            KeyAction::Wait(ms) => steps.push(ReportStep::Sleep(*ms)),
+            KeyAction::Hold(key) => {
+                match key {
+                    KeyRef::Modifier(mask) => {
+                        if held_modifier & mask != 0 {
+                            continue;
+                        }
+                        held_modifier |= mask;
+                        state.press_modifiers(*mask);
+                    }
+                    KeyRef::Key(hid) => {
+                        if held_keys.contains(hid) {
+                            continue;
+                        }
+                        held_keys.push(*hid);
+                        state.press_key(*hid);
+                    }
+                }
+                check_held(&state)?;
+                steps.push(ReportStep::Report(state.report()));
+            }
+            KeyAction::Release(KeyRef::Modifier(mask)) => {
+                if held_modifier & mask == 0 {
+                    bail!("Cannot release modifier {:#04x}: it is not held", mask);
+                }
+                held_modifier &= !mask;
+                state.release_modifiers(*mask);
+                steps.push(ReportStep::Report(state.report()));
+            }
+            KeyAction::Release(KeyRef::Key(hid)) => {
+                let idx = held_keys
+                    .iter()
+                    .position(|k| k == hid)
+                    .ok_or_else(|| anyhow!("Cannot release key {:#04x}: it is not held", hid))?;
+                held_keys.remove(idx);
+                state.release_key(*hid);
+                steps.push(ReportStep::Report(state.report()));
+            }
+            KeyAction::Text(text) => {
+                for step in text_to_macro_steps(text, layout, delay_ms)? {
+                    press_step(&mut state, &step, &held_keys, &mut steps)?;
+                }
+            }
+        }
+    }
+
+    if !state.is_empty() {
+        state.clear();
+        steps.push(ReportStep::Report(state.report()));
+    }
+    Ok(steps)
+}
+
+/// Parses and plans a key sequence in one go.
+pub fn plan_key_sequence(
+    input: &str,
+    layout: &KeyboardLayout,
+    delay_ms: u64,
+) -> Result<Vec<ReportStep>> {
+    let actions = parse_key_sequence(input)?;
+    plan_key_actions(&actions, layout, delay_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::layouts;
+    use crate::keyboard_state::KeyboardReport;
+
+    #[test]
+    fn test_resolve_aliases_and_names() {
+        assert_eq!(resolve_key("ctrl"), Some(KeyRef::Modifier(0x01)));
+        assert_eq!(resolve_key("super"), Some(KeyRef::Modifier(0x08)));
+        assert_eq!(resolve_key("AltGr"), Some(KeyRef::Modifier(0x40)));
+        assert_eq!(resolve_key("Return"), Some(KeyRef::Key(0x28)));
+        assert_eq!(resolve_key("Delete"), Some(KeyRef::Key(0x4c)));
+        assert_eq!(resolve_key("f12"), Some(KeyRef::Key(0x45)));
+        assert_eq!(resolve_key("r"), Some(KeyRef::Key(0x15)));
+        assert_eq!(resolve_key("7"), Some(KeyRef::Key(0x24)));
+        assert_eq!(resolve_key("NotAKey"), None);
+    }
+
+    #[test]
+    fn test_parse_chords() {
+        let actions = parse_key_sequence("ctrl+alt+Delete super+r").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                KeyAction::Chord {
+                    modifier: 0x05,
+                    keys: vec![0x4c],
+                    repeat: 1
+                },
+                KeyAction::Chord {
+                    modifier: 0x08,
+                    keys: vec![0x15],
+                    repeat: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_repeat_wait_and_text() {
+        let actions = parse_key_sequence(r#"<F12>x5 {wait 500} "a \"b\"""#).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                KeyAction::Chord {
+                    modifier: 0,
+                    keys: vec![0x45],
+                    repeat: 5
+                },
+                KeyAction::Wait(500),
+                KeyAction::Text("a \"b\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_key_sequence("ctrl+bogus").is_err());
+        assert!(parse_key_sequence("ctrl+").is_err());
+        assert!(parse_key_sequence("\"unterminated").is_err());
+        assert!(parse_key_sequence("<F1>x0").is_err());
+        assert!(parse_key_sequence("{jump 3}").is_err());
+        assert!(parse_key_sequence("{wait 500").is_err());
+    }
+
+    fn report(modifier: u8, keys: &[u8]) -> ReportStep {
+        ReportStep::Report(KeyboardReport::new(modifier, keys.to_vec()))
+    }
+
+    /// The reports of a plan, without the pauses between them.
+    fn reports(steps: &[ReportStep]) -> Vec<ReportStep> {
+        steps
+            .iter()
+            .filter(|step| matches!(step, ReportStep::Report(_)))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_hold_release() {
+        let layout = layouts::get_layout("en-US").unwrap();
+        let steps = plan_key_sequence(
+            "{hold shift} Tab {wait 100} {release shift} Tab",
+            layout,
+            20,
+        )
+        .unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                report(0x02, &[]),
+                report(0x02, &[0x2b]),
+                ReportStep::Sleep(20),
+                report(0x02, &[]),
+                ReportStep::Sleep(RELEASE_DELAY_MS),
+                ReportStep::Sleep(100),
+                report(0, &[]),
+                report(0, &[0x2b]),
+                ReportStep::Sleep(20),
+                report(0, &[]),
+                ReportStep::Sleep(RELEASE_DELAY_MS),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_alt_tab_keeps_alt_held() {
+        let layout = layouts::get_layout("en-US").unwrap();
+        let steps = plan_key_sequence("{hold alt} Tab Tab {release alt}", layout, 20).unwrap();
+        assert_eq!(
+            reports(&steps),
+            vec![
+                report(0x04, &[]),
+                report(0x04, &[0x2b]),
+                report(0x04, &[]),
+                report(0x04, &[0x2b]),
+                report(0x04, &[]),
+                report(0, &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_held_key_and_chord_sharing_a_modifier() {
+        let layout = layouts::get_layout("en-US").unwrap();
+        // The chord's own ctrl press must not release the held ctrl, and a
+        // held key pressed again by a chord stays held.
+        let steps = plan_key_sequence("{hold ctrl} {hold a} ctrl+a", layout, 20).unwrap();
+        assert_eq!(
+            reports(&steps),
+            vec![
+                report(0x01, &[]),
+                report(0x01, &[0x04]),
+                report(0x01, &[0x04]),
+                report(0x01, &[0x04]),
+                // Keys still held at the end are released.
+                report(0, &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_release_without_hold_fails() {
+        let layout = layouts::get_layout("en-US").unwrap();
+        assert!(plan_key_sequence("{release ctrl}", layout, 20).is_err());
+    }
+
+    #[test]
+    fn test_plan_text_uses_layout() {
+        let layout = layouts::get_layout("en-US").unwrap();
+        let steps = plan_key_sequence("\"Hi\" Return", layout, 20).unwrap();
+        let reports = reports(&steps);
+        assert_eq!(reports.len(), 6);
+        assert_eq!(reports[0], report(0x02, &[0x0b]));
+        assert_eq!(reports[4], report(0, &[0x28]));
+    }
+}
@@ -1,9 +1,47 @@
 use crate::jetkvm_rpc_client::JetKvmRpcClient;
-use anyhow::Result as AnyResult;
+use crate::keyboard_layout::{layouts, KeyboardLayout};
+use crate::keyboard_state::{
+    apply_caps_lock, caps_lock_keys, plan_key_combinations, plan_macro_steps,
+    plan_timed_macro_step, KeyboardLedState, KeysDownState, ReportStep, RELEASE_DELAY_MS,
+};
+use crate::text_to_macro::MacroStep;
+use crate::typing_profile::{LatencyTracker, TimingRng, TypingProfile};
+use anyhow::{anyhow, bail, Result as AnyResult};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tokio::time::{sleep, Duration};
-use tracing::debug;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long `set_lock_state` waits for the host to update its LEDs.
+const LOCK_STATE_TIMEOUT_MS: u64 = 1000;
+
+/// All-up reports re-sent by `ensure_keys_released` before giving up.
+const STUCK_KEY_RETRIES: usize = 3;
+
+/// How typing helpers handle lock keys that are already on at the target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LockKeyStrategy {
+    /// Assume Caps Lock is off.
+    Ignore,
+    /// Turn Caps Lock off while typing and restore it afterwards.
+    ToggleCapsLock,
+    /// Leave Caps Lock alone and invert Shift on the letter keys it affects.
+    #[default]
+    InvertShift,
+}
+
+/// Options for the typing helpers.
+#[derive(Clone, Debug, Default)]
+pub struct TypingOptions {
+    /// Press/release timing, jitter, bursts and adaptive slowdown.
+    pub profile: TypingProfile,
+    /// How to handle lock keys that are already on at the target.
+    pub lock_keys: LockKeyStrategy,
+    /// Cross-check `getKeyDownState` after every N key presses (and at the
+    /// end), re-sending all-up reports if a key is left held.
+    pub verify_every: Option<usize>,
+}
 
 /// Sends a keyboard report with the given modifier and keys.
 pub async fn rpc_keyboard_report(
@@ -90,44 +128,266 @@ fn char_to_hid(c: char) -> Option<(u8, u8)> {
 
 /// Sends text as a series of keyboard events (press and release) over the JSON‑RPC channel.
 /// This is a simple US-ASCII only implementation. For full layout support, use `send_text_with_layout`.
+/// Each key is held for 10 ms. Letters are corrected for the target's Caps Lock state.
 pub async fn rpc_sendtext(
     client: &crate::jetkvm_rpc_client::JetKvmRpcClient,
     text: &str,
 ) -> AnyResult<()> {
+    let options = TypingOptions {
+        profile: TypingProfile::sendtext(),
+        ..Default::default()
+    };
+    rpc_sendtext_with_options(client, text, &options).await
+}
+
+/// Like `rpc_sendtext`, with explicit typing options.
+pub async fn rpc_sendtext_with_options(
+    client: &crate::jetkvm_rpc_client::JetKvmRpcClient,
+    text: &str,
+    options: &TypingOptions,
+) -> AnyResult<()> {
+    let mut macro_steps = Vec::new();
     for c in text.chars() {
         if let Some((modifier, keycode)) = char_to_hid(c) {
-            crate::keyboard::rpc_keyboard_report(client, modifier as u64, vec![keycode]).await?;
-            sleep(Duration::from_millis(10)).await;
-            crate::keyboard::rpc_keyboard_report(client, 0, vec![]).await?;
-            sleep(Duration::from_millis(10)).await;
+            macro_steps.push(MacroStep::new(
+                vec![keycode],
+                modifier,
+                options.profile.press_ms,
+            ));
         } else {
             debug!("Unsupported character: {}", c);
         }
     }
-    Ok(())
+    send_typing_steps(client, macro_steps, &layouts::EN_US, options).await
 }
 
 /// Sends text using a specific keyboard layout with full support for accented characters and dead keys.
+/// Each key is held for `delay_ms`. Letters are corrected for the target's Caps Lock state.
 pub async fn send_text_with_layout(
     client: &crate::jetkvm_rpc_client::JetKvmRpcClient,
     text: &str,
     layout_code: &str,
     delay_ms: u64,
 ) -> AnyResult<()> {
-    use crate::text_to_macro::text_to_macro_steps_with_layout_code;
+    let options = TypingOptions {
+        profile: TypingProfile::standard().with_press_ms(delay_ms),
+        ..Default::default()
+    };
+    send_text_with_options(client, text, layout_code, &options).await
+}
+
+/// Like `send_text_with_layout`, with explicit timing, lock-key and verification options.
+pub async fn send_text_with_options(
+    client: &crate::jetkvm_rpc_client::JetKvmRpcClient,
+    text: &str,
+    layout_code: &str,
+    options: &TypingOptions,
+) -> AnyResult<()> {
+    use crate::text_to_macro::text_to_macro_steps;
+
+    let layout = layouts::get_layout_or_default(layout_code);
+    let macro_steps = text_to_macro_steps(text, layout, options.profile.press_ms)?;
+    send_typing_steps(client, macro_steps, layout, options).await
+}
+
+/// Sends a key sequence written in the `key_sequence` DSL, e.g.
+/// `ctrl+alt+Delete {wait 500} "root" Return`. Quoted text is typed with the given layout.
+pub async fn send_key_sequence(
+    client: &crate::jetkvm_rpc_client::JetKvmRpcClient,
+    sequence: &str,
+    layout_code: &str,
+    delay_ms: u64,
+) -> AnyResult<()> {
+    use crate::key_sequence::plan_key_sequence;
 
-    let macro_steps = text_to_macro_steps_with_layout_code(text, layout_code, delay_ms)?;
+    let layout = layouts::get_layout(layout_code)
+        .ok_or_else(|| anyhow!("Unknown keyboard layout: {}", layout_code))?;
+    let steps = plan_key_sequence(sequence, layout, delay_ms)?;
+    send_report_steps(client, &steps).await
+}
 
-    for step in macro_steps {
-        rpc_keyboard_report(client, step.modifier as u64, step.keys).await?;
-        sleep(Duration::from_millis(step.delay_ms)).await;
-        rpc_keyboard_report(client, 0, vec![]).await?;
-        sleep(Duration::from_millis(10)).await;
+/// Presses each macro step for its `delay_ms`, then releases all keys.
+pub async fn send_macro_steps(
+    client: &crate::jetkvm_rpc_client::JetKvmRpcClient,
+    macro_steps: Vec<crate::text_to_macro::MacroStep>,
+) -> AnyResult<()> {
+    let steps = plan_macro_steps(&macro_steps)?;
+    send_report_steps(client, &steps).await
+}
+
+/// Sends typing steps while following the target's Caps Lock LED.
+///
+/// The LED state is read before typing and then tracked through the device's
+/// `keyboardLedState` notifications, so a Caps Lock toggled mid-way is picked
+/// up from the next character on. If the state cannot be read, Caps Lock is
+/// assumed to be off. Each key is timed by the options' `TypingProfile`
+/// (the steps' own `delay_ms` is ignored). With `verify_every` set, stuck
+/// keys are detected and released along the way.
+pub async fn send_typing_steps(
+    client: &JetKvmRpcClient,
+    macro_steps: Vec<MacroStep>,
+    layout: &KeyboardLayout,
+    options: &TypingOptions,
+) -> AnyResult<()> {
+    let strategy = options.lock_keys;
+    let profile = &options.profile;
+    let mut rng = TimingRng::from_time();
+    let mut latency = LatencyTracker::new();
+    let caps_keys = caps_lock_keys(layout);
+    let mut led_rx = client.keyboard_led_watcher();
+    let initial = if strategy == LockKeyStrategy::Ignore {
+        KeyboardLedState::default()
+    } else {
+        get_keyboard_led_state(client).await.unwrap_or_else(|e| {
+            warn!(
+                "Could not read keyboard LED state, assuming lock keys are off: {}",
+                e
+            );
+            KeyboardLedState::default()
+        })
+    };
+    let mut caps_lock = initial.caps_lock;
+    if strategy == LockKeyStrategy::ToggleCapsLock && caps_lock {
+        caps_lock = set_lock_state(client, Some(false), None, None)
+            .await?
+            .caps_lock;
+    }
+    led_rx.mark_unchanged();
+
+    for (idx, step) in macro_steps.iter().enumerate() {
+        if strategy != LockKeyStrategy::Ignore && led_rx.has_changed().unwrap_or(false) {
+            if let Some(state) = *led_rx.borrow_and_update() {
+                debug!("Keyboard LED state changed while typing: {:?}", state);
+                caps_lock = state.caps_lock;
+            }
+            if strategy == LockKeyStrategy::ToggleCapsLock && caps_lock {
+                caps_lock = set_lock_state(client, Some(false), None, None)
+                    .await?
+                    .caps_lock;
+                led_rx.mark_unchanged();
+            }
+        }
+        let step = apply_caps_lock(step, caps_lock, &caps_keys);
+        let (press_ms, release_ms) = profile.step_timing(idx, latency.slowdown(), &mut rng);
+        let reports = plan_timed_macro_step(&step, press_ms, release_ms)?;
+        if profile.adaptive {
+            send_report_steps_measured(client, &reports, &mut latency).await?;
+        } else {
+            send_report_steps(client, &reports).await?;
+        }
+
+        let last = idx + 1 == macro_steps.len();
+        if options
+            .verify_every
+            .map(|every| every.max(1))
+            .is_some_and(|every| idx % every == every - 1 || last)
+        {
+            ensure_keys_released(client).await?;
+        }
     }
 
+    if strategy == LockKeyStrategy::ToggleCapsLock && initial.caps_lock {
+        set_lock_state(client, Some(true), None, None).await?;
+    }
     Ok(())
 }
 
+/// Reads the keys the device currently holds down.
+pub async fn get_key_down_state(client: &JetKvmRpcClient) -> AnyResult<KeysDownState> {
+    client.call("getKeyDownState", json!({})).await
+}
+
+/// Checks `getKeyDownState` and re-sends all-up reports until the device
+/// holds no keys or modifiers. Returns how many all-up reports were re-sent.
+pub async fn ensure_keys_released(client: &JetKvmRpcClient) -> AnyResult<usize> {
+    let mut resent = 0;
+    loop {
+        let state = get_key_down_state(client).await?;
+        if state.is_empty() {
+            if resent > 0 {
+                warn!("Released stuck keys after {} all-up report(s)", resent);
+            }
+            return Ok(resent);
+        }
+        if resent == STUCK_KEY_RETRIES {
+            bail!(
+                "Keys still held after {} all-up reports: {:?}",
+                resent,
+                state
+            );
+        }
+        warn!("Device reports keys still held: {:?}, releasing", state);
+        rpc_keyboard_report(client, 0, vec![]).await?;
+        resent += 1;
+        sleep(Duration::from_millis(RELEASE_DELAY_MS)).await;
+    }
+}
+
+/// Releases every key and modifier, and every button of both the relative
+/// and the absolute mouse. Absolute reports always carry a position, so the
+/// absolute buttons are released at `abs_position` when given, else where
+/// this client last put the pointer, else at 0,0. Returns how many extra
+/// all-up reports were needed.
+pub async fn release_all(
+    client: &JetKvmRpcClient,
+    abs_position: Option<(i64, i64)>,
+) -> AnyResult<usize> {
+    use crate::mouse::{rpc_abs_mouse_report, rpc_rel_mouse_report};
+
+    rpc_keyboard_report(client, 0, vec![]).await?;
+    rpc_rel_mouse_report(client, 0, 0, 0).await?;
+    let last_position = *client.abs_mouse_position.lock().await;
+    let (x, y) = abs_position.or(last_position).unwrap_or((0, 0));
+    rpc_abs_mouse_report(client, x, y, 0).await?;
+    sleep(Duration::from_millis(RELEASE_DELAY_MS)).await;
+    ensure_keys_released(client).await
+}
+
+/// Reads the keyboard LED state as a typed value.
+pub async fn get_keyboard_led_state(client: &JetKvmRpcClient) -> AnyResult<KeyboardLedState> {
+    client.call("getKeyboardLedState", json!({})).await
+}
+
+/// Taps Caps Lock, Num Lock and Scroll Lock as needed so the host's LEDs match
+/// the requested state, then waits for the LEDs to confirm it. `None` leaves a
+/// lock unchanged. Returns the resulting LED state.
+pub async fn set_lock_state(
+    client: &JetKvmRpcClient,
+    caps_lock: Option<bool>,
+    num_lock: Option<bool>,
+    scroll_lock: Option<bool>,
+) -> AnyResult<KeyboardLedState> {
+    let current = get_keyboard_led_state(client).await?;
+    let toggles = current.lock_key_toggles(caps_lock, num_lock, scroll_lock);
+    if toggles.is_empty() {
+        return Ok(current);
+    }
+
+    let macro_steps = toggles
+        .into_iter()
+        .map(|key| MacroStep::new(vec![key], 0, 50))
+        .collect();
+    send_macro_steps(client, macro_steps).await?;
+
+    let deadline = Instant::now() + Duration::from_millis(LOCK_STATE_TIMEOUT_MS);
+    loop {
+        let state = get_keyboard_led_state(client).await?;
+        if state
+            .lock_key_toggles(caps_lock, num_lock, scroll_lock)
+            .is_empty()
+        {
+            return Ok(state);
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "Lock keys did not reach the requested state, LEDs report {:?}",
+                state
+            );
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
 /// Sends a Return (Enter) key press then releases it.
 pub async fn send_return(client: &crate::jetkvm_rpc_client::JetKvmRpcClient) -> AnyResult<()> {
     // Press Return (keycode 0x28)
@@ -330,12 +590,14 @@ pub async fn send_ctrl_cmd_q(client: &crate::jetkvm_rpc_client::JetKvmRpcClient)
 /// - `hold`: The duration (in milliseconds) to hold the key press (defaults to 100 ms).
 /// - `wait`: The delay (in milliseconds) after releasing the keys before proceeding to the next combination (defaults to 10 ms).
 ///
-///
+/// `hold_keys` and `hold_modifiers` default to `false` when omitted.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyCombo {
     pub modifier: u8,
     pub keys: Vec<u8>,
+    #[serde(default)]
     pub hold_keys: bool,
+    #[serde(default)]
     pub hold_modifiers: bool,
     pub hold: Option<u64>,
     pub wait: Option<u64>,
@@ -343,6 +605,29 @@ pub struct KeyCombo {
     pub clear_keys: Option<bool>,
 }
 
+/// Loads key combinations from inline JSON, or from a `.json`, `.yaml` or `.yml` file.
+pub fn load_key_combos(source: &str) -> AnyResult<Vec<KeyCombo>> {
+    if source.trim_start().starts_with('[') {
+        return serde_json::from_str(source)
+            .map_err(|e| anyhow!("Invalid key combinations JSON: {}", e));
+    }
+
+    let contents = std::fs::read_to_string(source)
+        .map_err(|e| anyhow!("Failed to read key combinations file '{}': {}", source, e))?;
+    let is_yaml = std::path::Path::new(source)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"));
+
+    if is_yaml {
+        serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid key combinations YAML in '{}': {}", source, e))
+    } else {
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid key combinations JSON in '{}': {}", source, e))
+    }
+}
+
 pub async fn rpc_get_keyboard_layout(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getKeyboardLayout", json!({})).await
 }
@@ -360,172 +645,52 @@ pub async fn rpc_get_key_down_state(client: &JetKvmRpcClient) -> AnyResult<Value
     client.send_rpc("getKeyDownState", json!({})).await
 }
 
-use std::collections::HashSet;
-use tokio::time;
+/// Sends a sequence of key combinations.
+///
+/// The full report sequence is planned up front by
+/// `keyboard_state::plan_key_combinations`, so a combo list that would exceed
+/// six simultaneous keys is rejected before anything is sent.
 pub async fn send_key_combinations(
     client: &JetKvmRpcClient,
     key_combos: Vec<KeyCombo>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut active_modifiers: u8 = 0;
-    let mut active_keys: HashSet<u8> = HashSet::new();
-    let mut hold_modifiers: HashSet<u8> = HashSet::new();
-    let mut hold_keys: HashSet<u8> = HashSet::new();
-
-    for combo in key_combos {
-        tracing::debug!(
-            "[DEBUG] Processing combo -> Modifier: {:#04x}, Keys: {:?}, Hold Keys: {}, Hold Modifiers: {}, Hold: {:?}, Wait: {:?}, Instant Release: {:?}",
-            combo.modifier,
-            combo.keys,
-            combo.hold_keys,
-            combo.hold_modifiers,
-            combo.hold,
-            combo.wait,
-            combo.instant_release.unwrap_or(false)
-        );
-
-        if combo.hold_modifiers {
-            hold_modifiers.insert(combo.modifier);
-        }
-
-        if combo.hold_keys {
-            hold_keys.extend(&combo.keys);
-        }
-
-        active_modifiers |= combo.modifier;
-        active_keys.extend(&combo.keys);
+    let steps = plan_key_combinations(&key_combos)?;
+    send_report_steps(client, &steps).await?;
+    Ok(())
+}
 
-        if combo.clear_keys.unwrap_or(false) {
-            // Immediately clear all keys and force modifiers to zero.
-            active_keys.clear();
-            active_modifiers = 0;
-            tracing::debug!(
-                "[DEBUG] clear_keys flag active. Forcing release of all keys and modifiers."
-            );
-            client
-                .send_rpc(
-                    "keyboardReport",
-                    json!({
-                        "modifier": active_modifiers,
-                        "keys": Vec::<u8>::new(),
-                    }),
-                )
-                .await?;
-
-            if let Some(wait_duration) = combo.wait {
-                tracing::debug!(
-                    "[DEBUG] Waiting {}ms after clear_keys combo...",
-                    wait_duration
+/// Executes a planned report sequence: sends each report and honours each pause.
+pub async fn send_report_steps(client: &JetKvmRpcClient, steps: &[ReportStep]) -> AnyResult<()> {
+    for step in steps {
+        match step {
+            ReportStep::Report(report) => {
+                debug!(
+                    "Sending keyboard report - Modifier: {:#04x}, Keys: {:02x?}",
+                    report.modifier, report.keys
                 );
-                time::sleep(Duration::from_millis(wait_duration)).await;
+                rpc_keyboard_report(client, report.modifier as u64, report.keys.clone()).await?;
             }
-            continue;
-        }
-
-        tracing::debug!(
-            "[DEBUG] Sending Key Press - Modifier: {:#04x}, Keys: {:?}",
-            active_modifiers,
-            active_keys
-        );
-
-        client
-            .send_rpc(
-                "keyboardReport",
-                json!({
-                    "modifier": active_modifiers,
-                    "keys": active_keys.clone().into_iter().collect::<Vec<u8>>(),
-                }),
-            )
-            .await?;
-
-        if let Some(hold_duration) = combo.hold {
-            tracing::debug!(
-                "[DEBUG] Holding keys for {}ms - Modifier: {:#04x}, Keys: {:?}",
-                hold_duration,
-                active_modifiers,
-                active_keys
-            );
-            time::sleep(Duration::from_millis(hold_duration)).await;
-
-            // If the combo did NOT specify to hold the keys, remove them from the active set.
-            if !combo.hold_keys {
-                let keys_to_remove: HashSet<u8> = combo.keys.iter().cloned().collect();
-                active_keys = active_keys.difference(&keys_to_remove).cloned().collect();
-            }
-            // For modifiers, we still check the hold_modifiers set.
-            if !hold_modifiers.contains(&combo.modifier) {
-                active_modifiers &= !combo.modifier;
-            }
-
-            tracing::debug!(
-                "[DEBUG] Releasing keys after hold duration. New Modifier: {:#04x}, New Keys: {:?}",
-                active_modifiers,
-                active_keys
-            );
-
-            client
-                .send_rpc(
-                    "keyboardReport",
-                    json!({
-                        "modifier": active_modifiers,
-                        "keys": active_keys.clone().into_iter().collect::<Vec<u8>>(),
-                    }),
-                )
-                .await?;
+            ReportStep::Sleep(ms) => sleep(Duration::from_millis(*ms)).await,
         }
+    }
+    Ok(())
+}
 
-        if combo.instant_release.unwrap_or(false) {
-            let keys_to_release: HashSet<u8> = combo.keys.iter().cloned().collect();
-            tracing::debug!(
-        "[DEBUG] Instant Release: Before releasing, active_keys: {:?}, active_modifiers: {:#04x}",
-        active_keys,
-        active_modifiers
-    );
-            active_keys = active_keys.difference(&keys_to_release).cloned().collect();
-
-            // Check if any other combo still holds this modifier.
-            if combo.instant_release.unwrap_or(false)
-                || active_keys.is_empty() && !hold_modifiers.contains(&combo.modifier)
-            {
-                active_modifiers &= !combo.modifier;
+/// Like `send_report_steps`, recording each report's round-trip time.
+async fn send_report_steps_measured(
+    client: &JetKvmRpcClient,
+    steps: &[ReportStep],
+    latency: &mut LatencyTracker,
+) -> AnyResult<()> {
+    for step in steps {
+        match step {
+            ReportStep::Report(report) => {
+                let started = Instant::now();
+                rpc_keyboard_report(client, report.modifier as u64, report.keys.clone()).await?;
+                latency.record(started.elapsed().as_secs_f64() * 1000.0);
             }
-
-            tracing::debug!(
-        "[DEBUG] Instant Release: After releasing, keys_to_release: {:?}, active_keys: {:?}, active_modifiers: {:#04x}",
-        keys_to_release,
-        active_keys,
-        active_modifiers
-    );
-
-            client
-                .send_rpc(
-                    "keyboardReport",
-                    json!({
-                        "modifier": active_modifiers,
-                        "keys": active_keys.clone().into_iter().collect::<Vec<u8>>(),
-                    }),
-                )
-                .await?;
-        }
-
-        if let Some(wait_duration) = combo.wait {
-            tracing::debug!(
-                "[DEBUG] End of combo: active_keys: {:?}, active_modifiers: {:#04x}",
-                active_keys,
-                active_modifiers
-            );
-            tracing::debug!(
-                "[DEBUG] Waiting {}ms before processing next key combo...",
-                wait_duration
-            );
-            time::sleep(Duration::from_millis(wait_duration)).await;
+            ReportStep::Sleep(ms) => sleep(Duration::from_millis(*ms)).await,
         }
     }
-
-    tracing::debug!(
-        "[DEBUG] Finished processing all key combos. Final Held Keys: {:?}, Final Held Modifier: {:#04x}",
-        active_keys,
-        active_modifiers
-    );
-
     Ok(())
 }
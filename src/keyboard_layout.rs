@@ -1,22 +1,23 @@
+use anyhow::{anyhow, Result};
 use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
-pub struct KeyCombo {
+/// One key press, optionally with Shift or AltGr held.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyStroke {
     pub key: String,
     pub shift: bool,
     pub alt_right: bool,
+    /// The key produces nothing on its own and combines with the next stroke.
     pub dead_key: bool,
-    pub accent_key: Option<Box<KeyCombo>>,
 }
 
-impl KeyCombo {
+impl KeyStroke {
     pub fn new(key: impl Into<String>) -> Self {
         Self {
             key: key.into(),
             shift: false,
             alt_right: false,
             dead_key: false,
-            accent_key: None,
         }
     }
 
@@ -34,11 +35,101 @@ impl KeyCombo {
         self.dead_key = true;
         self
     }
+}
+
+/// The key strokes that type one character, in order. The last stroke is the
+/// character's own key; earlier strokes are dead keys (or, with `compose`,
+/// the rest of a compose sequence). There is always at least one stroke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCombo {
+    strokes: Vec<KeyStroke>,
+    /// Press the layout's compose key before `strokes`.
+    pub compose: bool,
+}
+
+impl KeyCombo {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            strokes: vec![KeyStroke::new(key)],
+            compose: false,
+        }
+    }
+
+    /// A combo typed by `strokes` in order. Fails if `strokes` is empty.
+    pub fn sequence(strokes: Vec<KeyStroke>) -> Result<Self> {
+        if strokes.is_empty() {
+            return Err(anyhow!("A key combo needs at least one key stroke"));
+        }
+        Ok(Self {
+            strokes,
+            compose: false,
+        })
+    }
+
+    /// A compose sequence: the layout's compose key followed by `strokes`.
+    /// Fails if `strokes` is empty.
+    pub fn compose(strokes: Vec<KeyStroke>) -> Result<Self> {
+        Ok(Self {
+            compose: true,
+            ..Self::sequence(strokes)?
+        })
+    }
+
+    /// Every stroke, the character's own key last.
+    pub fn strokes(&self) -> &[KeyStroke] {
+        &self.strokes
+    }
+
+    pub fn with_shift(mut self) -> Self {
+        self.base_mut().shift = true;
+        self
+    }
+
+    pub fn with_alt_right(mut self) -> Self {
+        self.base_mut().alt_right = true;
+        self
+    }
 
+    /// Marks the character's own key as a dead key; typing the character
+    /// then ends with the layout's `DeadKeyFinish`.
+    pub fn with_dead_key(mut self) -> Self {
+        self.base_mut().dead_key = true;
+        self
+    }
+
+    /// Adds a dead key pressed before the character's own key. Called more
+    /// than once, the accents are pressed in the order they were added.
     pub fn with_accent_key(mut self, accent: KeyCombo) -> Self {
-        self.accent_key = Some(Box::new(accent));
+        let base = self.strokes.len() - 1;
+        self.strokes.splice(base..base, accent.strokes);
         self
     }
+
+    /// The character's own key, i.e. the last stroke.
+    pub fn base(&self) -> &KeyStroke {
+        self.strokes.last().expect("KeyCombo has no strokes")
+    }
+
+    /// Strokes pressed before the character's own key.
+    pub fn accents(&self) -> &[KeyStroke] {
+        &self.strokes[..self.strokes.len() - 1]
+    }
+
+    fn base_mut(&mut self) -> &mut KeyStroke {
+        self.strokes.last_mut().expect("KeyCombo has no strokes")
+    }
+}
+
+/// What is pressed after a character whose own key is a dead key, so the
+/// target prints the accent instead of waiting for a letter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeadKeyFinish {
+    /// Press Space, which most targets treat as "print the accent".
+    #[default]
+    Space,
+    /// Press the dead key a second time, for targets that print the accent
+    /// on a repeated dead key rather than on Space.
+    Repeat,
 }
 
 #[derive(Debug, Clone)]
@@ -46,6 +137,9 @@ pub struct KeyboardLayout {
     pub iso_code: String,
     pub name: String,
     pub chars: HashMap<char, KeyCombo>,
+    /// Key that starts a compose sequence on the target, if any.
+    pub compose_key: Option<KeyStroke>,
+    pub dead_key_finish: DeadKeyFinish,
 }
 
 impl KeyboardLayout {
@@ -54,6 +148,8 @@ impl KeyboardLayout {
             iso_code: iso_code.into(),
             name: name.into(),
             chars: HashMap::new(),
+            compose_key: None,
+            dead_key_finish: DeadKeyFinish::default(),
         }
     }
 
@@ -62,9 +158,47 @@ impl KeyboardLayout {
         self
     }
 
+    pub fn with_compose_key(mut self, key: KeyStroke) -> Self {
+        self.compose_key = Some(key);
+        self
+    }
+
+    pub fn with_dead_key_finish(mut self, finish: DeadKeyFinish) -> Self {
+        self.dead_key_finish = finish;
+        self
+    }
+
     pub fn get_char(&self, c: char) -> Option<&KeyCombo> {
         self.chars.get(&c)
     }
+
+    /// Every stroke typed for `c`: the compose key if the combo needs it, the
+    /// combo's strokes, and the dead-key finish if the last stroke is a dead key.
+    pub fn strokes_for(&self, c: char) -> Result<Vec<KeyStroke>> {
+        let combo = self
+            .get_char(c)
+            .ok_or_else(|| anyhow!("Character '{}' not found in layout {}", c, self.iso_code))?;
+        let mut strokes = Vec::with_capacity(combo.strokes.len() + 2);
+        if combo.compose {
+            let compose_key = self.compose_key.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "Character '{}' needs a compose key, but layout {} has none",
+                    c,
+                    self.iso_code
+                )
+            })?;
+            strokes.push(compose_key.clone());
+        }
+        strokes.extend(combo.strokes.iter().cloned());
+        let base = combo.base();
+        if base.dead_key {
+            strokes.push(match self.dead_key_finish {
+                DeadKeyFinish::Space => KeyStroke::new("Space"),
+                DeadKeyFinish::Repeat => base.clone(),
+            });
+        }
+        Ok(strokes)
+    }
 }
 
 pub mod layouts {
@@ -85,6 +219,11 @@ pub mod layouts {
         }
     }
 
+    /// Every built-in layout.
+    pub fn all_layouts() -> Vec<&'static KeyboardLayout> {
+        vec![&EN_US, &ES_ES]
+    }
+
     pub fn get_layout_or_default(iso_code: &str) -> &'static KeyboardLayout {
         get_layout(iso_code).unwrap_or(&EN_US)
     }
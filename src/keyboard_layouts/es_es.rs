@@ -3,11 +3,13 @@ use crate::keyboard_layout::{KeyCombo, KeyboardLayout};
 pub fn create_layout() -> KeyboardLayout {
     let mut layout = KeyboardLayout::new("es-ES", "Español");
 
-    let key_trema = KeyCombo::new("Quote").with_shift();
-    let key_acute = KeyCombo::new("Quote");
-    let key_hat = KeyCombo::new("BracketRight").with_shift();
-    let key_grave = KeyCombo::new("BracketRight");
-    let key_tilde = KeyCombo::new("Digit4").with_alt_right();
+    // All accents are dead keys. ^ and ` share the key right of P
+    // (BracketLeft); BracketRight is + and *.
+    let key_trema = KeyCombo::new("Quote").with_shift().with_dead_key();
+    let key_acute = KeyCombo::new("Quote").with_dead_key();
+    let key_hat = KeyCombo::new("BracketLeft").with_shift().with_dead_key();
+    let key_grave = KeyCombo::new("BracketLeft").with_dead_key();
+    let key_tilde = KeyCombo::new("Digit4").with_alt_right().with_dead_key();
 
     layout.chars.insert('A', KeyCombo::new("KeyA").with_shift());
     layout.chars.insert(
@@ -374,9 +376,7 @@ pub fn create_layout() -> KeyboardLayout {
     layout
         .chars
         .insert('?', KeyCombo::new("Minus").with_shift());
-    layout
-        .chars
-        .insert('¡', KeyCombo::new("Equal").with_dead_key());
+    layout.chars.insert('¡', KeyCombo::new("Equal"));
     layout
         .chars
         .insert('¿', KeyCombo::new("Equal").with_shift());
@@ -420,6 +420,11 @@ pub fn create_layout() -> KeyboardLayout {
     layout
         .chars
         .insert('>', KeyCombo::new("IntlBackslash").with_shift());
+    layout.chars.insert('¨', key_trema);
+    layout.chars.insert('´', key_acute);
+    layout.chars.insert('^', key_hat);
+    layout.chars.insert('`', key_grave);
+    layout.chars.insert('~', key_tilde);
     layout.chars.insert(' ', KeyCombo::new("Space"));
     layout.chars.insert('\n', KeyCombo::new("Enter"));
 
@@ -0,0 +1,426 @@
+use crate::jetkvm_rpc_client::{rpc_result, JetKvmRpcClient};
+use crate::key_sequence::{resolve_key, KeyRef};
+use crate::keyboard::send_macro_steps;
+use crate::keyboard_state::{plan_macro_steps, MAX_KEYS};
+use crate::text_to_macro::MacroStep;
+use anyhow::{anyhow, bail, Result as AnyResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Limits enforced by the device when saving macros.
+pub const MAX_MACROS: usize = 25;
+pub const MAX_STEPS_PER_MACRO: usize = 10;
+pub const MAX_MACRO_NAME_LEN: usize = 50;
+/// Step delay used by the web UI when none is given.
+pub const DEFAULT_STEP_DELAY_MS: u64 = 50;
+
+fn default_step_delay() -> u64 {
+    DEFAULT_STEP_DELAY_MS
+}
+
+/// One step of a stored macro. Keys and modifiers use the web UI names,
+/// e.g. `KeyA`, `Enter`, `ControlLeft`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyboardMacroStep {
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub modifiers: Vec<String>,
+    #[serde(default = "default_step_delay")]
+    pub delay: u64,
+}
+
+/// A keyboard macro as stored in the device configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyboardMacro {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub steps: Vec<KeyboardMacroStep>,
+    #[serde(default)]
+    pub sort_order: u32,
+}
+
+impl KeyboardMacro {
+    pub fn new(name: impl Into<String>, steps: Vec<KeyboardMacroStep>) -> Self {
+        Self {
+            id: String::new(),
+            name: name.into(),
+            steps,
+            sort_order: 0,
+        }
+    }
+
+    /// Converts the stored steps into macro steps for the keyboard report path.
+    pub fn to_macro_steps(&self) -> AnyResult<Vec<MacroStep>> {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(idx, step)| {
+                let mut macro_step = MacroStep {
+                    keys: Vec::new(),
+                    modifier: 0,
+                    delay_ms: step.delay,
+                };
+                for name in &step.modifiers {
+                    match resolve_key(name) {
+                        Some(KeyRef::Modifier(mask)) => macro_step.modifier |= mask,
+                        _ => bail!(
+                            "Macro '{}' step #{}: unknown modifier '{}'",
+                            self.name,
+                            idx,
+                            name
+                        ),
+                    }
+                }
+                for name in &step.keys {
+                    match resolve_key(name) {
+                        Some(KeyRef::Modifier(mask)) => macro_step.modifier |= mask,
+                        Some(KeyRef::Key(hid)) if !macro_step.keys.contains(&hid) => {
+                            macro_step.keys.push(hid)
+                        }
+                        Some(KeyRef::Key(_)) => {}
+                        None => bail!(
+                            "Macro '{}' step #{}: unknown key '{}'",
+                            self.name,
+                            idx,
+                            name
+                        ),
+                    }
+                }
+                if macro_step.keys.len() > MAX_KEYS {
+                    bail!(
+                        "Macro '{}' step #{}: {} keys exceed the {}-key report limit",
+                        self.name,
+                        idx,
+                        macro_step.keys.len(),
+                        MAX_KEYS
+                    );
+                }
+                Ok(macro_step)
+            })
+            .collect()
+    }
+
+    /// Checks the macro against the device limits and that every key resolves.
+    pub fn validate(&self) -> AnyResult<()> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            bail!("Macro name must not be empty");
+        }
+        if name.chars().count() > MAX_MACRO_NAME_LEN {
+            bail!(
+                "Macro name '{}' exceeds {} characters",
+                name,
+                MAX_MACRO_NAME_LEN
+            );
+        }
+        if self.steps.is_empty() {
+            bail!("Macro '{}' has no steps", name);
+        }
+        if self.steps.len() > MAX_STEPS_PER_MACRO {
+            bail!(
+                "Macro '{}' has {} steps, the maximum is {}",
+                name,
+                self.steps.len(),
+                MAX_STEPS_PER_MACRO
+            );
+        }
+        plan_macro_steps(&self.to_macro_steps()?)?;
+        Ok(())
+    }
+}
+
+/// Finds a macro by id, or by case-insensitive name.
+pub fn find_keyboard_macro(macros: &[KeyboardMacro], name_or_id: &str) -> Option<usize> {
+    macros.iter().position(|m| m.id == name_or_id).or_else(|| {
+        macros
+            .iter()
+            .position(|m| m.name.eq_ignore_ascii_case(name_or_id.trim()))
+    })
+}
+
+/// Validates a macro list, fills in missing ids and renumbers `sort_order`
+/// to follow the list order.
+pub fn normalize_keyboard_macros(macros: &mut [KeyboardMacro]) -> AnyResult<()> {
+    if macros.len() > MAX_MACROS {
+        bail!(
+            "{} macros given, the device stores at most {}",
+            macros.len(),
+            MAX_MACROS
+        );
+    }
+    for (idx, keyboard_macro) in macros.iter().enumerate() {
+        keyboard_macro.validate()?;
+        if macros[..idx].iter().any(|m| {
+            m.name
+                .trim()
+                .eq_ignore_ascii_case(keyboard_macro.name.trim())
+        }) {
+            bail!("Duplicate macro name '{}'", keyboard_macro.name.trim());
+        }
+    }
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    for (idx, keyboard_macro) in macros.iter_mut().enumerate() {
+        keyboard_macro.name = keyboard_macro.name.trim().to_string();
+        if keyboard_macro.id.is_empty() {
+            keyboard_macro.id = format!("macro-{:x}-{}", seed, idx);
+        }
+        keyboard_macro.sort_order = idx as u32 + 1;
+    }
+    Ok(())
+}
+
+/// Moves the named macros to the front in the given order; the rest keep
+/// their relative order after them.
+pub fn reorder_keyboard_macro_list(
+    mut macros: Vec<KeyboardMacro>,
+    order: &[String],
+) -> AnyResult<Vec<KeyboardMacro>> {
+    let mut reordered = Vec::with_capacity(macros.len());
+    for name_or_id in order {
+        let idx = find_keyboard_macro(&macros, name_or_id)
+            .ok_or_else(|| anyhow!("Macro '{}' not found", name_or_id))?;
+        reordered.push(macros.remove(idx));
+    }
+    reordered.extend(macros);
+    Ok(reordered)
+}
+
+/// Merges imported macros into an existing list, replacing macros with the
+/// same name and appending the rest.
+pub fn merge_keyboard_macros(
+    mut existing: Vec<KeyboardMacro>,
+    imported: Vec<KeyboardMacro>,
+) -> Vec<KeyboardMacro> {
+    for mut keyboard_macro in imported {
+        match find_keyboard_macro(&existing, &keyboard_macro.name) {
+            Some(idx) => {
+                keyboard_macro.id = existing[idx].id.clone();
+                existing[idx] = keyboard_macro;
+            }
+            None => {
+                keyboard_macro.id.clear();
+                existing.push(keyboard_macro);
+            }
+        }
+    }
+    existing
+}
+
+pub async fn rpc_get_keyboard_macros(client: &JetKvmRpcClient) -> AnyResult<Value> {
+    client.send_rpc("getKeyboardMacros", json!({})).await
+}
+
+pub async fn rpc_set_keyboard_macros(client: &JetKvmRpcClient, macros: Value) -> AnyResult<Value> {
+    let params = json!({ "params": { "macros": macros } });
+    client.send_rpc("setKeyboardMacros", params).await
+}
+
+/// Fetches the stored macros, sorted by `sort_order`.
+pub async fn get_keyboard_macros(client: &JetKvmRpcClient) -> AnyResult<Vec<KeyboardMacro>> {
+    let mut macros: Vec<KeyboardMacro> = client
+        .call::<Option<_>>("getKeyboardMacros", json!({}))
+        .await?
+        .unwrap_or_default();
+    macros.sort_by_key(|m| m.sort_order);
+    Ok(macros)
+}
+
+/// Validates and replaces the full macro list on the device.
+pub async fn set_keyboard_macros(
+    client: &JetKvmRpcClient,
+    mut macros: Vec<KeyboardMacro>,
+) -> AnyResult<Vec<KeyboardMacro>> {
+    normalize_keyboard_macros(&mut macros)?;
+    let res = rpc_set_keyboard_macros(client, serde_json::to_value(&macros)?).await?;
+    rpc_result("setKeyboardMacros", res)?;
+    Ok(macros)
+}
+
+/// Appends a new macro; fails if a macro with the same name exists.
+pub async fn add_keyboard_macro(
+    client: &JetKvmRpcClient,
+    keyboard_macro: KeyboardMacro,
+) -> AnyResult<Vec<KeyboardMacro>> {
+    let mut macros = get_keyboard_macros(client).await?;
+    if find_keyboard_macro(&macros, &keyboard_macro.name).is_some() {
+        bail!("Macro '{}' already exists", keyboard_macro.name.trim());
+    }
+    macros.push(KeyboardMacro {
+        id: String::new(),
+        ..keyboard_macro
+    });
+    set_keyboard_macros(client, macros).await
+}
+
+/// Replaces the steps (and optionally the name) of an existing macro.
+pub async fn update_keyboard_macro(
+    client: &JetKvmRpcClient,
+    name_or_id: &str,
+    new_name: Option<String>,
+    steps: Vec<KeyboardMacroStep>,
+) -> AnyResult<Vec<KeyboardMacro>> {
+    let mut macros = get_keyboard_macros(client).await?;
+    let idx = find_keyboard_macro(&macros, name_or_id)
+        .ok_or_else(|| anyhow!("Macro '{}' not found", name_or_id))?;
+    if let Some(name) = new_name {
+        macros[idx].name = name;
+    }
+    macros[idx].steps = steps;
+    set_keyboard_macros(client, macros).await
+}
+
+pub async fn delete_keyboard_macro(
+    client: &JetKvmRpcClient,
+    name_or_id: &str,
+) -> AnyResult<Vec<KeyboardMacro>> {
+    let mut macros = get_keyboard_macros(client).await?;
+    let idx = find_keyboard_macro(&macros, name_or_id)
+        .ok_or_else(|| anyhow!("Macro '{}' not found", name_or_id))?;
+    macros.remove(idx);
+    set_keyboard_macros(client, macros).await
+}
+
+pub async fn reorder_keyboard_macros(
+    client: &JetKvmRpcClient,
+    order: &[String],
+) -> AnyResult<Vec<KeyboardMacro>> {
+    let macros = get_keyboard_macros(client).await?;
+    set_keyboard_macros(client, reorder_keyboard_macro_list(macros, order)?).await
+}
+
+/// Returns the stored macros as pretty-printed JSON.
+pub async fn export_keyboard_macros(client: &JetKvmRpcClient) -> AnyResult<String> {
+    let macros = get_keyboard_macros(client).await?;
+    Ok(serde_json::to_string_pretty(&macros)?)
+}
+
+/// Imports macros from JSON. With `replace` the device list is overwritten,
+/// otherwise macros are merged by name.
+pub async fn import_keyboard_macros(
+    client: &JetKvmRpcClient,
+    json_text: &str,
+    replace: bool,
+) -> AnyResult<Vec<KeyboardMacro>> {
+    let imported: Vec<KeyboardMacro> = serde_json::from_str(json_text)
+        .map_err(|e| anyhow!("Invalid keyboard macros JSON: {}", e))?;
+    let macros = if replace {
+        imported
+    } else {
+        merge_keyboard_macros(get_keyboard_macros(client).await?, imported)
+    };
+    set_keyboard_macros(client, macros).await
+}
+
+/// Runs a stored macro through the keyboard report path.
+pub async fn run_keyboard_macro(client: &JetKvmRpcClient, name_or_id: &str) -> AnyResult<()> {
+    let macros = get_keyboard_macros(client).await?;
+    let idx = find_keyboard_macro(&macros, name_or_id)
+        .ok_or_else(|| anyhow!("Macro '{}' not found", name_or_id))?;
+    send_macro_steps(client, macros[idx].to_macro_steps()?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(keys: &[&str], modifiers: &[&str], delay: u64) -> KeyboardMacroStep {
+        KeyboardMacroStep {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            modifiers: modifiers.iter().map(|m| m.to_string()).collect(),
+            delay,
+        }
+    }
+
+    fn names(macros: &[KeyboardMacro]) -> Vec<&str> {
+        macros.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_device_json_round_trip() {
+        let json = r#"[{"id":"a1","name":"Login","steps":[{"keys":["KeyA"],"modifiers":["ControlLeft"],"delay":100},{"keys":["Enter"],"modifiers":[]}],"sortOrder":1}]"#;
+        let macros: Vec<KeyboardMacro> = serde_json::from_str(json).unwrap();
+        assert_eq!(macros[0].sort_order, 1);
+        assert_eq!(macros[0].steps[1].delay, DEFAULT_STEP_DELAY_MS);
+
+        let value = serde_json::to_value(&macros).unwrap();
+        assert_eq!(value[0]["sortOrder"], 1);
+        assert_eq!(value[0]["steps"][0]["modifiers"][0], "ControlLeft");
+    }
+
+    #[test]
+    fn test_to_macro_steps() {
+        let keyboard_macro = KeyboardMacro::new(
+            "Select all",
+            vec![
+                step(&["KeyA"], &["ControlLeft"], 80),
+                step(&["Enter"], &[], 20),
+            ],
+        );
+        let steps = keyboard_macro.to_macro_steps().unwrap();
+        assert_eq!(steps[0].modifier, 0x01);
+        assert_eq!(steps[0].keys, vec![0x04]);
+        assert_eq!(steps[0].delay_ms, 80);
+        assert_eq!(steps[1].keys, vec![0x28]);
+
+        let bad = KeyboardMacro::new("Bad", vec![step(&["NotAKey"], &[], 20)]);
+        assert!(bad.to_macro_steps().is_err());
+    }
+
+    #[test]
+    fn test_validation_limits() {
+        assert!(KeyboardMacro::new("  ", vec![step(&["KeyA"], &[], 20)])
+            .validate()
+            .is_err());
+        assert!(KeyboardMacro::new("Empty", vec![]).validate().is_err());
+        let too_many = vec![step(&["KeyA"], &[], 20); MAX_STEPS_PER_MACRO + 1];
+        assert!(KeyboardMacro::new("Long", too_many).validate().is_err());
+        let seven_keys = step(&["a", "b", "c", "d", "e", "f", "g"], &[], 20);
+        assert!(KeyboardMacro::new("Wide", vec![seven_keys])
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_normalize_assigns_ids_and_order() {
+        let mut macros = vec![
+            KeyboardMacro::new("One", vec![step(&["KeyA"], &[], 20)]),
+            KeyboardMacro::new("Two", vec![step(&["KeyB"], &[], 20)]),
+        ];
+        normalize_keyboard_macros(&mut macros).unwrap();
+        assert!(macros.iter().all(|m| !m.id.is_empty()));
+        assert_ne!(macros[0].id, macros[1].id);
+        assert_eq!(macros[1].sort_order, 2);
+
+        macros.push(KeyboardMacro::new("one", vec![step(&["KeyC"], &[], 20)]));
+        assert!(normalize_keyboard_macros(&mut macros).is_err());
+    }
+
+    #[test]
+    fn test_reorder_and_merge() {
+        let macros: Vec<KeyboardMacro> = ["A", "B", "C"]
+            .iter()
+            .map(|n| KeyboardMacro::new(*n, vec![step(&["KeyA"], &[], 20)]))
+            .collect();
+        let reordered =
+            reorder_keyboard_macro_list(macros.clone(), &["c".to_string(), "A".to_string()])
+                .unwrap();
+        assert_eq!(names(&reordered), vec!["C", "A", "B"]);
+        assert!(reorder_keyboard_macro_list(macros.clone(), &["Z".to_string()]).is_err());
+
+        let imported = vec![
+            KeyboardMacro::new("b", vec![step(&["KeyB"], &[], 20)]),
+            KeyboardMacro::new("D", vec![step(&["KeyD"], &[], 20)]),
+        ];
+        let merged = merge_keyboard_macros(macros, imported);
+        assert_eq!(names(&merged), vec!["A", "b", "C", "D"]);
+        assert_eq!(merged[1].steps[0].keys, vec!["KeyB".to_string()]);
+    }
+}
@@ -0,0 +1,615 @@
+//! Deterministic USB HID boot-keyboard state machine.
+//!
+//! Tracks pressed keys in ordered report slots (first pressed = first slot),
+//! reference-counts each modifier bit independently, and reports
+//! `ErrorRollOver` in every slot while more than six keys are held, as a real
+//! boot-protocol keyboard does. Shared by `send_key_combinations`,
+//! `send_text_with_layout` and the viewer's `KeyboardState` so all of them
+//! emit identical, reproducible report sequences.
+
+use crate::keyboard::KeyCombo;
+use crate::keyboard_layout::KeyboardLayout;
+use crate::keyboard_mappings::{key_name_to_hid, HID_KEY_TO_MODIFIER_MASK, KEYS};
+use crate::text_to_macro::MacroStep;
+use anyhow::{bail, Result};
+use arrayvec::ArrayVec;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Number of key slots in a boot-protocol keyboard report.
+pub const MAX_KEYS: usize = 6;
+
+/// HID usage reported in every slot when too many keys are held.
+pub const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Pause after the all-keys-up report between two macro steps.
+pub const RELEASE_DELAY_MS: u64 = 10;
+
+/// HID usages of the lock keys.
+pub const CAPS_LOCK: u8 = 0x39;
+pub const NUM_LOCK: u8 = 0x53;
+pub const SCROLL_LOCK: u8 = 0x47;
+
+/// ShiftLeft | ShiftRight.
+const SHIFT_MASK: u8 = 0x22;
+
+/// A single `keyboardReport` payload.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct KeyboardReport {
+    pub modifier: u8,
+    pub keys: Vec<u8>,
+}
+
+impl KeyboardReport {
+    pub fn new(modifier: u8, keys: Vec<u8>) -> Self {
+        Self { modifier, keys }
+    }
+}
+
+/// One step of a planned keyboard sequence: send a report, or pause.
+/// Serializes as `{"report": {...}}` or `{"sleep": ms}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStep {
+    Report(KeyboardReport),
+    Sleep(u64),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HidKeyboardState {
+    /// Reference count per modifier bit (bit 0 = ControlLeft … bit 7 = MetaRight).
+    modifier_refs: [u32; 8],
+    /// Reported keys, in press order.
+    slots: ArrayVec<u8, MAX_KEYS>,
+    /// Keys pressed while all slots were taken, in press order.
+    overflow: Vec<u8>,
+}
+
+impl HidKeyboardState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current modifier byte: every bit with a non-zero reference count.
+    pub fn modifier(&self) -> u8 {
+        self.modifier_refs
+            .iter()
+            .enumerate()
+            .filter(|(_, &refs)| refs > 0)
+            .fold(0u8, |acc, (bit, _)| acc | (1 << bit))
+    }
+
+    /// All held non-modifier keys in press order, including overflowed ones.
+    pub fn keys(&self) -> Vec<u8> {
+        self.slots
+            .iter()
+            .chain(self.overflow.iter())
+            .copied()
+            .collect()
+    }
+
+    pub fn is_pressed(&self, hid: u8) -> bool {
+        self.slots.contains(&hid) || self.overflow.contains(&hid)
+    }
+
+    /// True while more than `MAX_KEYS` keys are held.
+    pub fn is_rolled_over(&self) -> bool {
+        !self.overflow.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modifier() == 0 && self.slots.is_empty() && self.overflow.is_empty()
+    }
+
+    /// Adds one reference to each bit set in `mask`.
+    pub fn press_modifiers(&mut self, mask: u8) {
+        for (bit, refs) in self.modifier_refs.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refs += 1;
+            }
+        }
+    }
+
+    /// Drops one reference from each bit set in `mask`. A bit stays reported
+    /// until every press of it has been released.
+    pub fn release_modifiers(&mut self, mask: u8) {
+        for (bit, refs) in self.modifier_refs.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refs = refs.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Presses a key. Modifier usages (0xE0–0xE7) are routed to the
+    /// modifier byte; pressing an already-held key is a no-op.
+    pub fn press_key(&mut self, hid: u8) {
+        if let Some(&mask) = HID_KEY_TO_MODIFIER_MASK.get(&hid) {
+            self.press_modifiers(mask);
+            return;
+        }
+        if self.is_pressed(hid) {
+            return;
+        }
+        if self.slots.try_push(hid).is_err() {
+            self.overflow.push(hid);
+        }
+    }
+
+    /// Releases a key, promoting the oldest overflowed key into the freed slot.
+    pub fn release_key(&mut self, hid: u8) {
+        if let Some(&mask) = HID_KEY_TO_MODIFIER_MASK.get(&hid) {
+            self.release_modifiers(mask);
+            return;
+        }
+        if let Some(idx) = self.slots.iter().position(|&k| k == hid) {
+            self.slots.remove(idx);
+            if !self.overflow.is_empty() {
+                let promoted = self.overflow.remove(0);
+                self.slots.push(promoted);
+            }
+        } else if let Some(idx) = self.overflow.iter().position(|&k| k == hid) {
+            self.overflow.remove(idx);
+        }
+    }
+
+    /// Releases every key and modifier, regardless of reference counts.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// The report a real keyboard would send for the current state.
+    pub fn report(&self) -> KeyboardReport {
+        let keys = if self.is_rolled_over() {
+            vec![ERROR_ROLL_OVER; MAX_KEYS]
+        } else {
+            self.slots.to_vec()
+        };
+        KeyboardReport::new(self.modifier(), keys)
+    }
+}
+
+fn check_rollover(state: &HidKeyboardState) -> Result<()> {
+    if state.is_rolled_over() {
+        bail!(
+            "Too many keys pressed at once ({} > {}): {:02x?}",
+            state.keys().len(),
+            MAX_KEYS,
+            state.keys()
+        );
+    }
+    Ok(())
+}
+
+/// Plans the report sequence for `send_key_combinations`.
+///
+/// Each combo presses its modifier and keys on top of whatever is still held,
+/// then optionally releases them after `hold` ms (unless `hold_keys` /
+/// `hold_modifiers`) and/or immediately (`instant_release`). Modifier bits
+/// are reference counted, so a bit held by an earlier combo survives a later
+/// combo releasing the same bit. `clear_keys` releases everything.
+pub fn plan_key_combinations(key_combos: &[KeyCombo]) -> Result<Vec<ReportStep>> {
+    let mut state = HidKeyboardState::new();
+    let mut held_keys: Vec<u8> = Vec::new();
+    let mut steps = Vec::new();
+
+    for combo in key_combos {
+        if combo.clear_keys.unwrap_or(false) {
+            state.clear();
+            held_keys.clear();
+            steps.push(ReportStep::Report(state.report()));
+            if let Some(wait) = combo.wait {
+                steps.push(ReportStep::Sleep(wait));
+            }
+            continue;
+        }
+
+        state.press_modifiers(combo.modifier);
+        for &key in &combo.keys {
+            state.press_key(key);
+            if combo.hold_keys && !held_keys.contains(&key) {
+                held_keys.push(key);
+            }
+        }
+        check_rollover(&state)?;
+        steps.push(ReportStep::Report(state.report()));
+
+        let mut modifiers_released = false;
+        if let Some(hold) = combo.hold {
+            steps.push(ReportStep::Sleep(hold));
+            if !combo.hold_keys {
+                for key in combo.keys.iter().filter(|k| !held_keys.contains(k)) {
+                    state.release_key(*key);
+                }
+            }
+            if !combo.hold_modifiers {
+                state.release_modifiers(combo.modifier);
+                modifiers_released = true;
+            }
+            steps.push(ReportStep::Report(state.report()));
+        }
+
+        if combo.instant_release.unwrap_or(false) {
+            for key in &combo.keys {
+                state.release_key(*key);
+            }
+            held_keys.retain(|k| !combo.keys.contains(k));
+            if !modifiers_released {
+                state.release_modifiers(combo.modifier);
+            }
+            steps.push(ReportStep::Report(state.report()));
+        }
+
+        if let Some(wait) = combo.wait {
+            steps.push(ReportStep::Sleep(wait));
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Checks that every key code is a known HID usage and that the combos never
+/// exceed six simultaneous keys, returning the planned report sequence.
+pub fn validate_key_combos(key_combos: &[KeyCombo]) -> Result<Vec<ReportStep>> {
+    if key_combos.is_empty() {
+        bail!("No key combinations given");
+    }
+    for (idx, combo) in key_combos.iter().enumerate() {
+        if let Some(key) = combo
+            .keys
+            .iter()
+            .find(|&&key| !KEYS.values().any(|&known| known == key))
+        {
+            bail!("Combo #{}: unknown HID key code {:#04x}", idx, key);
+        }
+    }
+    plan_key_combinations(key_combos)
+}
+
+/// Plans the report sequence for a list of macro steps: each step is pressed
+/// for its `delay_ms`, then everything is released for `RELEASE_DELAY_MS`.
+pub fn plan_macro_steps(macro_steps: &[MacroStep]) -> Result<Vec<ReportStep>> {
+    let mut steps = Vec::new();
+    for step in macro_steps {
+        steps.extend(plan_timed_macro_step(
+            step,
+            step.delay_ms,
+            RELEASE_DELAY_MS,
+        )?);
+    }
+    Ok(steps)
+}
+
+/// Plans a single macro step with explicit press and release times.
+pub fn plan_timed_macro_step(
+    step: &MacroStep,
+    press_ms: u64,
+    release_ms: u64,
+) -> Result<Vec<ReportStep>> {
+    let mut state = HidKeyboardState::new();
+    state.press_modifiers(step.modifier);
+    for &key in &step.keys {
+        state.press_key(key);
+    }
+    check_rollover(&state)?;
+    let pressed = state.report();
+    state.clear();
+    Ok(vec![
+        ReportStep::Report(pressed),
+        ReportStep::Sleep(press_ms),
+        ReportStep::Report(state.report()),
+        ReportStep::Sleep(release_ms),
+    ])
+}
+
+/// Keys the device currently holds down, as returned by `getKeyDownState`.
+/// Empty key slots are reported as zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeysDownState {
+    pub modifier: u8,
+    pub keys: Vec<u8>,
+}
+
+impl KeysDownState {
+    /// Whether the device state matches `report`, ignoring key order and
+    /// empty slots.
+    pub fn matches(&self, report: &KeyboardReport) -> bool {
+        let held: HashSet<u8> = self.keys.iter().copied().filter(|&k| k != 0).collect();
+        let expected: HashSet<u8> = report.keys.iter().copied().filter(|&k| k != 0).collect();
+        self.modifier == report.modifier && held == expected
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches(&KeyboardReport::default())
+    }
+}
+
+/// Keyboard LEDs driven by the target host, as returned by
+/// `getKeyboardLedState` and pushed in `keyboardLedState` notifications.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyboardLedState {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+    pub scroll_lock: bool,
+    pub compose: bool,
+    pub kana: bool,
+}
+
+impl KeyboardLedState {
+    /// Lock keys to tap so the LEDs end up in the requested state.
+    /// `None` leaves that lock as it is.
+    pub fn lock_key_toggles(
+        &self,
+        caps_lock: Option<bool>,
+        num_lock: Option<bool>,
+        scroll_lock: Option<bool>,
+    ) -> Vec<u8> {
+        [
+            (caps_lock, self.caps_lock, CAPS_LOCK),
+            (num_lock, self.num_lock, NUM_LOCK),
+            (scroll_lock, self.scroll_lock, SCROLL_LOCK),
+        ]
+        .into_iter()
+        .filter(|&(wanted, current, _)| wanted.is_some_and(|wanted| wanted != current))
+        .map(|(_, _, key)| key)
+        .collect()
+    }
+}
+
+/// Keys whose unshifted character in `layout` is a lowercase letter, i.e. the
+/// keys Caps Lock turns into their shifted form.
+pub fn caps_lock_keys(layout: &KeyboardLayout) -> HashSet<u8> {
+    layout
+        .chars
+        .iter()
+        .filter(|(c, combo)| c.is_lowercase() && !combo.compose && combo.accents().is_empty())
+        .map(|(_, combo)| combo.base())
+        .filter(|stroke| !stroke.shift && !stroke.alt_right)
+        .filter_map(|stroke| key_name_to_hid(&stroke.key))
+        .collect()
+}
+
+/// Adjusts a typing step for the target's Caps Lock: with Caps Lock on, Shift
+/// is inverted on the letter keys it affects so the typed character stays the
+/// same. Steps using Ctrl, Alt, AltGr or GUI are left untouched.
+pub fn apply_caps_lock(step: &MacroStep, caps_lock: bool, caps_keys: &HashSet<u8>) -> MacroStep {
+    let mut step = step.clone();
+    if caps_lock
+        && step.modifier & !SHIFT_MASK == 0
+        && matches!(step.keys.as_slice(), [key] if caps_keys.contains(key))
+    {
+        step.modifier = if step.modifier & SHIFT_MASK != 0 {
+            0
+        } else {
+            0x02
+        };
+    }
+    step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(modifier: u8, keys: &[u8]) -> ReportStep {
+        ReportStep::Report(KeyboardReport::new(modifier, keys.to_vec()))
+    }
+
+    fn combo(modifier: u8, keys: &[u8]) -> KeyCombo {
+        KeyCombo {
+            modifier,
+            keys: keys.to_vec(),
+            hold_keys: false,
+            hold_modifiers: false,
+            hold: None,
+            wait: None,
+            instant_release: None,
+            clear_keys: None,
+        }
+    }
+
+    #[test]
+    fn test_keys_keep_press_order() {
+        let mut state = HidKeyboardState::new();
+        for key in [0x1b, 0x04, 0x16] {
+            state.press_key(key);
+        }
+        assert_eq!(state.report().keys, vec![0x1b, 0x04, 0x16]);
+        state.release_key(0x04);
+        assert_eq!(state.report().keys, vec![0x1b, 0x16]);
+    }
+
+    #[test]
+    fn test_rollover_reports_error_and_recovers() {
+        let mut state = HidKeyboardState::new();
+        for key in 0x04..=0x0a {
+            state.press_key(key);
+        }
+        assert!(state.is_rolled_over());
+        assert_eq!(state.report().keys, vec![ERROR_ROLL_OVER; MAX_KEYS]);
+
+        state.release_key(0x05);
+        assert!(!state.is_rolled_over());
+        assert_eq!(
+            state.report().keys,
+            vec![0x04, 0x06, 0x07, 0x08, 0x09, 0x0a]
+        );
+    }
+
+    #[test]
+    fn test_modifier_bits_are_reference_counted() {
+        let mut state = HidKeyboardState::new();
+        state.press_modifiers(0x01 | 0x04);
+        state.press_modifiers(0x01);
+        state.release_modifiers(0x01 | 0x04);
+        assert_eq!(state.modifier(), 0x01);
+        state.release_modifiers(0x01);
+        assert_eq!(state.modifier(), 0);
+        state.release_modifiers(0x01);
+        assert_eq!(state.modifier(), 0);
+    }
+
+    #[test]
+    fn test_modifier_usage_codes_map_to_modifier_byte() {
+        let mut state = HidKeyboardState::new();
+        state.press_key(0xe1);
+        state.press_key(0x04);
+        assert_eq!(state.report(), KeyboardReport::new(0x02, vec![0x04]));
+        state.release_key(0xe1);
+        assert_eq!(state.report(), KeyboardReport::new(0, vec![0x04]));
+    }
+
+    #[test]
+    fn test_plan_hold_then_release() {
+        let mut ctrl_c = combo(0x01, &[0x06]);
+        ctrl_c.hold = Some(100);
+        ctrl_c.wait = Some(10);
+        assert_eq!(
+            plan_key_combinations(&[ctrl_c]).unwrap(),
+            vec![
+                report(0x01, &[0x06]),
+                ReportStep::Sleep(100),
+                report(0, &[]),
+                ReportStep::Sleep(10),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_alt_tab_keeps_held_modifier() {
+        let mut alt = combo(0x04, &[]);
+        alt.hold_modifiers = true;
+        let mut tab = combo(0x04, &[0x2b]);
+        tab.hold = Some(50);
+        let mut clear = combo(0, &[]);
+        clear.clear_keys = Some(true);
+
+        assert_eq!(
+            plan_key_combinations(&[alt, tab.clone(), tab, clear]).unwrap(),
+            vec![
+                report(0x04, &[]),
+                report(0x04, &[0x2b]),
+                ReportStep::Sleep(50),
+                report(0x04, &[]),
+                report(0x04, &[0x2b]),
+                ReportStep::Sleep(50),
+                report(0x04, &[]),
+                report(0, &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_instant_release() {
+        let mut first = combo(0x02, &[0x04]);
+        first.hold_keys = true;
+        let mut second = combo(0x01, &[0x05]);
+        second.instant_release = Some(true);
+
+        assert_eq!(
+            plan_key_combinations(&[first, second]).unwrap(),
+            vec![
+                report(0x02, &[0x04]),
+                report(0x03, &[0x04, 0x05]),
+                report(0x02, &[0x04]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_rejects_more_than_six_keys() {
+        let too_many = combo(0, &[0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a]);
+        assert!(plan_key_combinations(&[too_many]).is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_unknown_keys_and_empty_input() {
+        assert!(validate_key_combos(&[]).is_err());
+        assert!(validate_key_combos(&[combo(0, &[0x03])]).is_err());
+        assert!(validate_key_combos(&[combo(0x01, &[0x06])]).is_ok());
+    }
+
+    #[test]
+    fn test_report_steps_serialize_for_dry_run() {
+        let steps = vec![report(0x01, &[0x06]), ReportStep::Sleep(100)];
+        assert_eq!(
+            serde_json::to_string(&steps).unwrap(),
+            r#"[{"report":{"modifier":1,"keys":[6]}},{"sleep":100}]"#
+        );
+    }
+
+    #[test]
+    fn test_keys_down_state_matches_reports() {
+        let idle: KeysDownState =
+            serde_json::from_str(r#"{"modifier":0,"keys":[0,0,0,0,0,0]}"#).unwrap();
+        assert!(idle.is_empty());
+
+        let stuck = KeysDownState {
+            modifier: 0x02,
+            keys: vec![0x05, 0x04, 0, 0, 0, 0],
+        };
+        assert!(!stuck.is_empty());
+        assert!(stuck.matches(&KeyboardReport::new(0x02, vec![0x04, 0x05])));
+        assert!(!stuck.matches(&KeyboardReport::new(0, vec![0x04, 0x05])));
+    }
+
+    #[test]
+    fn test_lock_key_toggles() {
+        let leds = KeyboardLedState {
+            caps_lock: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            leds.lock_key_toggles(Some(true), None, None),
+            Vec::<u8>::new()
+        );
+        assert_eq!(
+            leds.lock_key_toggles(Some(false), Some(true), Some(false)),
+            vec![CAPS_LOCK, NUM_LOCK]
+        );
+
+        let parsed: KeyboardLedState =
+            serde_json::from_str(r#"{"caps_lock":true,"num_lock":false,"shift":false}"#).unwrap();
+        assert_eq!(parsed, leds);
+    }
+
+    #[test]
+    fn test_apply_caps_lock_inverts_shift_on_letters() {
+        let caps_keys = caps_lock_keys(&crate::keyboard_layout::layouts::EN_US);
+        assert!(caps_keys.contains(&0x04));
+        assert!(!caps_keys.contains(&0x1e));
+
+        let lower_a = MacroStep::new(vec![0x04], 0, 20);
+        let upper_a = MacroStep::new(vec![0x04], 0x02, 20);
+        let bang = MacroStep::new(vec![0x1e], 0x02, 20);
+        let ctrl_a = MacroStep::new(vec![0x04], 0x01, 20);
+
+        assert_eq!(apply_caps_lock(&lower_a, true, &caps_keys).modifier, 0x02);
+        assert_eq!(apply_caps_lock(&upper_a, true, &caps_keys).modifier, 0);
+        assert_eq!(apply_caps_lock(&bang, true, &caps_keys).modifier, 0x02);
+        assert_eq!(apply_caps_lock(&ctrl_a, true, &caps_keys).modifier, 0x01);
+        assert_eq!(apply_caps_lock(&lower_a, false, &caps_keys).modifier, 0);
+    }
+
+    #[test]
+    fn test_plan_macro_steps() {
+        let steps = vec![
+            MacroStep::new(vec![0x0b], 0x02, 20),
+            MacroStep::new(vec![0x0c], 0, 20),
+        ];
+        assert_eq!(
+            plan_macro_steps(&steps).unwrap(),
+            vec![
+                report(0x02, &[0x0b]),
+                ReportStep::Sleep(20),
+                report(0, &[]),
+                ReportStep::Sleep(RELEASE_DELAY_MS),
+                report(0, &[0x0c]),
+                ReportStep::Sleep(20),
+                report(0, &[]),
+                ReportStep::Sleep(RELEASE_DELAY_MS),
+            ]
+        );
+    }
+}
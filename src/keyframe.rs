@@ -0,0 +1,170 @@
+//! Asking the device's encoder for a keyframe.
+//!
+//! A decoder can only start on an IDR frame, and the JetKVM encoder sends
+//! one only every few seconds. `KeyframeRequester` sends an RTCP Picture
+//! Loss Indication and a Full Intra Request for the video track so a capture
+//! or recording can start right away, and so a decoder recovers quickly
+//! after packets are lost. Requests after loss are rate-limited, because a
+//! burst of loss would otherwise flood the encoder with them.
+
+use anyhow::{anyhow, Result as AnyResult};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::packet::Packet as RtcpPacket;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::{FirEntry, FullIntraRequest};
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::track::track_remote::TrackRemote;
+
+/// Shortest time between two loss-triggered keyframe requests.
+pub const MIN_LOSS_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Packets missing between two consecutive RTP sequence numbers, allowing
+/// for wrap-around. Reordered or repeated packets count as no loss.
+pub fn sequence_gap(previous: u16, current: u16) -> u64 {
+    let delta = current.wrapping_sub(previous);
+    if delta == 0 || delta >= 0x8000 {
+        0
+    } else {
+        (delta - 1) as u64
+    }
+}
+
+/// Follows RTP sequence numbers and reports how many packets went missing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SequenceTracker {
+    last: Option<u16>,
+}
+
+impl SequenceTracker {
+    /// Whether `sequence` is a duplicate or arrived after a later packet.
+    pub fn is_late(&self, sequence: u16) -> bool {
+        self.last.is_some_and(|previous| {
+            let delta = sequence.wrapping_sub(previous);
+            delta == 0 || delta >= 0x8000
+        })
+    }
+
+    /// Records `sequence` and returns the number of packets lost before it.
+    /// Late packets neither count as loss nor move the tracker back.
+    pub fn observe(&mut self, sequence: u16) -> u64 {
+        let Some(previous) = self.last else {
+            self.last = Some(sequence);
+            return 0;
+        };
+        let delta = sequence.wrapping_sub(previous);
+        if delta != 0 && delta < 0x8000 {
+            self.last = Some(sequence);
+        }
+        sequence_gap(previous, sequence)
+    }
+}
+
+/// Sends keyframe requests for the video track over RTCP. Cheap to clone;
+/// clones share the FIR sequence number and the rate limit.
+#[derive(Clone, Default)]
+pub struct KeyframeRequester {
+    peer_connection: Arc<Mutex<Weak<RTCPeerConnection>>>,
+    fir_sequence: Arc<AtomicU8>,
+    last_loss_request: Arc<Mutex<Option<Instant>>>,
+}
+
+impl KeyframeRequester {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the connection requests are sent on.
+    pub fn set_peer_connection(&self, peer_connection: &Arc<RTCPeerConnection>) {
+        *self.peer_connection.lock().unwrap() = Arc::downgrade(peer_connection);
+    }
+
+    /// Sends a PLI and a FIR for `track`.
+    pub async fn request(&self, track: &TrackRemote) -> AnyResult<()> {
+        let peer_connection = self
+            .peer_connection
+            .lock()
+            .unwrap()
+            .upgrade()
+            .ok_or_else(|| anyhow!("Not connected"))?;
+        let media_ssrc = track.ssrc();
+        let sequence_number = self.fir_sequence.fetch_add(1, Ordering::Relaxed);
+        let packets: Vec<Box<dyn RtcpPacket + Send + Sync>> = vec![
+            Box::new(PictureLossIndication {
+                sender_ssrc: 0,
+                media_ssrc,
+            }),
+            Box::new(FullIntraRequest {
+                sender_ssrc: 0,
+                media_ssrc,
+                fir: vec![FirEntry {
+                    ssrc: media_ssrc,
+                    sequence_number,
+                }],
+            }),
+        ];
+        peer_connection
+            .write_rtcp(&packets)
+            .await
+            .map_err(|e| anyhow!("Failed to send keyframe request: {}", e))?;
+        debug!("Requested keyframe for SSRC {:#x}", media_ssrc);
+        Ok(())
+    }
+
+    /// Like `request`, but at most once per `MIN_LOSS_REQUEST_INTERVAL`.
+    /// Returns whether a request was sent.
+    pub async fn request_after_loss(&self, track: &TrackRemote) -> AnyResult<bool> {
+        if !self.loss_request_due(Instant::now()) {
+            return Ok(false);
+        }
+        self.request(track).await?;
+        Ok(true)
+    }
+
+    fn loss_request_due(&self, now: Instant) -> bool {
+        let mut last = self.last_loss_request.lock().unwrap();
+        if last.is_some_and(|last| now.saturating_duration_since(last) < MIN_LOSS_REQUEST_INTERVAL)
+        {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_gap() {
+        assert_eq!(sequence_gap(10, 11), 0);
+        assert_eq!(sequence_gap(10, 14), 3);
+        assert_eq!(sequence_gap(65535, 1), 1);
+        assert_eq!(sequence_gap(10, 9), 0);
+        assert_eq!(sequence_gap(10, 10), 0);
+    }
+
+    #[test]
+    fn test_sequence_tracker_ignores_late_packets() {
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.observe(100), 0);
+        assert_eq!(tracker.observe(103), 2);
+        assert!(tracker.is_late(102));
+        assert_eq!(tracker.observe(102), 0);
+        assert_eq!(tracker.observe(104), 0);
+        assert_eq!(tracker.observe(65535), 0);
+        assert_eq!(tracker.observe(106), 1);
+    }
+
+    #[test]
+    fn test_loss_requests_are_rate_limited() {
+        let requester = KeyframeRequester::new();
+        let start = Instant::now();
+        assert!(requester.loss_request_due(start));
+        assert!(!requester.loss_request_due(start + Duration::from_millis(100)));
+        assert!(requester.loss_request_due(start + MIN_LOSS_REQUEST_INTERVAL));
+    }
+}
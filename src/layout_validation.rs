@@ -0,0 +1,305 @@
+//! Consistency checks for `KeyboardLayout` tables.
+//!
+//! `validate_layout` reports which characters a layout can type, grouped by
+//! Unicode block, together with anything that would make typing unreliable:
+//! key names missing from `keyboard_mappings::KEYS`, two characters typed by
+//! the same key strokes, accent keys not declared as dead keys, compose
+//! sequences without a compose key, and characters that do not survive an
+//! encode/decode round trip through `text_to_macro` and `report_decoder`.
+
+use crate::keyboard_layout::KeyboardLayout;
+use crate::keyboard_mappings::key_name_to_hid;
+use crate::keyboard_state::{plan_macro_steps, KeyboardReport, ReportStep};
+use crate::report_decoder::reports_to_text;
+use crate::text_to_macro::text_to_macro_steps;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Unicode blocks used to group coverage, as `(first, last, name)`.
+const UNICODE_BLOCKS: &[(u32, u32, &str)] = &[
+    (0x0000, 0x007F, "Basic Latin"),
+    (0x0080, 0x00FF, "Latin-1 Supplement"),
+    (0x0100, 0x017F, "Latin Extended-A"),
+    (0x0180, 0x024F, "Latin Extended-B"),
+    (0x0250, 0x02AF, "IPA Extensions"),
+    (0x02B0, 0x02FF, "Spacing Modifier Letters"),
+    (0x0300, 0x036F, "Combining Diacritical Marks"),
+    (0x0370, 0x03FF, "Greek and Coptic"),
+    (0x0400, 0x04FF, "Cyrillic"),
+    (0x1E00, 0x1EFF, "Latin Extended Additional"),
+    (0x2000, 0x206F, "General Punctuation"),
+    (0x20A0, 0x20CF, "Currency Symbols"),
+    (0x2100, 0x214F, "Letterlike Symbols"),
+];
+
+/// A problem found in a layout.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LayoutIssue {
+    /// `ch` uses a key name that is not in `keyboard_mappings::KEYS`.
+    InvalidKeyName { ch: char, key: String },
+    /// `first` and `second` are typed by the same key strokes.
+    Conflict { first: char, second: char },
+    /// `ch` is composed with an accent key that is not declared a dead key.
+    AccentNotDeadKey { ch: char, key: String },
+    /// `ch` is a compose sequence but the layout has no compose key.
+    MissingComposeKey { ch: char },
+    /// A dead key used by `accented` also types `plain` directly, so the
+    /// target cannot tell the two apart.
+    AccentShadowsChar { accented: char, plain: char },
+    /// Typing `ch` and decoding the reports gives `decoded` instead.
+    RoundTrip { ch: char, decoded: String },
+}
+
+impl fmt::Display for LayoutIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutIssue::InvalidKeyName { ch, key } => {
+                write!(f, "{:?} uses unknown key {:?}", ch, key)
+            }
+            LayoutIssue::Conflict { first, second } => {
+                write!(f, "{:?} and {:?} use the same key strokes", first, second)
+            }
+            LayoutIssue::AccentNotDeadKey { ch, key } => {
+                write!(
+                    f,
+                    "{:?} uses accent key {:?} which is not a dead key",
+                    ch, key
+                )
+            }
+            LayoutIssue::MissingComposeKey { ch } => {
+                write!(f, "{:?} needs a compose key but the layout has none", ch)
+            }
+            LayoutIssue::AccentShadowsChar { accented, plain } => write!(
+                f,
+                "accent key of {:?} also types {:?} directly",
+                accented, plain
+            ),
+            LayoutIssue::RoundTrip { ch, decoded } => {
+                write!(f, "{:?} decodes back as {:?}", ch, decoded)
+            }
+        }
+    }
+}
+
+/// Characters a layout can type within one Unicode block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BlockCoverage {
+    pub block: &'static str,
+    /// Sorted characters of the block the layout can type.
+    pub chars: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LayoutReport {
+    pub iso_code: String,
+    pub name: String,
+    pub char_count: usize,
+    pub coverage: Vec<BlockCoverage>,
+    /// Printable ASCII characters the layout cannot type.
+    pub missing_ascii: String,
+    pub issues: Vec<LayoutIssue>,
+}
+
+impl LayoutReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Name of the Unicode block `c` belongs to, or `"Other"`.
+pub fn unicode_block(c: char) -> &'static str {
+    let code = c as u32;
+    UNICODE_BLOCKS
+        .iter()
+        .find(|(first, last, _)| (*first..=*last).contains(&code))
+        .map(|(_, _, name)| *name)
+        .unwrap_or("Other")
+}
+
+/// Checks `layout` and reports its coverage and issues.
+pub fn validate_layout(layout: &KeyboardLayout) -> LayoutReport {
+    let mut chars: Vec<char> = layout.chars.keys().copied().collect();
+    chars.sort();
+
+    let mut issues = Vec::new();
+    let mut typed_by: HashMap<Vec<KeyboardReport>, char> = HashMap::new();
+    let mut plain_strokes: HashMap<(&str, bool, bool), char> = HashMap::new();
+
+    for &c in &chars {
+        let combo = &layout.chars[&c];
+        if let [stroke] = combo.strokes() {
+            if !combo.compose && !stroke.dead_key {
+                plain_strokes
+                    .entry((stroke.key.as_str(), stroke.shift, stroke.alt_right))
+                    .or_insert(c);
+            }
+        }
+    }
+
+    for &c in &chars {
+        let combo = &layout.chars[&c];
+        let strokes = match layout.strokes_for(c) {
+            Ok(strokes) => strokes,
+            Err(_) => {
+                issues.push(LayoutIssue::MissingComposeKey { ch: c });
+                continue;
+            }
+        };
+        let mut keys_valid = true;
+        for stroke in &strokes {
+            if key_name_to_hid(&stroke.key).is_none() {
+                issues.push(LayoutIssue::InvalidKeyName {
+                    ch: c,
+                    key: stroke.key.clone(),
+                });
+                keys_valid = false;
+            }
+        }
+
+        if !combo.compose {
+            for accent in combo.accents().iter().filter(|accent| !accent.dead_key) {
+                issues.push(LayoutIssue::AccentNotDeadKey {
+                    ch: c,
+                    key: accent.key.clone(),
+                });
+            }
+        }
+        for stroke in combo.strokes().iter().filter(|stroke| stroke.dead_key) {
+            if let Some(&plain) =
+                plain_strokes.get(&(stroke.key.as_str(), stroke.shift, stroke.alt_right))
+            {
+                issues.push(LayoutIssue::AccentShadowsChar { accented: c, plain });
+            }
+        }
+
+        if !keys_valid {
+            continue;
+        }
+        let reports = encode(c, layout);
+        match typed_by.get(&reports) {
+            Some(&first) => issues.push(LayoutIssue::Conflict { first, second: c }),
+            None => {
+                typed_by.insert(reports.clone(), c);
+            }
+        }
+        let decoded = reports_to_text(&reports, layout);
+        if decoded != c.to_string() {
+            issues.push(LayoutIssue::RoundTrip { ch: c, decoded });
+        }
+    }
+
+    let mut blocks: BTreeMap<u32, BlockCoverage> = BTreeMap::new();
+    for &c in &chars {
+        let block = unicode_block(c);
+        let first = UNICODE_BLOCKS
+            .iter()
+            .find(|(_, _, name)| *name == block)
+            .map_or(u32::MAX, |(first, _, _)| *first);
+        blocks
+            .entry(first)
+            .or_insert_with(|| BlockCoverage {
+                block,
+                chars: String::new(),
+            })
+            .chars
+            .push(c);
+    }
+
+    LayoutReport {
+        iso_code: layout.iso_code.clone(),
+        name: layout.name.clone(),
+        char_count: chars.len(),
+        coverage: blocks.into_values().collect(),
+        missing_ascii: (' '..='~')
+            .filter(|c| !layout.chars.contains_key(c))
+            .collect(),
+        issues,
+    }
+}
+
+/// Key reports typed for `c`, without timing.
+fn encode(c: char, layout: &KeyboardLayout) -> Vec<KeyboardReport> {
+    text_to_macro_steps(&c.to_string(), layout, 0)
+        .and_then(|steps| plan_macro_steps(&steps))
+        .map(|plan| {
+            plan.into_iter()
+                .filter_map(|step| match step {
+                    ReportStep::Report(report) => Some(report),
+                    ReportStep::Sleep(_) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::{layouts, KeyCombo, KeyStroke};
+
+    #[test]
+    fn test_built_in_layouts_are_valid() {
+        for layout in layouts::all_layouts() {
+            let report = validate_layout(layout);
+            assert!(
+                report.is_valid(),
+                "layout {}: {:?}",
+                layout.iso_code,
+                report.issues
+            );
+            assert_eq!(report.char_count, layout.chars.len());
+            assert_eq!(report.coverage[0].block, "Basic Latin");
+        }
+        assert_eq!(validate_layout(&layouts::EN_US).missing_ascii, "");
+    }
+
+    #[test]
+    fn test_reports_broken_layout() {
+        let acute = KeyCombo::new("Quote");
+        let compose_e =
+            KeyCombo::compose(vec![KeyStroke::new("Quote"), KeyStroke::new("KeyE")]).unwrap();
+        let layout = KeyboardLayout::new("xx-XX", "Broken")
+            .with_char('a', KeyCombo::new("KeyA"))
+            .with_char('b', KeyCombo::new("KeyA"))
+            .with_char('c', KeyCombo::new("NoSuchKey"))
+            .with_char('\'', KeyCombo::new("Quote"))
+            .with_char('á', KeyCombo::new("KeyA").with_accent_key(acute))
+            .with_char('é', compose_e);
+        let report = validate_layout(&layout);
+        assert!(!report.is_valid());
+        assert!(report.issues.contains(&LayoutIssue::Conflict {
+            first: 'a',
+            second: 'b'
+        }));
+        assert!(report.issues.contains(&LayoutIssue::InvalidKeyName {
+            ch: 'c',
+            key: "NoSuchKey".to_string()
+        }));
+        assert!(report.issues.contains(&LayoutIssue::AccentNotDeadKey {
+            ch: 'á',
+            key: "Quote".to_string()
+        }));
+        assert!(report
+            .issues
+            .contains(&LayoutIssue::MissingComposeKey { ch: 'é' }));
+        assert_eq!(report.coverage.len(), 2);
+        assert_eq!(report.coverage[1].block, "Latin-1 Supplement");
+        assert_eq!(report.coverage[1].chars, "áé");
+
+        let dead_acute = KeyCombo::new("Quote").with_dead_key();
+        let layout = layout
+            .with_char('á', KeyCombo::new("KeyA").with_accent_key(dead_acute))
+            .with_compose_key(KeyStroke::new("ContextMenu"));
+        let report = validate_layout(&layout);
+        assert!(report.issues.contains(&LayoutIssue::AccentShadowsChar {
+            accented: 'á',
+            plain: '\''
+        }));
+        assert!(!report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, LayoutIssue::MissingComposeKey { .. })));
+    }
+}
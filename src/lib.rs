@@ -1,27 +1,48 @@
 pub mod advanced;
 pub mod auth;
+pub mod boot;
 pub mod cloud;
 pub mod device;
 pub mod extension;
+pub mod frame_stream;
 pub mod console;
+pub mod h264;
 pub mod hardware;
 pub mod jetkvm_rpc_client;
 pub mod jiggler;
+pub mod key_sequence;
 pub mod keyboard;
 pub mod keyboard_layout;
 pub mod keyboard_layouts;
+pub mod keyboard_macros;
 pub mod keyboard_mappings;
+pub mod keyboard_state;
+pub mod keyframe;
+pub mod layout_validation;
 pub mod mouse;
+pub mod mouse_path;
 pub mod network;
 pub mod power;
+#[cfg(feature = "gstreamer")]
+pub mod recording;
+pub mod report_decoder;
 pub mod rpc_client;
+pub mod screen_wait;
+pub mod screenshot;
 pub mod signaling;
 pub mod storage;
 pub mod system;
+pub mod template_match;
+pub mod text_screen;
 pub mod text_to_macro;
+pub mod typing_profile;
 pub mod usb;
 pub mod video;
+pub mod vga_font;
 pub mod viewer;
 pub mod wol;
 
 pub use jetkvm_rpc_client::JetKvmRpcClient;
+
+#[cfg(not(any(feature = "gstreamer", feature = "openh264")))]
+compile_error!("enable the `gstreamer` or `openh264` feature to decode video");
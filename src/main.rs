@@ -22,21 +22,40 @@ use jetkvm_client::hardware::{
     rpc_get_backlight_settings, rpc_get_display_rotation, rpc_set_backlight_settings,
     rpc_set_display_rotation,
 };
+use jetkvm_client::boot::{
+    boot_into, parse_resolution, BootOptions, BootPowerAction, BootUntil,
+    DEFAULT_DC_OFF_MS, DEFAULT_PRESS_INTERVAL_MS, DEFAULT_SCREEN_CHANGE_THRESHOLD,
+    DEFAULT_TIMEOUT_MS,
+};
 use jetkvm_client::jiggler::{
-    rpc_get_jiggler_config, rpc_get_jiggler_state, rpc_set_jiggler_config, rpc_set_jiggler_state,
+    device_schedule_zone, rpc_get_jiggler_config, rpc_get_jiggler_state, rpc_set_jiggler_state,
+    run_jiggler_schedule, set_jiggler_config, JigglerConfig, JigglerPreset, JigglerSchedule,
+    JigglerWindow, ScheduleZone, DEFAULT_SCHEDULE_CHECK_SECS,
 };
 use jetkvm_client::jetkvm_rpc_client::{JetKvmRpcClient, SignalingMethod};
 use serde_json::{json, Value};
 use jetkvm_client::keyboard::{
-    rpc_get_key_down_state, rpc_get_keyboard_layout, rpc_get_keyboard_led_state,
-    rpc_keyboard_report, rpc_sendtext, rpc_set_keyboard_layout, send_ctrl_a, send_ctrl_c,
-    send_ctrl_cmd_q, send_ctrl_v, send_ctrl_x, send_key_combinations, send_return,
-    send_text_with_layout, send_windows_key, KeyCombo,
+    load_key_combos, release_all, rpc_get_key_down_state, rpc_get_keyboard_layout,
+    rpc_get_keyboard_led_state, rpc_keyboard_report, rpc_sendtext_with_options,
+    rpc_set_keyboard_layout, send_ctrl_a, send_ctrl_c, send_ctrl_cmd_q, send_ctrl_v, send_ctrl_x,
+    send_key_sequence, send_report_steps, send_return, send_text_with_options, send_windows_key,
+    set_lock_state, LockKeyStrategy, TypingOptions,
+};
+use jetkvm_client::keyboard_macros::{
+    add_keyboard_macro, delete_keyboard_macro, export_keyboard_macros, get_keyboard_macros,
+    import_keyboard_macros, reorder_keyboard_macros, run_keyboard_macro, set_keyboard_macros,
+    update_keyboard_macro, KeyboardMacro, KeyboardMacroStep,
 };
+use jetkvm_client::keyboard_layout::layouts;
+use jetkvm_client::keyboard_state::validate_key_combos;
+use jetkvm_client::layout_validation::validate_layout;
 use jetkvm_client::mouse::{
-    rpc_abs_mouse_report, rpc_double_click, rpc_left_click, rpc_left_click_and_drag_to_center,
-    rpc_middle_click, rpc_move_mouse, rpc_rel_mouse_report, rpc_right_click, rpc_wheel_report,
+    buttons_mask, click_at, double_click_at, drag_path, left_click_and_drag_to_center_at,
+    left_click_at, middle_click_at, mouse_down, mouse_up, move_mouse_at, move_path,
+    right_click_at, rpc_abs_mouse_report, rpc_rel_mouse_report, scroll, CoordinateUnits,
+    MouseButton, MousePosition, DEFAULT_DRAG_DURATION_MS,
 };
+use jetkvm_client::mouse_path::{Easing, PathStyle};
 use jetkvm_client::network::{
     rpc_get_network_settings, rpc_get_network_state, rpc_renew_dhcp_lease,
     rpc_set_network_settings,
@@ -45,6 +64,23 @@ use jetkvm_client::power::{
     rpc_get_atx_state, rpc_get_dc_power_state, rpc_set_atx_power_action, rpc_set_dc_power_state,
     rpc_set_dc_restore_state,
 };
+#[cfg(feature = "gstreamer")]
+use jetkvm_client::recording::{record_for, RecordingFormat, RecordingOptions};
+use jetkvm_client::screen_wait::{
+    wait_for_change, wait_for_stable, DEFAULT_CHANGE_THRESHOLD, DEFAULT_STABLE_FOR_MS,
+    DEFAULT_STABLE_THRESHOLD, DEFAULT_WAIT_TIMEOUT_MS,
+};
+use jetkvm_client::screenshot::{
+    Region, ScreenshotFormat, ScreenshotOptions, DEFAULT_JPEG_QUALITY,
+};
+use jetkvm_client::template_match::{
+    click_image, find_image, load_template, wait_for_image, MatchOptions,
+    DEFAULT_IMAGE_TIMEOUT_MS, DEFAULT_MATCH_THRESHOLD,
+};
+use jetkvm_client::text_screen::{
+    read_screen_text, wait_for_text, TextReader, DEFAULT_TEXT_TIMEOUT_MS,
+};
+use jetkvm_client::vga_font::Font;
 use jetkvm_client::storage::{
     rpc_delete_storage_file, rpc_get_storage_space, rpc_get_virtual_media_state,
     rpc_list_storage_files, rpc_mount_with_http, rpc_mount_with_storage,
@@ -54,12 +90,13 @@ use jetkvm_client::system::{
     rpc_get_auto_update_state, rpc_get_edid, rpc_get_local_version, rpc_get_timezones,
     rpc_get_update_status, rpc_reboot, rpc_set_auto_update_state, rpc_set_edid, rpc_try_update,
 };
+use jetkvm_client::typing_profile::{TypingPreset, TypingProfile};
 use jetkvm_client::usb::{
     rpc_get_usb_config, rpc_get_usb_devices, rpc_get_usb_emulation_state, rpc_set_usb_config,
     rpc_set_usb_devices, rpc_set_usb_emulation_state,
 };
 use jetkvm_client::video::{
-    rpc_get_stream_quality_factor, rpc_get_video_log_status, rpc_get_video_state,
+    rpc_get_stream_quality_factor, FrameFreshness, rpc_get_video_log_status, rpc_get_video_state,
 };
 use jetkvm_client::wol::{
     rpc_get_wake_on_lan_devices, rpc_send_wol_magic_packet, rpc_set_wake_on_lan_devices,
@@ -103,6 +140,174 @@ struct Cli {
     commands: Vec<String>,
 }
 
+/// A pointer position shared by the mouse commands.
+#[derive(clap::Args, Debug)]
+struct PointArgs {
+    x: f64,
+    y: f64,
+    /// Units of X and Y: raw absMouseReport coordinates (0-32767), target-screen pixels, or 0.0-1.0 fractions.
+    #[arg(long, value_enum, default_value_t = CoordinateUnits::Raw)]
+    units: CoordinateUnits,
+}
+
+impl PointArgs {
+    fn position(&self) -> MousePosition {
+        MousePosition::new(self.units, self.x, self.y)
+    }
+}
+
+/// Buttons held by the press and drag commands.
+#[derive(clap::Args, Debug)]
+struct ButtonArgs {
+    /// Button to use (left, right, middle, back, forward); repeat for a chord.
+    #[arg(long = "button", value_enum, default_values_t = [MouseButton::Left])]
+    buttons: Vec<MouseButton>,
+}
+
+impl ButtonArgs {
+    fn mask(&self) -> u64 {
+        buttons_mask(&self.buttons)
+    }
+}
+
+/// How the pointer travels in the path and drag commands.
+#[derive(clap::Args, Debug)]
+struct MotionArgs {
+    /// Shape of the path through the points.
+    #[arg(long, value_enum, default_value_t = PathStyle::Linear)]
+    path: PathStyle,
+    /// Speed profile along the path.
+    #[arg(long, value_enum, default_value_t = Easing::EaseInOut)]
+    easing: Easing,
+    /// Time to travel the whole path, in milliseconds.
+    #[arg(long, default_value_t = DEFAULT_DRAG_DURATION_MS)]
+    duration: u64,
+}
+
+/// Template image and search options for the image commands.
+#[derive(clap::Args, Debug)]
+struct TemplateArgs {
+    /// Image file (PNG, JPEG, ...) to look for.
+    template: String,
+    /// Lowest match score (-1.0-1.0) that counts as found.
+    #[arg(long, default_value_t = DEFAULT_MATCH_THRESHOLD)]
+    threshold: f64,
+    /// Only search this region of the frame, as WIDTHxHEIGHT+X+Y.
+    #[arg(long)]
+    region: Option<Region>,
+    /// Template scales to try, comma separated (e.g. 0.8,1,1.25).
+    #[arg(long, value_delimiter = ',', default_values_t = [1.0])]
+    scale: Vec<f64>,
+}
+
+impl TemplateArgs {
+    fn load(&self) -> AnyResult<(image::RgbaImage, MatchOptions)> {
+        let template = load_template(&self.template)?;
+        let options = MatchOptions {
+            threshold: self.threshold,
+            region: self.region,
+            scales: self.scale.clone(),
+        };
+        Ok((template, options))
+    }
+}
+
+/// Font for the text-mode commands.
+#[derive(clap::Args, Debug)]
+struct FontArgs {
+    /// PSF1/PSF2 console font (.psf or .psf.gz) to read with, e.g. a Terminus font from
+    /// /usr/share/consolefonts; the bundled 8x16 VGA font, the only built-in one, when omitted.
+    #[arg(long)]
+    font: Option<String>,
+}
+
+impl FontArgs {
+    fn load(&self) -> AnyResult<Font> {
+        match &self.font {
+            Some(path) => Font::from_psf_file(path),
+            None => Ok(Font::vga()),
+        }
+    }
+}
+
+/// Parses an `X,Y` point.
+fn parse_point(s: &str) -> Result<(f64, f64), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Invalid point {:?}, expected X,Y", s))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid point {:?}, expected X,Y", s))
+    };
+    Ok((parse(x)?, parse(y)?))
+}
+
+/// Typing options shared by the text commands.
+#[derive(clap::Args, Debug)]
+struct TypingArgs {
+    /// Timing preset [default: standard].
+    #[arg(long, value_enum)]
+    profile: Option<TypingPreset>,
+    /// How long each key is held, in milliseconds (overrides the preset).
+    #[arg(long)]
+    delay: Option<u64>,
+    /// Pause after each key release, in milliseconds (overrides the preset).
+    #[arg(long)]
+    release_delay: Option<u64>,
+    /// Random extra time of up to this many milliseconds per press and release.
+    #[arg(long)]
+    jitter: Option<u64>,
+    /// Number of key presses per burst; 0 disables bursts.
+    #[arg(long)]
+    burst: Option<usize>,
+    /// Pause after each burst, in milliseconds.
+    #[arg(long)]
+    burst_pause: Option<u64>,
+    /// Slow down while the RPC round-trip time rises.
+    #[arg(long, action = ArgAction::Set)]
+    adaptive: Option<bool>,
+    /// How to handle a Caps Lock that is already on at the target.
+    #[arg(long, value_enum, default_value_t = LockKeyStrategy::InvertShift)]
+    lock_keys: LockKeyStrategy,
+    /// Check for stuck keys after every N key presses and release them.
+    #[arg(long)]
+    verify_every: Option<usize>,
+}
+
+impl TypingArgs {
+    fn typing_options(&self) -> TypingOptions {
+        self.typing_options_or(TypingProfile::standard())
+    }
+
+    /// Typing options starting from `default` when no `--profile` is given.
+    fn typing_options_or(&self, default: TypingProfile) -> TypingOptions {
+        let mut profile = self.profile.map_or(default, TypingProfile::from_preset);
+        if let Some(delay) = self.delay {
+            profile = profile.with_press_ms(delay);
+        }
+        if let Some(release_delay) = self.release_delay {
+            profile = profile.with_release_ms(release_delay);
+        }
+        if let Some(jitter) = self.jitter {
+            profile = profile.with_jitter_ms(jitter);
+        }
+        if self.burst.is_some() || self.burst_pause.is_some() {
+            let burst = self.burst.unwrap_or(profile.burst_len);
+            let burst_pause = self.burst_pause.unwrap_or(profile.burst_pause_ms);
+            profile = profile.with_burst(burst, burst_pause);
+        }
+        if let Some(adaptive) = self.adaptive {
+            profile = profile.with_adaptive(adaptive);
+        }
+        TypingOptions {
+            profile,
+            lock_keys: self.lock_keys,
+            verify_every: self.verify_every,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 enum Commands {
     /// Sends a "ping" request.
@@ -126,15 +331,19 @@ enum Commands {
     },
     /// Sends text as a series of keyboard events (US ASCII only).
     #[command(name = "sendtext")]
-    Sendtext { text: String },
+    Sendtext {
+        text: String,
+        #[command(flatten)]
+        typing: TypingArgs,
+    },
     /// Sends text using a specific keyboard layout (supports accents and special characters).
     #[command(name = "send-text-with-layout")]
     SendTextWithLayout {
         text: String,
         #[arg(long, default_value = "en-US")]
         layout: String,
-        #[arg(long, default_value = "20")]
-        delay: u64,
+        #[command(flatten)]
+        typing: TypingArgs,
     },
     /// Sends a Return (Enter) key press.
     #[command(name = "send-return")]
@@ -157,38 +366,245 @@ enum Commands {
     /// Sends a Ctrl-Cmd-Q key press to lock a macOS screen.
     #[command(name = "send-ctrl-cmd-q")]
     SendCtrlCmdQ,
-    /// Sends a sequence of key combinations (JSON format).
+    /// Sends a sequence of key combinations from inline JSON or a JSON/YAML file.
     #[command(name = "send-key-combinations")]
-    SendKeyCombinations { combos: String },
+    SendKeyCombinations {
+        combos: String,
+        /// Validate and print the keyboard reports without sending them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Sends a human-readable key sequence (e.g. `ctrl+alt+Delete {wait 500} "text"`).
+    #[command(name = "keys")]
+    Keys {
+        sequence: String,
+        #[arg(long, default_value = "en-US")]
+        layout: String,
+        #[arg(long, default_value = "20")]
+        delay: u64,
+    },
     /// Sends an absolute mouse report with x, y coordinates and button state.
     #[command(name = "abs-mouse-report")]
     AbsMouseReport { x: i64, y: i64, buttons: u64 },
-    /// Sends a wheel report with the given wheelY value.
+    /// Scrolls by the given wheelY value (positive = up), optionally also horizontally.
     #[command(name = "wheel-report")]
-    WheelReport { wheel_y: i64 },
+    WheelReport {
+        #[arg(allow_negative_numbers = true)]
+        wheel_y: i64,
+        /// Horizontal scroll (positive = right); best effort, as firmware without it may ignore it.
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        wheel_x: i64,
+    },
     /// Moves the mouse to the specified absolute coordinates.
     #[command(name = "move-mouse")]
-    MoveMouse { x: i64, y: i64 },
+    MoveMouse {
+        #[command(flatten)]
+        point: PointArgs,
+    },
     /// Simulates a left mouse click at the specified coordinates.
     #[command(name = "left-click")]
-    LeftClick { x: i64, y: i64 },
+    LeftClick {
+        #[command(flatten)]
+        point: PointArgs,
+    },
     /// Simulates a right mouse click at the specified coordinates.
     #[command(name = "right-click")]
-    RightClick { x: i64, y: i64 },
+    RightClick {
+        #[command(flatten)]
+        point: PointArgs,
+    },
     /// Simulates a middle mouse click at the specified coordinates.
     #[command(name = "middle-click")]
-    MiddleClick { x: i64, y: i64 },
+    MiddleClick {
+        #[command(flatten)]
+        point: PointArgs,
+    },
+    /// Clicks any mouse button or chord (including back/forward) at the specified coordinates.
+    #[command(name = "click")]
+    Click {
+        #[command(flatten)]
+        point: PointArgs,
+        #[command(flatten)]
+        buttons: ButtonArgs,
+    },
     /// Simulates a double left click at the specified coordinates.
     #[command(name = "double-click")]
-    DoubleClick { x: i64, y: i64 },
+    DoubleClick {
+        #[command(flatten)]
+        point: PointArgs,
+    },
     /// Clicks and drags from a position to center.
     #[command(name = "left-click-and-drag-to-center")]
-    LeftClickAndDragToCenter { start_x: i64, start_y: i64 },
-    /// Captures a screenshot as PNG (returns base64 encoded data URL).
+    LeftClickAndDragToCenter {
+        #[command(flatten)]
+        start: PointArgs,
+    },
+    /// Presses buttons at a position and drags them to another, optionally via more points.
+    #[command(name = "drag")]
+    Drag {
+        from_x: f64,
+        from_y: f64,
+        to_x: f64,
+        to_y: f64,
+        /// Intermediate point as X,Y; repeat for several.
+        #[arg(long, value_parser = parse_point)]
+        via: Vec<(f64, f64)>,
+        /// Units of the points, as for move-mouse.
+        #[arg(long, value_enum, default_value_t = CoordinateUnits::Raw)]
+        units: CoordinateUnits,
+        #[command(flatten)]
+        buttons: ButtonArgs,
+        #[command(flatten)]
+        motion: MotionArgs,
+    },
+    /// Moves the mouse through X,Y points without pressing buttons, starting at the first.
+    #[command(name = "move-path")]
+    MovePath {
+        #[arg(required = true, num_args = 2.., value_parser = parse_point)]
+        points: Vec<(f64, f64)>,
+        /// Units of the points, as for move-mouse.
+        #[arg(long, value_enum, default_value_t = CoordinateUnits::Raw)]
+        units: CoordinateUnits,
+        #[command(flatten)]
+        motion: MotionArgs,
+    },
+    /// Presses and holds mouse buttons at a position.
+    #[command(name = "mouse-down")]
+    MouseDown {
+        #[command(flatten)]
+        point: PointArgs,
+        #[command(flatten)]
+        buttons: ButtonArgs,
+    },
+    /// Releases all mouse buttons at a position.
+    #[command(name = "mouse-up")]
+    MouseUp {
+        #[command(flatten)]
+        point: PointArgs,
+    },
+    /// Captures a screenshot as PNG, JPEG, WebP, PPM or raw RGBA, optionally cropped,
+    /// scaled or grayscale (returns the image as a base64 data URL unless --file-only).
     #[command(name = "screenshot")]
     Screenshot {
+        /// Path to save the image to.
         #[arg(long)]
         output: Option<String>,
+        /// Only accept a frame decoded within this many milliseconds.
+        #[arg(long, conflicts_with = "fresh")]
+        max_age: Option<u64>,
+        /// Wait for a frame decoded after the request.
+        #[arg(long)]
+        fresh: bool,
+        /// Image format (default: from the output extension, else png).
+        #[arg(long, value_enum)]
+        format: Option<ScreenshotFormat>,
+        /// JPEG quality, 1-100.
+        #[arg(long, default_value_t = DEFAULT_JPEG_QUALITY)]
+        quality: u8,
+        /// Only capture this region of the frame, as WIDTHxHEIGHT+X+Y.
+        #[arg(long)]
+        crop: Option<Region>,
+        /// Shrink to fit within WIDTHxHEIGHT, keeping the aspect ratio.
+        #[arg(long, value_parser = parse_resolution)]
+        max_size: Option<(u32, u32)>,
+        /// Convert to grayscale.
+        #[arg(long)]
+        grayscale: bool,
+        /// Write the image to --output and leave the data out of the result.
+        #[arg(long, requires = "output")]
+        file_only: bool,
+    },
+    /// Records the video stream to MP4 or Matroska without re-encoding.
+    #[cfg(feature = "gstreamer")]
+    #[command(name = "record")]
+    Record {
+        /// Output file; later segments get a -001, -002, ... suffix.
+        #[arg(long)]
+        output: String,
+        /// How long to record, in seconds.
+        #[arg(long)]
+        duration: u64,
+        /// Container format (default: from the output extension, else mp4).
+        #[arg(long, value_enum)]
+        format: Option<RecordingFormat>,
+        /// Start a new file every this many seconds.
+        #[arg(long)]
+        segment: Option<u64>,
+    },
+    /// Waits until the screen (or a region of it) changes.
+    #[command(name = "wait-change")]
+    WaitChange {
+        /// Only watch this region of the frame, as WIDTHxHEIGHT+X+Y.
+        #[arg(long)]
+        region: Option<Region>,
+        /// Mean pixel difference (0.0-1.0) from the first frame that counts as a change.
+        #[arg(long, default_value_t = DEFAULT_CHANGE_THRESHOLD)]
+        threshold: f64,
+        /// Milliseconds before giving up.
+        #[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT_MS)]
+        timeout: u64,
+    },
+    /// Waits until the screen (or a region of it) stops changing.
+    #[command(name = "wait-stable")]
+    WaitStable {
+        /// Only watch this region of the frame, as WIDTHxHEIGHT+X+Y.
+        #[arg(long)]
+        region: Option<Region>,
+        /// Largest mean pixel difference (0.0-1.0) still counted as unchanged.
+        #[arg(long, default_value_t = DEFAULT_STABLE_THRESHOLD)]
+        threshold: f64,
+        /// Milliseconds the screen has to stay unchanged.
+        #[arg(long, default_value_t = DEFAULT_STABLE_FOR_MS)]
+        stable_for: u64,
+        /// Milliseconds before giving up.
+        #[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT_MS)]
+        timeout: u64,
+    },
+    /// Finds a template image in the current frame.
+    #[command(name = "find-image")]
+    FindImage {
+        #[command(flatten)]
+        template: TemplateArgs,
+    },
+    /// Waits until a template image appears on screen.
+    #[command(name = "wait-image")]
+    WaitImage {
+        #[command(flatten)]
+        template: TemplateArgs,
+        /// Milliseconds before giving up.
+        #[arg(long, default_value_t = DEFAULT_IMAGE_TIMEOUT_MS)]
+        timeout: u64,
+    },
+    /// Waits until a template image appears on screen and clicks its centre.
+    #[command(name = "click-image")]
+    ClickImage {
+        #[command(flatten)]
+        template: TemplateArgs,
+        /// Milliseconds before giving up.
+        #[arg(long, default_value_t = DEFAULT_IMAGE_TIMEOUT_MS)]
+        timeout: u64,
+        #[command(flatten)]
+        buttons: ButtonArgs,
+    },
+    /// Reads the text of a text-mode screen (BIOS setup, boot loader, console).
+    #[command(name = "read-text")]
+    ReadText {
+        #[command(flatten)]
+        font: FontArgs,
+        /// Also report the foreground and background colour of every cell.
+        #[arg(long)]
+        attributes: bool,
+    },
+    /// Waits until a text-mode screen shows the given text.
+    #[command(name = "wait-text")]
+    WaitText {
+        /// Text to look for within one line.
+        text: String,
+        #[command(flatten)]
+        font: FontArgs,
+        /// Milliseconds before giving up.
+        #[arg(long, default_value_t = DEFAULT_TEXT_TIMEOUT_MS)]
+        timeout: u64,
     },
     /// Waits for the specified number of milliseconds.
     #[command(name = "wait")]
@@ -199,12 +615,80 @@ enum Commands {
     /// Sets the keyboard layout.
     #[command(name = "set-keyboard-layout")]
     SetKeyboardLayout { layout: String },
+    /// Checks built-in keyboard layouts for conflicts and invalid keys, and reports their coverage.
+    #[command(name = "validate-keyboard-layout")]
+    ValidateKeyboardLayout {
+        /// Layout to check (e.g. es-ES); all built-in layouts when omitted.
+        layout: Option<String>,
+    },
     /// Gets the keyboard LED state (Caps/Num Lock).
     #[command(name = "get-keyboard-led-state")]
     GetKeyboardLedState,
+    /// Sets Caps Lock, Num Lock and Scroll Lock by tapping the lock keys.
+    #[command(name = "set-lock-state")]
+    SetLockState {
+        #[arg(long, action = ArgAction::Set)]
+        caps: Option<bool>,
+        #[arg(long, action = ArgAction::Set)]
+        num: Option<bool>,
+        #[arg(long, action = ArgAction::Set)]
+        scroll: Option<bool>,
+    },
     /// Gets the currently pressed keys.
     #[command(name = "get-key-down-state")]
     GetKeyDownState,
+    /// Releases all keys, modifiers and mouse buttons on the device.
+    #[command(name = "release-all")]
+    ReleaseAll {
+        /// Release absolute-mouse buttons at X,Y instead of the last known position (or 0,0).
+        #[arg(long, requires = "y")]
+        x: Option<i64>,
+        #[arg(long, requires = "x")]
+        y: Option<i64>,
+    },
+    /// Lists the keyboard macros stored on the device.
+    #[command(name = "get-keyboard-macros")]
+    GetKeyboardMacros,
+    /// Replaces all stored keyboard macros (JSON array).
+    #[command(name = "set-keyboard-macros")]
+    SetKeyboardMacros { macros: String },
+    /// Adds a keyboard macro from a JSON array of steps.
+    #[command(name = "add-keyboard-macro")]
+    AddKeyboardMacro { name: String, steps: String },
+    /// Replaces the steps of a keyboard macro, optionally renaming it.
+    #[command(name = "update-keyboard-macro")]
+    UpdateKeyboardMacro {
+        name: String,
+        steps: String,
+        #[arg(long)]
+        rename: Option<String>,
+    },
+    /// Deletes a keyboard macro by name or id.
+    #[command(name = "delete-keyboard-macro")]
+    DeleteKeyboardMacro { name: String },
+    /// Moves the given macros to the front, in order.
+    #[command(name = "reorder-keyboard-macros")]
+    ReorderKeyboardMacros {
+        #[arg(required = true, num_args = 1..)]
+        names: Vec<String>,
+    },
+    /// Exports the stored keyboard macros as JSON.
+    #[command(name = "export-keyboard-macros")]
+    ExportKeyboardMacros {
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Imports keyboard macros from a JSON file, merging by name.
+    #[command(name = "import-keyboard-macros")]
+    ImportKeyboardMacros {
+        file: String,
+        /// Replace all stored macros instead of merging.
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Runs a stored keyboard macro by name or id.
+    #[command(name = "run-keyboard-macro")]
+    RunKeyboardMacro { name: String },
     /// Sends a relative mouse report with dx, dy and button state.
     #[command(name = "rel-mouse-report")]
     RelMouseReport { dx: i64, dy: i64, buttons: u64 },
@@ -262,6 +746,33 @@ enum Commands {
     /// Sets DC restore state.
     #[command(name = "set-dc-restore-state")]
     SetDcRestoreState { state: u64 },
+    /// Powers the target on and keeps pressing a key until the BIOS or boot menu appears.
+    #[command(name = "boot-into")]
+    BootInto {
+        /// Key to press, in key sequence syntax (e.g. F2, F12, Delete, Escape).
+        key: String,
+        /// Power action that starts the boot.
+        #[arg(long, value_enum, default_value_t = BootPowerAction::AtxPower)]
+        power: BootPowerAction,
+        /// Milliseconds between key presses.
+        #[arg(long, default_value_t = DEFAULT_PRESS_INTERVAL_MS)]
+        interval: u64,
+        /// Milliseconds before giving up.
+        #[arg(long, default_value_t = DEFAULT_TIMEOUT_MS)]
+        timeout: u64,
+        /// Stop when the video input reaches this resolution (e.g. 1024x768) instead of on a screen change.
+        #[arg(long, conflicts_with = "no_wait")]
+        until_resolution: Option<String>,
+        /// Mean pixel difference (0.0-1.0) from the first frame that counts as a screen change.
+        #[arg(long, default_value_t = DEFAULT_SCREEN_CHANGE_THRESHOLD)]
+        threshold: f64,
+        /// Keep pressing the key until the timeout without watching the screen.
+        #[arg(long)]
+        no_wait: bool,
+        /// Milliseconds DC power stays off with --power dc-cycle.
+        #[arg(long, default_value_t = DEFAULT_DC_OFF_MS)]
+        dc_off: u64,
+    },
     /// Gets USB configuration.
     #[command(name = "get-usb-config")]
     GetUsbConfig,
@@ -322,9 +833,36 @@ enum Commands {
     /// Gets the mouse jiggler configuration.
     #[command(name = "get-jiggler-config")]
     GetJigglerConfig,
-    /// Sets the mouse jiggler configuration.
+    /// Sets the mouse jiggler configuration from JSON, after validating it.
     #[command(name = "set-jiggler-config")]
     SetJigglerConfig { config: String },
+    /// Sets the mouse jiggler configuration to one of the web UI presets.
+    #[command(name = "set-jiggler-preset")]
+    SetJigglerPreset {
+        #[arg(value_enum)]
+        preset: JigglerPreset,
+        /// IANA timezone for the jiggler schedule (see get-timezones).
+        #[arg(long)]
+        timezone: Option<String>,
+    },
+    /// Enables the jiggler inside the given weekly time windows and disables it outside them.
+    #[command(name = "jiggler-schedule")]
+    JigglerSchedule {
+        /// Time window such as "Mon-Fri 09:00-17:30" or "22:00-06:00"; repeat for several.
+        #[arg(long = "window", required = true)]
+        windows: Vec<String>,
+        /// Timezone the windows are written in: an IANA name such as Europe/Madrid (daylight
+        /// saving is followed) or a fixed UTC offset such as +02:00. Defaults to the timezone
+        /// in the device's jiggler config, or UTC when it has none.
+        #[arg(long, alias = "utc-offset", allow_hyphen_values = true)]
+        timezone: Option<ScheduleZone>,
+        /// Seconds between schedule checks.
+        #[arg(long, default_value_t = DEFAULT_SCHEDULE_CHECK_SECS)]
+        check_interval: u64,
+        /// Stop after this many seconds; runs until interrupted when omitted.
+        #[arg(long)]
+        duration: Option<u64>,
+    },
     /// Gets the video stream state.
     #[command(name = "get-video-state")]
     GetVideoState,
@@ -511,14 +1049,17 @@ async fn main() -> AnyResult<()> {
                     .await
                     .map(|_| json!({ "status": "ok" }))
             }
-            Commands::Sendtext { text } => rpc_sendtext(&client, &text)
-                .await
-                .map(|_| json!({ "status": "ok" })),
+            Commands::Sendtext { text, typing } => {
+                let options = typing.typing_options_or(TypingProfile::sendtext());
+                rpc_sendtext_with_options(&client, &text, &options)
+                    .await
+                    .map(|_| json!({ "status": "ok" }))
+            }
             Commands::SendTextWithLayout {
                 text,
                 layout,
-                delay,
-            } => send_text_with_layout(&client, &text, &layout, delay)
+                typing,
+            } => send_text_with_options(&client, &text, &layout, &typing.typing_options())
                 .await
                 .map(|_| json!({ "status": "ok" })),
             Commands::SendReturn => send_return(&client)
@@ -542,68 +1083,313 @@ async fn main() -> AnyResult<()> {
             Commands::SendCtrlCmdQ => send_ctrl_cmd_q(&client)
                 .await
                 .map(|_| json!({ "status": "ok" })),
-            Commands::SendKeyCombinations { combos } => {
-                let combos_vec: Vec<KeyCombo> = serde_json::from_str(&combos)?;
-                send_key_combinations(&client, combos_vec)
-                    .await
-                    .map(|_| json!({ "status": "ok" }))
-                    .map_err(|e| anyhow::anyhow!("{}", e))
+            Commands::SendKeyCombinations { combos, dry_run } => {
+                match load_key_combos(&combos).and_then(|c| validate_key_combos(&c)) {
+                    Ok(steps) if dry_run => Ok(json!({
+                        "status": "ok",
+                        "dry_run": true,
+                        "steps": steps
+                    })),
+                    Ok(steps) => send_report_steps(&client, &steps)
+                        .await
+                        .map(|_| json!({ "status": "ok" })),
+                    Err(e) => Err(e),
+                }
             }
+            Commands::Keys {
+                sequence,
+                layout,
+                delay,
+            } => send_key_sequence(&client, &sequence, &layout, delay)
+                .await
+                .map(|_| json!({ "status": "ok" })),
             Commands::AbsMouseReport { x, y, buttons } => {
                 rpc_abs_mouse_report(&client, x, y, buttons)
                     .await
                     .map(|_| json!({ "status": "ok" }))
             }
-            Commands::WheelReport { wheel_y } => rpc_wheel_report(&client, wheel_y)
+            Commands::WheelReport { wheel_y, wheel_x } => scroll(&client, wheel_y, wheel_x)
                 .await
-                .map(|_| json!({ "status": "ok" })),
-            Commands::MoveMouse { x, y } => rpc_move_mouse(&client, x, y)
+                .map(|horizontal| json!({ "status": "ok", "horizontal": horizontal })),
+            Commands::MoveMouse { point } => move_mouse_at(&client, point.position())
                 .await
                 .map(|_| json!({ "status": "ok" })),
-            Commands::LeftClick { x, y } => rpc_left_click(&client, x, y)
+            Commands::LeftClick { point } => left_click_at(&client, point.position())
                 .await
                 .map(|_| json!({ "status": "ok" })),
-            Commands::RightClick { x, y } => rpc_right_click(&client, x, y)
+            Commands::RightClick { point } => right_click_at(&client, point.position())
                 .await
                 .map(|_| json!({ "status": "ok" })),
-            Commands::MiddleClick { x, y } => rpc_middle_click(&client, x, y)
+            Commands::MiddleClick { point } => middle_click_at(&client, point.position())
                 .await
                 .map(|_| json!({ "status": "ok" })),
-            Commands::DoubleClick { x, y } => rpc_double_click(&client, x, y)
+            Commands::Click { point, buttons } => {
+                click_at(&client, point.position(), buttons.mask())
+                    .await
+                    .map(|_| json!({ "status": "ok" }))
+            }
+            Commands::DoubleClick { point } => double_click_at(&client, point.position())
                 .await
                 .map(|_| json!({ "status": "ok" })),
-            Commands::LeftClickAndDragToCenter { start_x, start_y } => {
-                rpc_left_click_and_drag_to_center(&client, start_x, start_y)
+            Commands::LeftClickAndDragToCenter { start } => {
+                left_click_and_drag_to_center_at(&client, start.position())
+                    .await
+                    .map(|_| json!({ "status": "ok" }))
+            }
+            Commands::Drag {
+                from_x,
+                from_y,
+                to_x,
+                to_y,
+                via,
+                units,
+                buttons,
+                motion,
+            } => {
+                let mut waypoints = vec![MousePosition::new(units, from_x, from_y)];
+                waypoints.extend(via.iter().map(|&(x, y)| MousePosition::new(units, x, y)));
+                waypoints.push(MousePosition::new(units, to_x, to_y));
+                drag_path(
+                    &client,
+                    &waypoints,
+                    buttons.mask(),
+                    motion.path,
+                    motion.easing,
+                    motion.duration,
+                )
+                .await
+                .map(|_| json!({ "status": "ok" }))
+            }
+            Commands::MovePath {
+                points,
+                units,
+                motion,
+            } => {
+                let waypoints: Vec<MousePosition> = points
+                    .iter()
+                    .map(|&(x, y)| MousePosition::new(units, x, y))
+                    .collect();
+                move_path(
+                    &client,
+                    &waypoints,
+                    motion.path,
+                    motion.easing,
+                    motion.duration,
+                )
+                .await
+                .map(|_| json!({ "status": "ok" }))
+            }
+            Commands::MouseDown { point, buttons } => {
+                mouse_down(&client, point.position(), buttons.mask())
                     .await
                     .map(|_| json!({ "status": "ok" }))
             }
-            Commands::Screenshot { output } => {
+            Commands::MouseUp { point } => mouse_up(&client, point.position())
+                .await
+                .map(|_| json!({ "status": "ok" })),
+            Commands::Screenshot {
+                output,
+                max_age,
+                fresh,
+                format,
+                quality,
+                crop,
+                max_size,
+                grayscale,
+                file_only,
+            } => {
+                let freshness = match (max_age, fresh) {
+                    (_, true) => FrameFreshness::Next,
+                    (Some(ms), false) => {
+                        FrameFreshness::MaxAge(tokio::time::Duration::from_millis(ms))
+                    }
+                    (None, false) => FrameFreshness::Latest,
+                };
+                let format = format.or_else(|| {
+                    let path = std::path::Path::new(output.as_deref()?);
+                    ScreenshotFormat::from_path(path)
+                });
+                let options = ScreenshotOptions {
+                    format: format.unwrap_or_default(),
+                    quality,
+                    crop,
+                    max_size,
+                    grayscale,
+                };
                 client
                     .video_capture
-                    .capture_screenshot_png()
+                    .capture_screenshot(freshness, &options)
                     .await
-                    .and_then(|png_data| {
-                        let base64_data = general_purpose::STANDARD.encode(&png_data);
-                        let data_url = format!("data:image/png;base64,{}", base64_data);
-                        
+                    .and_then(|screenshot| {
                         let mut result = json!({
                             "status": "ok",
-                            "format": "png",
-                            "size": png_data.len(),
-                            "data": data_url
+                            "format": screenshot.format,
+                            "width": screenshot.width,
+                            "height": screenshot.height,
+                            "size": screenshot.data.len(),
                         });
-                        
+                        if !file_only {
+                            let base64_data = general_purpose::STANDARD.encode(&screenshot.data);
+                            result["data"] = json!(format!(
+                                "data:{};base64,{}",
+                                screenshot.format.mime_type(),
+                                base64_data
+                            ));
+                        }
+
                         if let Some(output_path) = output {
                             let mut file = File::create(&output_path)
                                 .map_err(|e| anyhow::anyhow!("Failed to create output file: {}", e))?;
-                            file.write_all(&png_data)
+                            file.write_all(&screenshot.data)
                                 .map_err(|e| anyhow::anyhow!("Failed to write to output file: {}", e))?;
                             result["saved_to"] = json!(output_path);
                         }
-                        
+
                         Ok(result)
                     })
             }
+            #[cfg(feature = "gstreamer")]
+            Commands::Record {
+                output,
+                duration,
+                format,
+                segment,
+            } => {
+                let options = RecordingOptions {
+                    format: format
+                        .or_else(|| RecordingFormat::from_path(std::path::Path::new(&output)))
+                        .unwrap_or_default(),
+                    segment_duration: segment.map(tokio::time::Duration::from_secs),
+                };
+                record_for(
+                    &client.video_capture,
+                    &output,
+                    tokio::time::Duration::from_secs(duration),
+                    &options,
+                )
+                .await
+                .and_then(|summary| {
+                    let mut result = serde_json::to_value(summary)?;
+                    result["status"] = json!("ok");
+                    Ok(result)
+                })
+            }
+            Commands::WaitChange {
+                region,
+                threshold,
+                timeout,
+            } => wait_for_change(
+                &client.video_capture,
+                region,
+                threshold,
+                tokio::time::Duration::from_millis(timeout),
+            )
+            .await
+            .map(|result| {
+                json!({
+                    "status": "ok",
+                    "elapsed_ms": result.elapsed_ms,
+                    "difference": result.difference,
+                })
+            }),
+            Commands::WaitStable {
+                region,
+                threshold,
+                stable_for,
+                timeout,
+            } => wait_for_stable(
+                &client.video_capture,
+                region,
+                threshold,
+                tokio::time::Duration::from_millis(stable_for),
+                tokio::time::Duration::from_millis(timeout),
+            )
+            .await
+            .map(|result| {
+                json!({
+                    "status": "ok",
+                    "elapsed_ms": result.elapsed_ms,
+                    "difference": result.difference,
+                })
+            }),
+            Commands::FindImage { template } => match template.load() {
+                Ok((image, options)) => {
+                    find_image(&client.video_capture, &image, &options)
+                        .await
+                        .map(|found| json!({ "status": "ok", "match": found }))
+                }
+                Err(e) => Err(e),
+            },
+            Commands::WaitImage { template, timeout } => match template.load() {
+                Ok((image, options)) => wait_for_image(
+                    &client.video_capture,
+                    &image,
+                    &options,
+                    tokio::time::Duration::from_millis(timeout),
+                )
+                .await
+                .map(|found| json!({ "status": "ok", "match": found })),
+                Err(e) => Err(e),
+            },
+            Commands::ClickImage {
+                template,
+                timeout,
+                buttons,
+            } => match template.load() {
+                Ok((image, options)) => click_image(
+                    &client,
+                    &image,
+                    &options,
+                    tokio::time::Duration::from_millis(timeout),
+                    buttons.mask(),
+                )
+                .await
+                .map(|found| json!({ "status": "ok", "match": found })),
+                Err(e) => Err(e),
+            },
+            Commands::ReadText { font, attributes } => match font.load() {
+                Ok(font) => {
+                    let mut reader = TextReader::new(font);
+                    read_screen_text(&client.video_capture, &mut reader, attributes)
+                        .await
+                        .map(|screen| {
+                            let mut result = json!({
+                                "status": "ok",
+                                "grid": screen.grid,
+                                "confidence": screen.confidence,
+                                "lines": screen.lines,
+                            });
+                            if let Some(cells) = screen.attributes {
+                                result["attributes"] = json!(cells);
+                            }
+                            result
+                        })
+                }
+                Err(e) => Err(e),
+            },
+            Commands::WaitText {
+                text,
+                font,
+                timeout,
+            } => match font.load() {
+                Ok(font) => wait_for_text(
+                    &client.video_capture,
+                    font,
+                    &text,
+                    tokio::time::Duration::from_millis(timeout),
+                )
+                .await
+                .map(|found| {
+                    json!({
+                        "status": "ok",
+                        "row": found.row,
+                        "column": found.column,
+                        "line": found.line,
+                        "elapsed_ms": found.elapsed_ms,
+                    })
+                }),
+                Err(e) => Err(e),
+            },
             Commands::Wait { milliseconds } => {
                 tokio::time::sleep(tokio::time::Duration::from_millis(milliseconds)).await;
                 Ok(json!({ "status": "ok" }))
@@ -612,8 +1398,79 @@ async fn main() -> AnyResult<()> {
             Commands::SetKeyboardLayout { layout } => rpc_set_keyboard_layout(&client, layout)
                 .await
                 .map(|_| json!({ "status": "ok" })),
+            Commands::ValidateKeyboardLayout { layout } => {
+                let selected = match &layout {
+                    Some(iso_code) => layouts::get_layout(iso_code)
+                        .map(|layout| vec![layout])
+                        .ok_or_else(|| anyhow::anyhow!("Unknown keyboard layout: {}", iso_code)),
+                    None => Ok(layouts::all_layouts()),
+                };
+                selected.map(|selected| {
+                    let reports: Vec<_> = selected.into_iter().map(validate_layout).collect();
+                    json!({
+                        "status": "ok",
+                        "valid": reports.iter().all(|report| report.is_valid()),
+                        "layouts": reports,
+                    })
+                })
+            }
             Commands::GetKeyboardLedState => rpc_get_keyboard_led_state(&client).await,
+            Commands::SetLockState { caps, num, scroll } => {
+                set_lock_state(&client, caps, num, scroll)
+                    .await
+                    .map(|state| json!({ "status": "ok", "led_state": state }))
+            }
             Commands::GetKeyDownState => rpc_get_key_down_state(&client).await,
+            Commands::ReleaseAll { x, y } => release_all(&client, x.zip(y))
+                .await
+                .map(|resent| json!({ "status": "ok", "resent_reports": resent })),
+            Commands::GetKeyboardMacros => get_keyboard_macros(&client)
+                .await
+                .map(|macros| json!(macros)),
+            Commands::SetKeyboardMacros { macros } => {
+                let macros: Vec<KeyboardMacro> = serde_json::from_str(&macros)?;
+                set_keyboard_macros(&client, macros)
+                    .await
+                    .map(|macros| json!({ "status": "ok", "macros": macros }))
+            }
+            Commands::AddKeyboardMacro { name, steps } => {
+                let steps: Vec<KeyboardMacroStep> = serde_json::from_str(&steps)?;
+                add_keyboard_macro(&client, KeyboardMacro::new(name, steps))
+                    .await
+                    .map(|macros| json!({ "status": "ok", "macros": macros }))
+            }
+            Commands::UpdateKeyboardMacro { name, steps, rename } => {
+                let steps: Vec<KeyboardMacroStep> = serde_json::from_str(&steps)?;
+                update_keyboard_macro(&client, &name, rename, steps)
+                    .await
+                    .map(|macros| json!({ "status": "ok", "macros": macros }))
+            }
+            Commands::DeleteKeyboardMacro { name } => delete_keyboard_macro(&client, &name)
+                .await
+                .map(|macros| json!({ "status": "ok", "macros": macros })),
+            Commands::ReorderKeyboardMacros { names } => reorder_keyboard_macros(&client, &names)
+                .await
+                .map(|macros| json!({ "status": "ok", "macros": macros })),
+            Commands::ExportKeyboardMacros { output } => {
+                export_keyboard_macros(&client).await.and_then(|exported| match output {
+                    Some(output_path) => {
+                        std::fs::write(&output_path, &exported)
+                            .map_err(|e| anyhow::anyhow!("Failed to write to output file: {}", e))?;
+                        Ok(json!({ "status": "ok", "saved_to": output_path }))
+                    }
+                    None => Ok(serde_json::from_str(&exported)?),
+                })
+            }
+            Commands::ImportKeyboardMacros { file, replace } => {
+                let contents = std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", file, e))?;
+                import_keyboard_macros(&client, &contents, replace)
+                    .await
+                    .map(|macros| json!({ "status": "ok", "macros": macros }))
+            }
+            Commands::RunKeyboardMacro { name } => run_keyboard_macro(&client, &name)
+                .await
+                .map(|_| json!({ "status": "ok" })),
             Commands::RelMouseReport { dx, dy, buttons } => {
                 rpc_rel_mouse_report(&client, dx, dy, buttons)
                     .await
@@ -662,6 +1519,39 @@ async fn main() -> AnyResult<()> {
             Commands::SetDcPowerState { enabled } => rpc_set_dc_power_state(&client, enabled)
                 .await
                 .map(|_| json!({ "status": "ok" })),
+            Commands::BootInto {
+                key,
+                power,
+                interval,
+                timeout,
+                until_resolution,
+                threshold,
+                no_wait,
+                dc_off,
+            } => {
+                let until = match until_resolution {
+                    _ if no_wait => Ok(BootUntil::Timeout),
+                    Some(resolution) => parse_resolution(&resolution)
+                        .map(|(width, height)| BootUntil::Resolution { width, height }),
+                    None => Ok(BootUntil::ScreenChange { threshold }),
+                };
+                match until {
+                    Ok(until) => {
+                        let options = BootOptions {
+                            power,
+                            press_interval_ms: interval,
+                            timeout_ms: timeout,
+                            until,
+                            dc_off_ms: dc_off,
+                            ..BootOptions::new(key)
+                        };
+                        boot_into(&client, &options)
+                            .await
+                            .map(|outcome| json!({ "status": "ok", "outcome": outcome }))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
             Commands::SetDcRestoreState { state } => rpc_set_dc_restore_state(&client, state)
                 .await
                 .map(|_| json!({ "status": "ok" })),
@@ -706,11 +1596,42 @@ async fn main() -> AnyResult<()> {
                 .map(|_| json!({ "status": "ok" })),
             Commands::GetJigglerConfig => rpc_get_jiggler_config(&client).await,
             Commands::SetJigglerConfig { config } => {
-                let config_json: Value = serde_json::from_str(&config)?;
-                rpc_set_jiggler_config(&client, config_json)
+                let config: JigglerConfig = serde_json::from_str(&config)?;
+                set_jiggler_config(&client, &config)
                     .await
                     .map(|_| json!({ "status": "ok" }))
             }
+            Commands::SetJigglerPreset { preset, timezone } => {
+                let mut config = JigglerConfig::preset(preset);
+                config.timezone = timezone;
+                set_jiggler_config(&client, &config)
+                    .await
+                    .map(|_| json!({ "status": "ok", "config": config }))
+            }
+            Commands::JigglerSchedule {
+                windows,
+                timezone,
+                check_interval,
+                duration,
+            } => {
+                let windows: Vec<JigglerWindow> = windows
+                    .iter()
+                    .map(|window| window.parse())
+                    .collect::<Result<_, _>>()?;
+                let zone = match timezone {
+                    Some(zone) => zone,
+                    None => device_schedule_zone(&client).await?,
+                };
+                let schedule = JigglerSchedule { windows, zone };
+                run_jiggler_schedule(
+                    &client,
+                    &schedule,
+                    tokio::time::Duration::from_secs(check_interval),
+                    duration.map(tokio::time::Duration::from_secs),
+                )
+                .await
+                .map(|transitions| json!({ "status": "ok", "transitions": transitions }))
+            }
             Commands::GetVideoState => rpc_get_video_state(&client).await,
             Commands::GetStreamQualityFactor => rpc_get_stream_quality_factor(&client).await,
             Commands::GetVideoLogStatus => rpc_get_video_log_status(&client).await,
@@ -1,7 +1,119 @@
-use crate::jetkvm_rpc_client::JetKvmRpcClient;
-use anyhow::Result as AnyResult;
+use crate::jetkvm_rpc_client::{rpc_result, JetKvmRpcClient};
+use crate::mouse_path::{sample_path, Easing, PathStyle};
+use crate::typing_profile::TimingRng;
+use crate::video::get_video_state;
+use anyhow::{bail, Result as AnyResult};
+use clap::ValueEnum;
 use serde_json::{json, Value};
 use tokio::time::{sleep, Duration};
+use tracing::warn;
+
+/// JetKVM's USB-HID absolute pointer descriptor uses Logical Maximum 0x7FFF
+/// for both X and Y. The firmware does not scale this to the EDID resolution;
+/// the host's HID driver maps 0..32767 → full screen. We therefore always send
+/// coordinates in this range, regardless of the remote frame resolution.
+pub const ABS_MOUSE_MAX: i64 = 0x7FFF;
+
+/// Units of the x/y values given to the `*_at` mouse helpers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CoordinateUnits {
+    /// `absMouseReport` coordinates, 0..=32767 on both axes.
+    #[default]
+    Raw,
+    /// Target-screen pixels, converted using the current video resolution.
+    Pixels,
+    /// Fractions of the screen, 0.0..=1.0.
+    Normalized,
+}
+
+/// A pointer position on the target screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MousePosition {
+    Raw { x: i64, y: i64 },
+    Pixels { x: f64, y: f64 },
+    Normalized { x: f64, y: f64 },
+}
+
+impl MousePosition {
+    pub fn new(units: CoordinateUnits, x: f64, y: f64) -> Self {
+        match units {
+            CoordinateUnits::Raw => MousePosition::Raw {
+                x: x.round() as i64,
+                y: y.round() as i64,
+            },
+            CoordinateUnits::Pixels => MousePosition::Pixels { x, y },
+            CoordinateUnits::Normalized => MousePosition::Normalized { x, y },
+        }
+    }
+
+    /// The center of the screen.
+    pub fn center() -> Self {
+        MousePosition::Normalized { x: 0.5, y: 0.5 }
+    }
+
+    /// Converts to `absMouseReport` coordinates. `screen` is the target
+    /// resolution and is only needed for `Pixels`.
+    pub fn to_abs(self, screen: Option<(u32, u32)>) -> AnyResult<(i64, i64)> {
+        match self {
+            MousePosition::Raw { x, y } => Ok((x, y)),
+            MousePosition::Normalized { x, y } => Ok(normalized_to_abs(x, y)),
+            MousePosition::Pixels { x, y } => match screen {
+                Some((width, height)) => Ok(pixels_to_abs(x, y, width, height)),
+                None => bail!("Pixel coordinates need the target screen resolution"),
+            },
+        }
+    }
+}
+
+/// Maps fractions of the screen (0.0..=1.0) to `absMouseReport` coordinates.
+pub fn normalized_to_abs(x: f64, y: f64) -> (i64, i64) {
+    let scale = |v: f64| (v.clamp(0.0, 1.0) * ABS_MOUSE_MAX as f64).round() as i64;
+    (scale(x), scale(y))
+}
+
+/// Maps a target-screen pixel to the `absMouseReport` coordinate of its
+/// center, so the host's driver lands on that exact pixel.
+pub fn pixels_to_abs(x: f64, y: f64, width: u32, height: u32) -> (i64, i64) {
+    let scale = |v: f64, size: u32| {
+        if size == 0 {
+            return 0;
+        }
+        let v = v.clamp(0.0, (size - 1) as f64);
+        (((v + 0.5) * (ABS_MOUSE_MAX + 1) as f64 / size as f64) as i64).clamp(0, ABS_MOUSE_MAX)
+    };
+    (scale(x, width), scale(y, height))
+}
+
+/// Maps `absMouseReport` coordinates back to target-screen pixels.
+pub fn abs_to_pixels(x: i64, y: i64, width: u32, height: u32) -> (u32, u32) {
+    let scale = |v: i64, size: u32| {
+        let v = v.clamp(0, ABS_MOUSE_MAX) as u64;
+        ((v * size as u64) / (ABS_MOUSE_MAX as u64 + 1)) as u32
+    };
+    (scale(x, width), scale(y, height))
+}
+
+/// The target's screen resolution, from the device's video state.
+pub async fn screen_size(client: &JetKvmRpcClient) -> AnyResult<(u32, u32)> {
+    let state = get_video_state(client).await?;
+    if !state.ready || state.width == 0 || state.height == 0 {
+        bail!("No video signal, cannot convert pixel coordinates");
+    }
+    Ok((state.width, state.height))
+}
+
+/// Converts `position` to `absMouseReport` coordinates, reading the screen
+/// resolution from the device when it is given in pixels.
+pub async fn resolve_position(
+    client: &JetKvmRpcClient,
+    position: MousePosition,
+) -> AnyResult<(i64, i64)> {
+    let screen = match position {
+        MousePosition::Pixels { .. } => Some(screen_size(client).await?),
+        _ => None,
+    };
+    position.to_abs(screen)
+}
 
 /// Sends an absolute mouse report with x, y coordinates and button state.
 pub async fn rpc_abs_mouse_report(
@@ -15,7 +127,9 @@ pub async fn rpc_abs_mouse_report(
         "y": y,
         "buttons": buttons,
     });
-    client.send_rpc("absMouseReport", params).await
+    let result = client.send_rpc("absMouseReport", params).await?;
+    *client.abs_mouse_position.lock().await = Some((x, y));
+    Ok(result)
 }
 
 /// Sends a relative mouse report with dx, dy and button state.
@@ -39,82 +153,119 @@ pub async fn rpc_wheel_report(client: &JetKvmRpcClient, wheel_y: i64) -> AnyResu
     client.send_rpc("wheelReport", params).await
 }
 
+/// Sends a wheel report with vertical (`wheelY`, positive = up) and
+/// horizontal (`wheelX`, positive = right) movement.
+pub async fn rpc_wheel_report_xy(
+    client: &JetKvmRpcClient,
+    wheel_y: i64,
+    wheel_x: i64,
+) -> AnyResult<Value> {
+    let params = json!({ "wheelY": wheel_y, "wheelX": wheel_x });
+    client.send_rpc("wheelReport", params).await
+}
+
+/// Largest wheel movement in one report; the HID wheel fields are signed
+/// bytes.
+pub const WHEEL_MAX: i64 = 127;
+
+/// Scrolls by `wheel_y` and `wheel_x` detents, split into as many reports as
+/// the HID range needs. Horizontal scrolling is best effort: firmware
+/// without it usually ignores `wheelX` silently, so `true` only means the
+/// device did not reject it. If the device does answer with an error, the
+/// vertical part is sent on its own and the function returns `false`, so
+/// callers can stop sending horizontal scroll.
+pub async fn scroll(client: &JetKvmRpcClient, wheel_y: i64, wheel_x: i64) -> AnyResult<bool> {
+    let mut horizontal = true;
+    let (mut rest_y, mut rest_x) = (wheel_y, wheel_x);
+    while rest_y != 0 || rest_x != 0 {
+        let y = rest_y.clamp(-WHEEL_MAX, WHEEL_MAX);
+        let x = if horizontal {
+            rest_x.clamp(-WHEEL_MAX, WHEEL_MAX)
+        } else {
+            0
+        };
+        if x == 0 {
+            rpc_result("wheelReport", rpc_wheel_report(client, y).await?)?;
+        } else {
+            let res = rpc_wheel_report_xy(client, y, x).await?;
+            if let Some(error) = res.get("error") {
+                warn!(
+                    "Horizontal scrolling not supported by the device: {}",
+                    error
+                );
+                horizontal = false;
+                rest_x = 0;
+                continue;
+            }
+        }
+        rest_y -= y;
+        rest_x -= x;
+    }
+    Ok(horizontal)
+}
+
+/// Turns fractional scroll input (touchpads, high-resolution wheels) into
+/// whole wheel detents, carrying the remainder over to the next event so
+/// slow scrolling is not lost.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WheelAccumulator {
+    y: f64,
+    x: f64,
+}
+
+impl WheelAccumulator {
+    /// Adds `dy`/`dx` detents and returns the whole detents now due as
+    /// `(wheel_y, wheel_x)`, or `None` if nothing is due yet.
+    pub fn add(&mut self, dy: f64, dx: f64) -> Option<(i64, i64)> {
+        self.y += dy;
+        self.x += dx;
+        let (y, x) = (self.y.trunc(), self.x.trunc());
+        self.y -= y;
+        self.x -= x;
+        if y == 0.0 && x == 0.0 {
+            None
+        } else {
+            Some((y as i64, x as i64))
+        }
+    }
+}
+
 /// Moves the mouse to the specified absolute coordinates.
 pub async fn rpc_move_mouse(client: &JetKvmRpcClient, x: i64, y: i64) -> AnyResult<()> {
-    let params = json!({
-        "x": x,
-        "y": y,
-        "buttons": 0,
-    });
-    client.send_rpc("absMouseReport", params).await?;
+    rpc_abs_mouse_report(client, x, y, 0).await?;
     Ok(())
 }
 
-/// Simulates a left mouse click at the specified coordinates.
-/// It moves the mouse (optional if already positioned), then sends a press and release.
-pub async fn rpc_left_click(client: &JetKvmRpcClient, x: i64, y: i64) -> AnyResult<()> {
-    // Optionally move the mouse first:
-    rpc_move_mouse(client, x, y).await?;
-
-    // Press left button (assumed bit 0: value = 1)
-    let params_down = json!({
-        "x": x,
-        "y": y,
-        "buttons": 1
-    });
-    client.send_rpc("absMouseReport", params_down).await?;
+/// How long a button is held for a click.
+const CLICK_HOLD_MS: u64 = 100;
 
-    // Wait a little bit
-    sleep(Duration::from_millis(100)).await;
+/// Clicks `buttons` (a mask, see `buttons_mask`) at the specified
+/// coordinates: moves there, presses, and releases. Fails with a clear
+/// message if the firmware rejects the buttons, e.g. back/forward on a
+/// device whose HID descriptor only has three buttons.
+pub async fn rpc_click(client: &JetKvmRpcClient, x: i64, y: i64, buttons: u64) -> AnyResult<()> {
+    rpc_move_mouse(client, x, y).await?;
+    let res = rpc_abs_mouse_report(client, x, y, buttons).await?;
+    if let Some(error) = res.get("error") {
+        bail!("Device rejected mouse buttons {:#x}: {}", buttons, error);
+    }
+    sleep(Duration::from_millis(CLICK_HOLD_MS)).await;
+    rpc_move_mouse(client, x, y).await
+}
 
-    // Release button
-    let params_up = json!({
-        "x": x,
-        "y": y,
-        "buttons": 0
-    });
-    client.send_rpc("absMouseReport", params_up).await?;
-    Ok(())
+/// Simulates a left mouse click at the specified coordinates.
+pub async fn rpc_left_click(client: &JetKvmRpcClient, x: i64, y: i64) -> AnyResult<()> {
+    rpc_click(client, x, y, MouseButton::Left.mask()).await
 }
 
 /// Simulates a right mouse click at the specified coordinates.
-/// Right-click is typically represented as button bit 1 (value = 2).
 pub async fn rpc_right_click(client: &JetKvmRpcClient, x: i64, y: i64) -> AnyResult<()> {
-    rpc_move_mouse(client, x, y).await?;
-    let params_down = json!({
-        "x": x,
-        "y": y,
-        "buttons": 2
-    });
-    client.send_rpc("absMouseReport", params_down).await?;
-    sleep(Duration::from_millis(100)).await;
-    let params_up = json!({
-        "x": x,
-        "y": y,
-        "buttons": 0
-    });
-    client.send_rpc("absMouseReport", params_up).await?;
-    Ok(())
+    rpc_click(client, x, y, MouseButton::Right.mask()).await
 }
 
 /// Simulates a middle mouse click at the specified coordinates.
-/// Middle-click is typically represented as button bit 2 (value = 4).
 pub async fn rpc_middle_click(client: &JetKvmRpcClient, x: i64, y: i64) -> AnyResult<()> {
-    rpc_move_mouse(client, x, y).await?;
-    let params_down = json!({
-        "x": x,
-        "y": y,
-        "buttons": 4
-    });
-    client.send_rpc("absMouseReport", params_down).await?;
-    sleep(Duration::from_millis(100)).await;
-    let params_up = json!({
-        "x": x,
-        "y": y,
-        "buttons": 0
-    });
-    client.send_rpc("absMouseReport", params_up).await?;
-    Ok(())
+    rpc_click(client, x, y, MouseButton::Middle.mask()).await
 }
 
 /// Simulates a double left click at the specified coordinates.
@@ -126,94 +277,379 @@ pub async fn rpc_double_click(client: &JetKvmRpcClient, x: i64, y: i64) -> AnyRe
     Ok(())
 }
 
-/// Simulates a left click and drag from the given start coordinates to the center of the screen.
-pub async fn rpc_left_click_and_drag_to_center(
+/// Sends an absolute mouse report at `position`.
+pub async fn abs_mouse_report_at(
+    client: &JetKvmRpcClient,
+    position: MousePosition,
+    buttons: u64,
+) -> AnyResult<Value> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_abs_mouse_report(client, x, y, buttons).await
+}
+
+/// Moves the mouse to `position`.
+pub async fn move_mouse_at(client: &JetKvmRpcClient, position: MousePosition) -> AnyResult<()> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_move_mouse(client, x, y).await
+}
+
+/// Clicks `buttons` (a mask) at `position`.
+pub async fn click_at(
     client: &JetKvmRpcClient,
-    start_x: i64,
-    start_y: i64,
+    position: MousePosition,
+    buttons: u64,
+) -> AnyResult<()> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_click(client, x, y, buttons).await
+}
+
+/// Left click at `position`.
+pub async fn left_click_at(client: &JetKvmRpcClient, position: MousePosition) -> AnyResult<()> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_left_click(client, x, y).await
+}
+
+/// Right click at `position`.
+pub async fn right_click_at(client: &JetKvmRpcClient, position: MousePosition) -> AnyResult<()> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_right_click(client, x, y).await
+}
+
+/// Middle click at `position`.
+pub async fn middle_click_at(client: &JetKvmRpcClient, position: MousePosition) -> AnyResult<()> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_middle_click(client, x, y).await
+}
+
+/// Double left click at `position`.
+pub async fn double_click_at(client: &JetKvmRpcClient, position: MousePosition) -> AnyResult<()> {
+    let (x, y) = resolve_position(client, position).await?;
+    rpc_double_click(client, x, y).await
+}
+
+/// Mouse buttons, as bits of the `buttons` mask in mouse reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    /// Button 4, "back" in browsers and file managers. Like `Forward`, only
+    /// reaches the target if the device's HID descriptor has a fourth and
+    /// fifth button; firmware without them drops the bit silently.
+    Back,
+    /// Button 5, "forward".
+    Forward,
+}
+
+impl MouseButton {
+    pub fn mask(self) -> u64 {
+        match self {
+            MouseButton::Left => 1,
+            MouseButton::Right => 2,
+            MouseButton::Middle => 4,
+            MouseButton::Back => 8,
+            MouseButton::Forward => 16,
+        }
+    }
+}
+
+/// Combined mask of `buttons`, for chords such as left+right.
+pub fn buttons_mask(buttons: &[MouseButton]) -> u64 {
+    buttons.iter().fold(0, |mask, button| mask | button.mask())
+}
+
+/// Time between two reports while following a path (about 60 Hz).
+pub const MOVE_INTERVAL_MS: u64 = 16;
+pub const DEFAULT_DRAG_DURATION_MS: u64 = 500;
+/// Pause after pressing or before releasing buttons, so the target sees the
+/// press at the start position and the final position before the release.
+const PRESS_SETTLE_MS: u64 = 100;
+
+/// Converts several positions, reading the screen resolution at most once.
+pub async fn resolve_positions(
+    client: &JetKvmRpcClient,
+    positions: &[MousePosition],
+) -> AnyResult<Vec<(i64, i64)>> {
+    let needs_screen = positions
+        .iter()
+        .any(|position| matches!(position, MousePosition::Pixels { .. }));
+    let screen = if needs_screen {
+        Some(screen_size(client).await?)
+    } else {
+        None
+    };
+    positions
+        .iter()
+        .map(|position| position.to_abs(screen))
+        .collect()
+}
+
+/// Holds `buttons` (a mask, see `buttons_mask`) at `position`. The mask
+/// replaces the buttons held before, so pass the whole chord.
+pub async fn mouse_down(
+    client: &JetKvmRpcClient,
+    position: MousePosition,
+    buttons: u64,
+) -> AnyResult<()> {
+    abs_mouse_report_at(client, position, buttons).await?;
+    Ok(())
+}
+
+/// Releases all buttons at `position`.
+pub async fn mouse_up(client: &JetKvmRpcClient, position: MousePosition) -> AnyResult<()> {
+    abs_mouse_report_at(client, position, 0).await?;
+    Ok(())
+}
+
+/// Moves the pointer through `waypoints` over `duration_ms`, holding
+/// `buttons` the whole way. The pointer is assumed to be at the first
+/// waypoint already.
+pub async fn move_along_path(
+    client: &JetKvmRpcClient,
+    waypoints: &[MousePosition],
+    buttons: u64,
+    style: PathStyle,
+    easing: Easing,
+    duration_ms: u64,
+) -> AnyResult<()> {
+    let waypoints = resolve_positions(client, waypoints).await?;
+    follow_path(client, &waypoints, buttons, style, easing, duration_ms).await
+}
+
+/// Moves the pointer to the first waypoint, then through the others over
+/// `duration_ms` without holding any button.
+pub async fn move_path(
+    client: &JetKvmRpcClient,
+    waypoints: &[MousePosition],
+    style: PathStyle,
+    easing: Easing,
+    duration_ms: u64,
+) -> AnyResult<()> {
+    let waypoints = resolve_positions(client, waypoints).await?;
+    let Some(&(x, y)) = waypoints.first() else {
+        bail!("A path needs at least one waypoint");
+    };
+    rpc_abs_mouse_report(client, x, y, 0).await?;
+    follow_path(client, &waypoints, 0, style, easing, duration_ms).await
+}
+
+/// `move_along_path` with waypoints already in `absMouseReport` coordinates.
+async fn follow_path(
+    client: &JetKvmRpcClient,
+    waypoints: &[(i64, i64)],
+    buttons: u64,
+    style: PathStyle,
+    easing: Easing,
+    duration_ms: u64,
+) -> AnyResult<()> {
+    let steps = (duration_ms / MOVE_INTERVAL_MS).max(1) as usize;
+    let interval = Duration::from_millis(duration_ms / steps as u64);
+    let path = sample_path(waypoints, style, easing, steps, &mut TimingRng::from_time());
+    for (x, y) in path {
+        rpc_abs_mouse_report(client, x, y, buttons).await?;
+        sleep(interval).await;
+    }
+    Ok(())
+}
+
+/// Presses `buttons` at the first waypoint, follows the path to the last one
+/// and releases there. If the path cannot be followed, the buttons are
+/// released where the pointer got to before the error is returned.
+pub async fn drag_path(
+    client: &JetKvmRpcClient,
+    waypoints: &[MousePosition],
+    buttons: u64,
+    style: PathStyle,
+    easing: Easing,
+    duration_ms: u64,
 ) -> AnyResult<()> {
-    // Define center coordinates. Adjust these values as needed for your display.
-    let center_x = 960;
-    let center_y = 540;
-
-    // 1. Move the mouse to the starting position (no buttons pressed)
-    client
-        .send_rpc(
-            "absMouseReport",
-            json!({
-                "x": start_x,
-                "y": start_y,
-                "buttons": 0,
-            }),
-        )
-        .await?;
-    sleep(Duration::from_millis(100)).await;
-
-    // 2. Press the left button down (buttons = 1)
-    client
-        .send_rpc(
-            "absMouseReport",
-            json!({
-                "x": start_x,
-                "y": start_y,
-                "buttons": 1,
-            }),
-        )
-        .await?;
-    sleep(Duration::from_millis(100)).await;
-
-    // 3. Send intermediate drag updates from start to center.
-    // We'll use a number of steps to simulate continuous movement.
-    let steps = 100;
-    for i in 1..=steps {
-        let x = start_x + ((center_x - start_x) * i) / steps;
-        let y = start_y + ((center_y - start_y) * i) / steps;
-        client
-            .send_rpc(
-                "absMouseReport",
-                json!({
-                    "x": x,
-                    "y": y,
-                    "buttons": 1,
-                }),
-            )
-            .await?;
-        sleep(Duration::from_millis(50)).await;
-    }
-
-    // 4. Release the left button at the center.
-    client
-        .send_rpc(
-            "absMouseReport",
-            json!({
-                "x": center_x,
-                "y": center_y,
-                "buttons": 0,
-            }),
-        )
-        .await?;
-    sleep(Duration::from_millis(100)).await;
-
-    client
-        .send_rpc(
-            "absMouseReport",
-            json!({
-                "x": 960,
-                "y": 540,
-                "buttons": 1
-            }),
-        )
-        .await?;
-
-    client
-        .send_rpc(
-            "absMouseReport",
-            json!({
-                "x": 960,
-                "y": 540,
-                "buttons": 1,
-            }),
-        )
-        .await?;
+    let waypoints = resolve_positions(client, waypoints).await?;
+    let (Some(&first), Some(&last)) = (waypoints.first(), waypoints.last()) else {
+        bail!("A drag needs at least one waypoint");
+    };
+    rpc_abs_mouse_report(client, first.0, first.1, 0).await?;
+    rpc_abs_mouse_report(client, first.0, first.1, buttons).await?;
+    sleep(Duration::from_millis(PRESS_SETTLE_MS)).await;
+    if let Err(e) = follow_path(client, &waypoints, buttons, style, easing, duration_ms).await {
+        let (x, y) = client.abs_mouse_position.lock().await.unwrap_or(first);
+        if let Err(release) = rpc_abs_mouse_report(client, x, y, 0).await {
+            warn!(
+                "Could not release mouse buttons after a failed drag: {}",
+                release
+            );
+        }
+        return Err(e);
+    }
+    sleep(Duration::from_millis(PRESS_SETTLE_MS)).await;
+    rpc_abs_mouse_report(client, last.0, last.1, 0).await?;
     Ok(())
 }
+
+/// Drags in a straight line from `from` to `to` holding `buttons`.
+pub async fn drag(
+    client: &JetKvmRpcClient,
+    from: MousePosition,
+    to: MousePosition,
+    buttons: u64,
+    duration_ms: u64,
+    easing: Easing,
+) -> AnyResult<()> {
+    drag_path(
+        client,
+        &[from, to],
+        buttons,
+        PathStyle::Linear,
+        easing,
+        duration_ms,
+    )
+    .await
+}
+
+/// Left click and drag from `start` to the center of the screen.
+pub async fn left_click_and_drag_to_center_at(
+    client: &JetKvmRpcClient,
+    start: MousePosition,
+) -> AnyResult<()> {
+    drag(
+        client,
+        start,
+        MousePosition::center(),
+        MouseButton::Left.mask(),
+        DEFAULT_DRAG_DURATION_MS,
+        Easing::default(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jetkvm_rpc_client::test_rpc;
+    use crate::keyboard::release_all;
+
+    #[tokio::test]
+    async fn test_scroll_falls_back_to_vertical_when_wheel_x_is_rejected() {
+        let (client, calls) = test_rpc::client(|_, params| {
+            if params.get("wheelX").is_some() {
+                json!({ "error": { "code": -32602, "message": "invalid params" } })
+            } else {
+                json!({ "result": null })
+            }
+        });
+        assert!(!scroll(&client, 200, 3).await.unwrap());
+
+        let sent: Vec<_> = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, p)| p.clone())
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                json!({ "wheelY": 127, "wheelX": 3 }),
+                json!({ "wheelY": 127 }),
+                json!({ "wheelY": 73 }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scroll_sends_horizontal_when_accepted() {
+        let (client, calls) = test_rpc::client(|_, _| json!({ "result": null }));
+        assert!(scroll(&client, -1, -130).await.unwrap());
+
+        let sent: Vec<_> = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, p)| p.clone())
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                json!({ "wheelY": -1, "wheelX": -127 }),
+                json!({ "wheelY": 0, "wheelX": -3 }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_release_all_reuses_last_moved_to_position() {
+        let (client, calls) = test_rpc::client(|method, _| match method {
+            "getKeyDownState" => json!({ "result": { "modifier": 0, "keys": [] } }),
+            _ => json!({ "result": null }),
+        });
+        rpc_move_mouse(&client, 700, 800).await.unwrap();
+        release_all(&client, None).await.unwrap();
+
+        let calls = calls.lock().unwrap();
+        let (_, last_abs) = calls
+            .iter()
+            .rev()
+            .find(|(method, _)| method == "absMouseReport")
+            .unwrap();
+        assert_eq!(*last_abs, json!({ "x": 700, "y": 800, "buttons": 0 }));
+    }
+
+    #[test]
+    fn test_pixel_and_normalized_conversion() {
+        assert_eq!(normalized_to_abs(0.0, 1.0), (0, ABS_MOUSE_MAX));
+        assert_eq!(normalized_to_abs(0.5, 2.0), (16384, ABS_MOUSE_MAX));
+
+        for (width, height) in [(1920, 1080), (1024, 768), (640, 480)] {
+            for (px, py) in [(0, 0), (width / 2, height / 3), (width - 1, height - 1)] {
+                let (x, y) = pixels_to_abs(px as f64, py as f64, width, height);
+                assert!((0..=ABS_MOUSE_MAX).contains(&x) && (0..=ABS_MOUSE_MAX).contains(&y));
+                assert_eq!(abs_to_pixels(x, y, width, height), (px, py));
+            }
+        }
+        assert_eq!(
+            pixels_to_abs(5000.0, -3.0, 1920, 1080),
+            pixels_to_abs(1919.0, 0.0, 1920, 1080)
+        );
+    }
+
+    #[test]
+    fn test_mouse_position_to_abs() {
+        let center = MousePosition::center().to_abs(None).unwrap();
+        assert_eq!(center, (16384, 16384));
+        assert_eq!(
+            MousePosition::new(CoordinateUnits::Raw, 100.4, 200.6)
+                .to_abs(None)
+                .unwrap(),
+            (100, 201)
+        );
+        let pixels = MousePosition::new(CoordinateUnits::Pixels, 960.0, 540.0);
+        assert!(pixels.to_abs(None).is_err());
+        assert_eq!(
+            pixels.to_abs(Some((1920, 1080))).unwrap(),
+            pixels_to_abs(960.0, 540.0, 1920, 1080)
+        );
+    }
+
+    #[test]
+    fn test_buttons_mask() {
+        assert_eq!(buttons_mask(&[]), 0);
+        assert_eq!(MouseButton::Middle.mask(), 4);
+        assert_eq!(
+            buttons_mask(&[MouseButton::Left, MouseButton::Right, MouseButton::Left]),
+            3
+        );
+        assert_eq!(
+            buttons_mask(&[MouseButton::Back, MouseButton::Forward]),
+            0b11000
+        );
+    }
+
+    #[test]
+    fn test_wheel_accumulator() {
+        let mut wheel = WheelAccumulator::default();
+        assert_eq!(wheel.add(0.5, 0.0), None);
+        assert_eq!(wheel.add(0.25, -0.5), None);
+        assert_eq!(wheel.add(0.5, -0.75), Some((1, -1)));
+        assert_eq!(wheel.add(-0.25, 0.0), None);
+        assert_eq!(wheel.add(3.0, 2.5), Some((3, 2)));
+        assert_eq!(wheel.add(-0.25, 0.25), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Pointer paths for drags and gestures.
+//!
+//! `sample_path` turns a list of waypoints (in `absMouseReport` coordinates)
+//! into the positions to report, one per step. `Easing` shapes how fast the
+//! pointer moves along the path; `PathStyle` decides its shape. The
+//! human-like style bends each segment by a random amount and adds a little
+//! jitter so the movement does not look machine-drawn. Every path ends
+//! exactly on the last waypoint.
+
+use crate::mouse::ABS_MOUSE_MAX;
+use crate::typing_profile::TimingRng;
+use clap::ValueEnum;
+
+/// Largest sideways bend of a human-like segment, relative to its length.
+const HUMAN_MAX_BEND: f64 = 0.2;
+/// Largest jitter of a human-like path, in `absMouseReport` units.
+const HUMAN_JITTER: i64 = 8;
+
+/// Progress along the path over time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Easing {
+    /// Constant speed.
+    Linear,
+    /// Start slow, then speed up.
+    EaseIn,
+    /// Start fast, then slow down.
+    EaseOut,
+    /// Slow at both ends.
+    #[default]
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps time `t` (0.0..=1.0) to progress along the path (0.0..=1.0).
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Shape of the path through the waypoints.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PathStyle {
+    /// Straight lines from waypoint to waypoint.
+    #[default]
+    Linear,
+    /// One Bezier curve from the first to the last waypoint, using the
+    /// waypoints in between as control points.
+    Bezier,
+    /// Gently curved segments with a little jitter.
+    Human,
+}
+
+/// Positions to report when moving through `waypoints` in `steps` steps.
+/// The start position itself is not included. `rng` is only used by
+/// `PathStyle::Human`.
+pub fn sample_path(
+    waypoints: &[(i64, i64)],
+    style: PathStyle,
+    easing: Easing,
+    steps: usize,
+    rng: &mut TimingRng,
+) -> Vec<(i64, i64)> {
+    let Some(&last) = waypoints.last() else {
+        return Vec::new();
+    };
+    let steps = steps.max(1);
+    let points: Vec<(f64, f64)> = waypoints
+        .iter()
+        .map(|&(x, y)| (x as f64, y as f64))
+        .collect();
+    let curve = match style {
+        PathStyle::Linear => points,
+        PathStyle::Bezier => points,
+        PathStyle::Human => human_control_points(&points, rng),
+    };
+
+    let mut path = Vec::with_capacity(steps);
+    for step in 1..steps {
+        let t = easing.apply(step as f64 / steps as f64);
+        let (x, y) = match style {
+            PathStyle::Linear => polyline_point(&curve, t),
+            PathStyle::Bezier => bezier_point(&curve, t),
+            PathStyle::Human => quadratic_chain_point(&curve, t),
+        };
+        let (mut x, mut y) = (x.round() as i64, y.round() as i64);
+        if style == PathStyle::Human {
+            x += rng.up_to(2 * HUMAN_JITTER as u64) as i64 - HUMAN_JITTER;
+            y += rng.up_to(2 * HUMAN_JITTER as u64) as i64 - HUMAN_JITTER;
+        }
+        path.push((x.clamp(0, ABS_MOUSE_MAX), y.clamp(0, ABS_MOUSE_MAX)));
+    }
+    path.push(last);
+    path
+}
+
+fn lerp(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+/// Finds the segment `t` falls in, with segments weighted by `lengths`;
+/// returns its index and the position within it.
+fn locate(lengths: &[f64], t: f64) -> (usize, f64) {
+    let total: f64 = lengths.iter().sum();
+    if total == 0.0 {
+        return (lengths.len().saturating_sub(1), 1.0);
+    }
+    let mut target = t * total;
+    for (i, &len) in lengths.iter().enumerate() {
+        if target <= len || i == lengths.len() - 1 {
+            return (i, if len == 0.0 { 1.0 } else { target / len });
+        }
+        target -= len;
+    }
+    (lengths.len() - 1, 1.0)
+}
+
+/// Point at `t` along straight segments, parametrised by distance.
+fn polyline_point(points: &[(f64, f64)], t: f64) -> (f64, f64) {
+    if points.len() == 1 {
+        return points[0];
+    }
+    let lengths: Vec<f64> = points.windows(2).map(|w| distance(w[0], w[1])).collect();
+    let (i, u) = locate(&lengths, t);
+    lerp(points[i], points[i + 1], u)
+}
+
+/// De Casteljau evaluation of the Bezier curve with control `points`.
+fn bezier_point(points: &[(f64, f64)], t: f64) -> (f64, f64) {
+    let mut level = points.to_vec();
+    while level.len() > 1 {
+        level = level.windows(2).map(|w| lerp(w[0], w[1], t)).collect();
+    }
+    level[0]
+}
+
+/// Inserts a randomly offset control point between every pair of
+/// waypoints: `[p0, c0, p1, c1, p2, ...]`.
+fn human_control_points(points: &[(f64, f64)], rng: &mut TimingRng) -> Vec<(f64, f64)> {
+    let mut out = vec![points[0]];
+    for w in points.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let len = distance(a, b);
+        let bend = (rng.up_to(2000) as f64 / 1000.0 - 1.0) * HUMAN_MAX_BEND * len;
+        let mid = lerp(a, b, 0.5);
+        let (nx, ny) = if len == 0.0 {
+            (0.0, 0.0)
+        } else {
+            (-(b.1 - a.1) / len, (b.0 - a.0) / len)
+        };
+        out.push((mid.0 + nx * bend, mid.1 + ny * bend));
+        out.push(b);
+    }
+    out
+}
+
+/// Point at `t` along the chain of quadratic curves built by
+/// `human_control_points`, with segments weighted by their straight length.
+fn quadratic_chain_point(curve: &[(f64, f64)], t: f64) -> (f64, f64) {
+    if curve.len() < 3 {
+        return curve[0];
+    }
+    let segments: Vec<&[(f64, f64)]> = curve.windows(3).step_by(2).collect();
+    let lengths: Vec<f64> = segments.iter().map(|s| distance(s[0], s[2])).collect();
+    let (i, u) = locate(&lengths, t);
+    bezier_point(segments[i], u)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_easing_endpoints_and_monotonic() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            let samples: Vec<f64> = (0..=20).map(|i| easing.apply(i as f64 / 20.0)).collect();
+            assert!(samples.windows(2).all(|w| w[0] <= w[1]), "{:?}", easing);
+        }
+        assert!(Easing::EaseIn.apply(0.25) < 0.25);
+        assert!(Easing::EaseOut.apply(0.25) > 0.25);
+    }
+
+    #[test]
+    fn test_linear_path_follows_waypoints() {
+        let mut rng = TimingRng::new(1);
+        let path = sample_path(
+            &[(0, 0), (1000, 0), (1000, 1000)],
+            PathStyle::Linear,
+            Easing::Linear,
+            4,
+            &mut rng,
+        );
+        assert_eq!(path, vec![(500, 0), (1000, 0), (1000, 500), (1000, 1000)]);
+        assert_eq!(
+            sample_path(&[(5, 5)], PathStyle::Linear, Easing::Linear, 3, &mut rng),
+            vec![(5, 5), (5, 5), (5, 5)]
+        );
+        assert!(sample_path(&[], PathStyle::Linear, Easing::Linear, 3, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_bezier_and_human_paths_end_on_target() {
+        let mut rng = TimingRng::new(7);
+        let bezier = sample_path(
+            &[(0, 0), (0, 2000), (2000, 2000)],
+            PathStyle::Bezier,
+            Easing::Linear,
+            2,
+            &mut rng,
+        );
+        assert_eq!(bezier, vec![(500, 1500), (2000, 2000)]);
+
+        let human = sample_path(
+            &[(100, 100), (30000, 20000)],
+            PathStyle::Human,
+            Easing::EaseInOut,
+            50,
+            &mut rng,
+        );
+        assert_eq!(human.len(), 50);
+        assert_eq!(*human.last().unwrap(), (30000, 20000));
+        for &(x, y) in &human {
+            assert!((0..=ABS_MOUSE_MAX).contains(&x) && (0..=ABS_MOUSE_MAX).contains(&y));
+        }
+        // Bent, but never further from the straight line than the bend allows.
+        let max_offset = human
+            .iter()
+            .map(|&(x, y)| {
+                let (dx, dy) = (29900.0, 19900.0);
+                ((x as f64 - 100.0) * dy - (y as f64 - 100.0) * dx).abs() / dx.hypot(dy)
+            })
+            .fold(0.0, f64::max);
+        assert!(max_offset <= HUMAN_MAX_BEND * 0.5 * 29900f64.hypot(19900.0) + 20.0);
+    }
+}
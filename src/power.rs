@@ -1,11 +1,27 @@
 use crate::jetkvm_rpc_client::JetKvmRpcClient;
 use anyhow::Result as AnyResult;
+use serde::Deserialize;
 use serde_json::{json, Value};
 
+/// The target's ATX front-panel LEDs, as returned by `getATXState`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AtxState {
+    /// Power LED: the target is running.
+    pub power: bool,
+    /// Disk activity LED.
+    pub hdd: bool,
+}
+
 pub async fn rpc_get_atx_state(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getATXState", json!({})).await
 }
 
+/// Reads the ATX LED state as a typed value.
+pub async fn get_atx_state(client: &JetKvmRpcClient) -> AnyResult<AtxState> {
+    client.call("getATXState", json!({})).await
+}
+
 pub async fn rpc_set_atx_power_action(client: &JetKvmRpcClient, action: String) -> AnyResult<Value> {
     let params = json!({ "action": action });
     client.send_rpc("setATXPowerAction", params).await
@@ -0,0 +1,355 @@
+//! Recording the WebRTC video stream to MP4 or Matroska.
+//!
+//! `VideoRecorder` subscribes to `VideoFrameCapture`'s RTP packets and
+//! remuxes the H.264 into a container without re-encoding:
+//!
+//! ```text
+//! appsrc -> rtpjitterbuffer -> rtph264depay -> h264parse -> splitmuxsink(mp4mux | matroskamux)
+//! ```
+//!
+//! The jitter buffer reorders packets and marks lost ones; the depayloader
+//! then drops damaged frames until the next keyframe instead of writing
+//! corrupt ones. A keyframe is requested over RTCP when the recording starts
+//! and after loss, so neither wait lasts long.
+//!
+//! `splitmuxsink` handles segment rotation; a resolution change also starts
+//! a new segment, because the containers cannot change the stream's
+//! dimensions mid-file. The first segment is written to the requested path,
+//! later ones get a `-001`, `-002`, ... suffix.
+//!
+//! Screenshots and `FrameStream`s keep working while a recording runs; each
+//! reader has its own subscription to the track's packets.
+
+use crate::keyframe::SequenceTracker;
+use crate::video::VideoFrameCapture;
+use crate::viewer::pipeline::serialize_rtp;
+use anyhow::{anyhow, Result as AnyResult};
+use clap::ValueEnum;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// How long `VideoRecorder::start` waits for the video track to appear.
+const TRACK_WAIT: Duration = Duration::from_secs(10);
+/// How long `stop` waits for the muxer to finish the file.
+const FINALIZE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Container to record into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    #[default]
+    Mp4,
+    /// Matroska (`.mkv`); survives an interrupted recording better than MP4.
+    Mkv,
+}
+
+impl RecordingFormat {
+    /// Picks the format from a file extension (`.mp4`, `.mkv`).
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "mp4" | "m4v" => Some(RecordingFormat::Mp4),
+            "mkv" => Some(RecordingFormat::Mkv),
+            _ => None,
+        }
+    }
+
+    fn muxer(self) -> &'static str {
+        match self {
+            RecordingFormat::Mp4 => "mp4mux",
+            RecordingFormat::Mkv => "matroskamux",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordingOptions {
+    pub format: RecordingFormat,
+    /// Start a new file after this much video; one file when `None`.
+    pub segment_duration: Option<Duration>,
+}
+
+/// What a finished recording produced.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RecordingSummary {
+    pub segments: Vec<PathBuf>,
+    pub duration_ms: u64,
+    pub packets: u64,
+    /// RTP packets missing from the sequence; frames they belonged to are
+    /// dropped until the next keyframe.
+    pub lost_packets: u64,
+    pub resolution_changes: u64,
+}
+
+/// Path of segment `index`: the output itself for the first segment, then
+/// `name-001.ext`, `name-002.ext`, ...
+pub fn segment_path(output: &Path, index: u32) -> PathBuf {
+    if index == 0 {
+        return output.to_path_buf();
+    }
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match output.extension() {
+        Some(ext) => format!("{}-{:03}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}-{:03}", stem, index),
+    };
+    output.with_file_name(name)
+}
+
+#[derive(Default)]
+struct Counters {
+    packets: AtomicU64,
+    lost_packets: AtomicU64,
+    resolution_changes: AtomicU64,
+}
+
+/// A running recording. Call `stop` to finish the file; dropping the
+/// recorder aborts it and may leave an unplayable MP4.
+pub struct VideoRecorder {
+    pipeline: gst::Pipeline,
+    appsrc: gst_app::AppSrc,
+    splitmux: gst::Element,
+    feed_task: JoinHandle<()>,
+    segments: Arc<Mutex<Vec<PathBuf>>>,
+    counters: Arc<Counters>,
+    started: Instant,
+}
+
+impl VideoRecorder {
+    /// Starts recording `capture`'s video track to `output`, waiting briefly
+    /// for the track if the connection is still coming up.
+    pub async fn start(
+        capture: &VideoFrameCapture,
+        output: impl AsRef<Path>,
+        options: &RecordingOptions,
+    ) -> AnyResult<Self> {
+        let mut rtp = capture.subscribe_rtp(TRACK_WAIT).await?;
+        gst::init()?;
+        let output = output.as_ref().to_path_buf();
+        let pipeline = gst::Pipeline::new();
+
+        let appsrc = gst::ElementFactory::make("appsrc")
+            .name("rtpsrc")
+            .property("format", gst::Format::Time)
+            .property("is-live", true)
+            .property("do-timestamp", true)
+            .property_from_str("stream-type", "stream")
+            .build()?;
+        let caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90000)
+            .field("encoding-name", "H264")
+            .field("payload", 102)
+            .build();
+        appsrc.set_property("caps", &caps);
+
+        let jitterbuffer = gst::ElementFactory::make("rtpjitterbuffer")
+            .property("latency", 200u32)
+            .property("do-lost", true)
+            .build()?;
+        let depay = gst::ElementFactory::make("rtph264depay")
+            .property("wait-for-keyframe", true)
+            .property("request-keyframe", true)
+            .build()?;
+        let parse = gst::ElementFactory::make("h264parse")
+            .property("config-interval", -1i32)
+            .build()?;
+        let splitmux = gst::ElementFactory::make("splitmuxsink")
+            .property("muxer-factory", options.format.muxer())
+            .property(
+                "max-size-time",
+                options
+                    .segment_duration
+                    .map_or(0, |duration| duration.as_nanos() as u64),
+            )
+            .property("send-keyframe-requests", true)
+            .build()?;
+
+        pipeline.add_many([&appsrc, &jitterbuffer, &depay, &parse, &splitmux])?;
+        gst::Element::link_many([&appsrc, &jitterbuffer, &depay, &parse, &splitmux])?;
+
+        let segments = Arc::new(Mutex::new(Vec::new()));
+        let segments_cb = Arc::clone(&segments);
+        splitmux.connect("format-location", false, move |args| {
+            let index = args[1].get::<u32>().unwrap_or(0);
+            let path = segment_path(&output, index);
+            info!("Recording segment {} to {}", index, path.display());
+            segments_cb.lock().unwrap().push(path.clone());
+            Some(path.to_string_lossy().into_owned().to_value())
+        });
+
+        let counters = Arc::new(Counters::default());
+        let parse_src = parse
+            .static_pad("src")
+            .ok_or_else(|| anyhow!("h264parse has no src pad"))?;
+        let splitmux_cb = splitmux.clone();
+        let counters_cb = Arc::clone(&counters);
+        let mut resolution: Option<(i32, i32)> = None;
+        parse_src.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::EventView::Caps(caps)) = info.event().map(|event| event.view()) {
+                let size = caps.caps().structure(0).and_then(|s| {
+                    Some((s.get::<i32>("width").ok()?, s.get::<i32>("height").ok()?))
+                });
+                if let Some(size) = size {
+                    if resolution.is_some_and(|previous| previous != size) {
+                        info!("Resolution changed to {}x{}; new segment", size.0, size.1);
+                        counters_cb
+                            .resolution_changes
+                            .fetch_add(1, Ordering::Relaxed);
+                        splitmux_cb.emit_by_name::<()>("split-now", &[]);
+                    }
+                    resolution = Some(size);
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        let appsrc = appsrc
+            .dynamic_cast::<gst_app::AppSrc>()
+            .map_err(|_| anyhow!("appsrc is not an AppSrc"))?;
+        pipeline.set_state(gst::State::Playing)?;
+        info!("Recording started ({:?})", options.format);
+
+        let appsrc_feed = appsrc.clone();
+        let counters_feed = Arc::clone(&counters);
+        let keyframes = capture.keyframe_requester();
+        if let Err(e) = keyframes.request(rtp.track()).await {
+            debug!("Keyframe request failed: {}", e);
+        }
+        let feed_task = tokio::spawn(async move {
+            let mut sequence = SequenceTracker::default();
+            while let Some(packet) = rtp.recv().await {
+                let lost = sequence.observe(packet.header.sequence_number);
+                if lost > 0 {
+                    debug!(
+                        "{} RTP packets lost before #{}",
+                        lost, packet.header.sequence_number
+                    );
+                    counters_feed
+                        .lost_packets
+                        .fetch_add(lost, Ordering::Relaxed);
+                    if let Err(e) = keyframes.request_after_loss(rtp.track()).await {
+                        debug!("Keyframe request failed: {}", e);
+                    }
+                }
+                counters_feed.packets.fetch_add(1, Ordering::Relaxed);
+                if appsrc_feed
+                    .push_buffer(gst::Buffer::from_slice(serialize_rtp(&packet)))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            pipeline,
+            appsrc,
+            splitmux,
+            feed_task,
+            segments,
+            counters,
+            started: Instant::now(),
+        })
+    }
+
+    /// Closes the current segment at the next keyframe and starts a new one.
+    pub fn split_now(&self) {
+        self.splitmux.emit_by_name::<()>("split-now", &[]);
+    }
+
+    /// Time since the recording started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Stops reading the track, lets the muxer finish the current file and
+    /// returns what was recorded.
+    pub async fn stop(self) -> AnyResult<RecordingSummary> {
+        self.feed_task.abort();
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        let _ = self.appsrc.end_of_stream();
+
+        let bus = self
+            .pipeline
+            .bus()
+            .ok_or_else(|| anyhow!("Recording pipeline has no bus"))?;
+        let finished = tokio::task::spawn_blocking(move || {
+            let timeout = gst::ClockTime::from_mseconds(FINALIZE_TIMEOUT.as_millis() as u64);
+            match bus.timed_pop_filtered(timeout, &[gst::MessageType::Eos, gst::MessageType::Error])
+            {
+                Some(message) => match message.view() {
+                    gst::MessageView::Error(err) => {
+                        Err(anyhow!("Recording failed: {}", err.error()))
+                    }
+                    _ => Ok(()),
+                },
+                None => Err(anyhow!("Timed out finishing the recording")),
+            }
+        })
+        .await?;
+        if let Err(e) = self.pipeline.set_state(gst::State::Null) {
+            warn!("Failed to stop recording pipeline cleanly: {:?}", e);
+        }
+        finished?;
+
+        let segments = self.segments.lock().unwrap().clone();
+        info!("Recording stopped: {} segment(s)", segments.len());
+        Ok(RecordingSummary {
+            segments,
+            duration_ms,
+            packets: self.counters.packets.load(Ordering::Relaxed),
+            lost_packets: self.counters.lost_packets.load(Ordering::Relaxed),
+            resolution_changes: self.counters.resolution_changes.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl Drop for VideoRecorder {
+    fn drop(&mut self) {
+        self.feed_task.abort();
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+/// Records for `duration` and returns the summary.
+pub async fn record_for(
+    capture: &VideoFrameCapture,
+    output: impl AsRef<Path>,
+    duration: Duration,
+    options: &RecordingOptions,
+) -> AnyResult<RecordingSummary> {
+    let recorder = VideoRecorder::start(capture, output, options).await?;
+    sleep(duration).await;
+    recorder.stop().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_path() {
+        let output = Path::new("/tmp/run/boot.mp4");
+        assert_eq!(segment_path(output, 0), output);
+        assert_eq!(segment_path(output, 1), Path::new("/tmp/run/boot-001.mp4"));
+        assert_eq!(
+            segment_path(Path::new("capture"), 12),
+            Path::new("capture-012")
+        );
+        assert_eq!(
+            RecordingFormat::from_path(Path::new("a.MKV")),
+            Some(RecordingFormat::Mkv)
+        );
+        assert_eq!(RecordingFormat::from_path(Path::new("a.avi")), None);
+    }
+}
@@ -0,0 +1,313 @@
+//! Reverse of `text_to_macro`: turns keyboard reports back into text.
+//!
+//! Each newly pressed key is looked up in the layout together with its Shift
+//! and AltGr state. Key presses that start a longer sequence (dead keys, the
+//! compose key) are held back until the sequence completes, so compositions
+//! such as `´` + `a` = `á` decode to a single character. Keys that produce no
+//! character in the layout, and keys pressed with Ctrl, Alt or GUI, are
+//! reported as named keys.
+
+use crate::keyboard_layout::KeyboardLayout;
+use crate::keyboard_mappings::{key_name_to_hid, KEYS};
+use crate::keyboard_state::{KeyboardReport, ERROR_ROLL_OVER};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+const SHIFT_MASK: u8 = 0x22;
+const ALT_RIGHT_MASK: u8 = 0x40;
+/// Ctrl, left Alt and GUI: a key pressed with any of these is a shortcut.
+const SHORTCUT_MASK: u8 = !(SHIFT_MASK | ALT_RIGHT_MASK);
+
+/// A key press as the layout sees it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Stroke {
+    key: u8,
+    shift: bool,
+    alt_right: bool,
+}
+
+impl Stroke {
+    fn new(key: u8, modifier: u8) -> Self {
+        Self {
+            key,
+            shift: modifier & SHIFT_MASK != 0,
+            alt_right: modifier & ALT_RIGHT_MASK != 0,
+        }
+    }
+
+    fn modifier(&self) -> u8 {
+        (if self.shift { 0x02 } else { 0 }) | (if self.alt_right { ALT_RIGHT_MASK } else { 0 })
+    }
+}
+
+/// One decoded key press.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodedKey {
+    Char(char),
+    /// A key with no character in the layout, or a shortcut. `name` is the
+    /// key name from `keyboard_mappings::KEYS`.
+    Named {
+        name: String,
+        modifier: u8,
+    },
+}
+
+impl fmt::Display for DecodedKey {
+    /// Characters print as themselves, named keys as e.g. `<Ctrl+Shift+KeyT>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodedKey::Char(c) => write!(f, "{}", c),
+            DecodedKey::Named { name, modifier } => {
+                write!(f, "<")?;
+                for (mask, label) in [
+                    (0x11, "Ctrl"),
+                    (0x04, "Alt"),
+                    (0x40, "AltGr"),
+                    (0x22, "Shift"),
+                    (0x88, "Meta"),
+                ] {
+                    if modifier & mask != 0 {
+                        write!(f, "{}+", label)?;
+                    }
+                }
+                write!(f, "{}>", name)
+            }
+        }
+    }
+}
+
+/// Streaming decoder: feed reports in order with `push`, then `finish`.
+pub struct ReportDecoder {
+    sequences: HashMap<Vec<Stroke>, char>,
+    /// Proper prefixes of `sequences`, i.e. strokes worth waiting on.
+    prefixes: HashSet<Vec<Stroke>>,
+    key_names: HashMap<u8, &'static str>,
+    pressed: Vec<u8>,
+    pending: Vec<Stroke>,
+    decoded: Vec<DecodedKey>,
+}
+
+impl ReportDecoder {
+    pub fn new(layout: &KeyboardLayout) -> Self {
+        let mut sequences = HashMap::new();
+        let mut prefixes = HashSet::new();
+
+        // Sorted so strokes shared by two characters always decode the same way.
+        let mut chars: Vec<char> = layout.chars.keys().copied().collect();
+        chars.sort();
+        for c in chars {
+            let Ok(strokes) = layout.strokes_for(c) else {
+                continue;
+            };
+            let Some(sequence) = strokes
+                .iter()
+                .map(|stroke| {
+                    key_name_to_hid(&stroke.key).map(|key| Stroke {
+                        key,
+                        shift: stroke.shift,
+                        alt_right: stroke.alt_right,
+                    })
+                })
+                .collect::<Option<Vec<Stroke>>>()
+            else {
+                continue;
+            };
+            for len in 1..sequence.len() {
+                prefixes.insert(sequence[..len].to_vec());
+            }
+            sequences.entry(sequence).or_insert(c);
+        }
+
+        let mut key_names: HashMap<u8, &'static str> = HashMap::new();
+        for (&name, &hid) in KEYS.iter() {
+            let entry = key_names.entry(hid).or_insert(name);
+            if name < *entry {
+                *entry = name;
+            }
+        }
+
+        Self {
+            sequences,
+            prefixes,
+            key_names,
+            pressed: Vec::new(),
+            pending: Vec::new(),
+            decoded: Vec::new(),
+        }
+    }
+
+    /// Decodes the keys newly pressed in `report`. Releases, held keys and
+    /// `ErrorRollOver` reports produce nothing.
+    pub fn push(&mut self, report: &KeyboardReport) {
+        if report.keys.contains(&ERROR_ROLL_OVER) {
+            return;
+        }
+        let newly_pressed: Vec<u8> = report
+            .keys
+            .iter()
+            .copied()
+            .filter(|&k| k != 0 && !self.pressed.contains(&k))
+            .collect();
+        self.pressed = report.keys.iter().copied().filter(|&k| k != 0).collect();
+
+        for key in newly_pressed {
+            if report.modifier & SHORTCUT_MASK != 0 {
+                self.resolve(true);
+                self.push_named(key, report.modifier);
+            } else {
+                self.pending.push(Stroke::new(key, report.modifier));
+                self.resolve(false);
+            }
+        }
+    }
+
+    /// Flushes an unfinished sequence and returns everything decoded.
+    pub fn finish(mut self) -> Vec<DecodedKey> {
+        self.resolve(true);
+        self.decoded
+    }
+
+    /// Decodes pending strokes. Unless `flush` is set, stops while the pending
+    /// strokes could still grow into a longer sequence.
+    fn resolve(&mut self, flush: bool) {
+        while !self.pending.is_empty() {
+            if !flush && self.prefixes.contains(&self.pending) {
+                return;
+            }
+            let complete = (1..=self.pending.len())
+                .rev()
+                .find(|&len| self.sequences.contains_key(&self.pending[..len]));
+            match complete {
+                Some(len) => {
+                    let c = self.sequences[&self.pending[..len]];
+                    self.decoded.push(DecodedKey::Char(c));
+                    self.pending.drain(..len);
+                }
+                None => {
+                    let stroke = self.pending.remove(0);
+                    self.push_named(stroke.key, stroke.modifier());
+                }
+            }
+        }
+    }
+
+    fn push_named(&mut self, key: u8, modifier: u8) {
+        let name = self
+            .key_names
+            .get(&key)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("{:#04x}", key));
+        self.decoded.push(DecodedKey::Named { name, modifier });
+    }
+}
+
+/// Decodes a whole report sequence.
+pub fn decode_reports(reports: &[KeyboardReport], layout: &KeyboardLayout) -> Vec<DecodedKey> {
+    let mut decoder = ReportDecoder::new(layout);
+    for report in reports {
+        decoder.push(report);
+    }
+    decoder.finish()
+}
+
+/// Decodes a report sequence into text, with named keys rendered as `<Name>`.
+pub fn reports_to_text(reports: &[KeyboardReport], layout: &KeyboardLayout) -> String {
+    decode_reports(reports, layout)
+        .iter()
+        .map(|key| key.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::{layouts, DeadKeyFinish, KeyCombo, KeyStroke};
+    use crate::keyboard_state::{plan_macro_steps, ReportStep};
+    use crate::text_to_macro::text_to_macro_steps;
+
+    fn encode(text: &str, layout: &KeyboardLayout) -> Vec<KeyboardReport> {
+        let steps = text_to_macro_steps(text, layout, 20).unwrap();
+        plan_macro_steps(&steps)
+            .unwrap()
+            .into_iter()
+            .filter_map(|step| match step {
+                ReportStep::Report(report) => Some(report),
+                ReportStep::Sleep(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_every_char_of_every_layout() {
+        for layout in layouts::all_layouts() {
+            let mut all_chars: Vec<char> = layout.chars.keys().copied().collect();
+            all_chars.sort();
+            for &c in &all_chars {
+                let text = c.to_string();
+                assert_eq!(
+                    reports_to_text(&encode(&text, layout), layout),
+                    text,
+                    "layout {} char {:?}",
+                    layout.iso_code,
+                    c
+                );
+            }
+
+            let text: String = all_chars.iter().collect();
+            assert_eq!(
+                reports_to_text(&encode(&text, layout), layout),
+                text,
+                "layout {}",
+                layout.iso_code
+            );
+        }
+    }
+
+    #[test]
+    fn test_dead_key_compositions() {
+        let layout = &*layouts::ES_ES;
+        let text = "¡Ñoño comió açaí! Ü ^`";
+        assert_eq!(reports_to_text(&encode(text, layout), layout), text);
+    }
+
+    #[test]
+    fn test_multi_dead_key_and_compose_sequences() {
+        let circumflex = KeyCombo::new("Digit6").with_shift().with_dead_key();
+        let dot_below = KeyCombo::new("Digit9").with_dead_key();
+        let layout = KeyboardLayout::new("xx-XX", "Test")
+            .with_char('e', KeyCombo::new("KeyE"))
+            .with_char('\'', KeyCombo::new("Quote"))
+            .with_char('^', circumflex.clone())
+            .with_char(
+                'ệ',
+                KeyCombo::new("KeyE")
+                    .with_accent_key(circumflex.clone())
+                    .with_accent_key(dot_below),
+            )
+            .with_char('ê', KeyCombo::new("KeyE").with_accent_key(circumflex))
+            .with_char(
+                'é',
+                KeyCombo::compose(vec![KeyStroke::new("Quote"), KeyStroke::new("KeyE")]).unwrap(),
+            )
+            .with_compose_key(KeyStroke::new("ContextMenu"))
+            .with_dead_key_finish(DeadKeyFinish::Repeat);
+        let text = "eệ^ê'é'e";
+        assert_eq!(reports_to_text(&encode(text, &layout), &layout), text);
+    }
+
+    #[test]
+    fn test_named_keys_and_shortcuts() {
+        let layout = &*layouts::EN_US;
+        let reports = vec![
+            KeyboardReport::new(0x01, vec![0x06]),
+            KeyboardReport::new(0, vec![]),
+            KeyboardReport::new(0, vec![0x29]),
+            KeyboardReport::new(0, vec![]),
+            KeyboardReport::new(0, vec![0x04]),
+            KeyboardReport::new(0, vec![0x04, 0x05]),
+            KeyboardReport::new(0, vec![ERROR_ROLL_OVER; 6]),
+            KeyboardReport::new(0, vec![]),
+        ];
+        assert_eq!(reports_to_text(&reports, layout), "<Ctrl+KeyC><Escape>ab");
+    }
+}
@@ -0,0 +1,242 @@
+//! Waiting for the screen to change or to settle.
+//!
+//! Both waits poll the screenshot decoder's latest frame and compare small
+//! grayscale signatures of it (optionally of one region), the same measure
+//! `boot_into` uses: the mean per-pixel difference from 0.0 (identical) to
+//! 1.0 (black vs. white). Comparing thumbnails hides
+//! compression noise, so the thresholds stay meaningful across resolutions.
+//!
+//! `wait_for_change` returns once the screen differs from how it looked at
+//! the start by more than a threshold. `wait_for_stable` returns once it has
+//! stayed within a threshold of one frame for a given time; a screen that
+//! drifts slowly does not count as stable, because frames are compared with
+//! the start of the stable period rather than with their predecessor.
+
+use crate::screenshot::{frame_image, Region};
+use crate::video::{FrameFreshness, VideoFrameCapture};
+use anyhow::{bail, Result as AnyResult};
+use image::imageops;
+use image::{GrayImage, RgbaImage};
+use serde::Serialize;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::debug;
+
+/// Difference that counts as a changed screen.
+pub const DEFAULT_CHANGE_THRESHOLD: f64 = 0.1;
+/// Difference still counted as the same screen when waiting for it to
+/// settle; above compression noise, below a blinking caret in a dialog.
+pub const DEFAULT_STABLE_THRESHOLD: f64 = 0.01;
+pub const DEFAULT_STABLE_FOR_MS: u64 = 1_000;
+pub const DEFAULT_WAIT_TIMEOUT_MS: u64 = 30_000;
+
+/// Size of the grayscale signature frames are compared by.
+const SIGNATURE_WIDTH: u32 = 64;
+const SIGNATURE_HEIGHT: u32 = 36;
+/// Pause between two looks at the latest frame.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Small grayscale thumbnail of `image`, or of `region` of it.
+pub fn screen_signature(image: &RgbaImage, region: Option<Region>) -> AnyResult<GrayImage> {
+    let gray = match region {
+        Some(r) => {
+            r.ensure_within(image.width(), image.height())?;
+            imageops::grayscale(&*imageops::crop_imm(image, r.x, r.y, r.width, r.height))
+        }
+        None => imageops::grayscale(image),
+    };
+    Ok(imageops::thumbnail(
+        &gray,
+        SIGNATURE_WIDTH,
+        SIGNATURE_HEIGHT,
+    ))
+}
+
+/// Mean per-pixel difference between two signatures, 0.0..=1.0.
+pub fn signature_difference(a: &GrayImage, b: &GrayImage) -> f64 {
+    let total: u64 = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|(p, q)| p.0[0].abs_diff(q.0[0]) as u64)
+        .sum();
+    total as f64 / (a.len().max(1) as f64 * 255.0)
+}
+
+/// Decides when a sequence of signatures has settled.
+pub struct StabilityTracker {
+    threshold: f64,
+    stable_for: Duration,
+    reference: Option<(GrayImage, Instant)>,
+    largest_difference: f64,
+}
+
+impl StabilityTracker {
+    pub fn new(threshold: f64, stable_for: Duration) -> Self {
+        Self {
+            threshold,
+            stable_for,
+            reference: None,
+            largest_difference: 0.0,
+        }
+    }
+
+    /// Feeds the signature seen at `now`; returns true once the screen has
+    /// stayed within the threshold for `stable_for`.
+    pub fn observe(&mut self, signature: GrayImage, now: Instant) -> bool {
+        if let Some((reference, since)) = &self.reference {
+            let difference = signature_difference(reference, &signature);
+            if difference <= self.threshold {
+                self.largest_difference = self.largest_difference.max(difference);
+                return now.saturating_duration_since(*since) >= self.stable_for;
+            }
+        }
+        self.reference = Some((signature, now));
+        self.largest_difference = 0.0;
+        self.stable_for.is_zero()
+    }
+
+    /// Largest difference from the reference frame in the current stable
+    /// period.
+    pub fn largest_difference(&self) -> f64 {
+        self.largest_difference
+    }
+}
+
+/// How a wait ended.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ScreenWaitResult {
+    pub elapsed_ms: u64,
+    /// For a change, the difference that ended the wait; for a stable
+    /// screen, the largest difference seen during the stable period.
+    pub difference: f64,
+}
+
+/// Signature of the latest frame, or `None` if it is the frame seen last.
+async fn next_signature(
+    capture: &VideoFrameCapture,
+    region: Option<Region>,
+    last_seen: &mut Option<Instant>,
+) -> AnyResult<Option<GrayImage>> {
+    let frame = capture.latest_frame(FrameFreshness::Latest).await?;
+    if *last_seen == Some(frame.decoded_at) {
+        return Ok(None);
+    }
+    *last_seen = Some(frame.decoded_at);
+    screen_signature(&frame_image(&frame)?, region).map(Some)
+}
+
+/// Waits until the screen (or `region`) differs from how it looked when the
+/// call started by more than `threshold`.
+pub async fn wait_for_change(
+    capture: &VideoFrameCapture,
+    region: Option<Region>,
+    threshold: f64,
+    timeout: Duration,
+) -> AnyResult<ScreenWaitResult> {
+    let start = Instant::now();
+    let mut last_seen = None;
+    let baseline = loop {
+        if let Some(signature) = next_signature(capture, region, &mut last_seen).await? {
+            break signature;
+        }
+    };
+    loop {
+        if start.elapsed() >= timeout {
+            bail!("Screen did not change within {} ms", timeout.as_millis());
+        }
+        sleep(POLL_INTERVAL).await;
+        let Some(signature) = next_signature(capture, region, &mut last_seen).await? else {
+            continue;
+        };
+        let difference = signature_difference(&baseline, &signature);
+        debug!("Screen difference from start: {:.3}", difference);
+        if difference > threshold {
+            return Ok(ScreenWaitResult {
+                elapsed_ms: start.elapsed().as_millis() as u64,
+                difference,
+            });
+        }
+    }
+}
+
+/// Waits until the screen (or `region`) has stayed within `threshold` of
+/// one frame for `stable_for`. The device may stop sending frames while
+/// nothing changes, so time without new frames counts as stable.
+pub async fn wait_for_stable(
+    capture: &VideoFrameCapture,
+    region: Option<Region>,
+    threshold: f64,
+    stable_for: Duration,
+    timeout: Duration,
+) -> AnyResult<ScreenWaitResult> {
+    let start = Instant::now();
+    let mut tracker = StabilityTracker::new(threshold, stable_for);
+    let mut last_seen = None;
+    let mut last_signature: Option<GrayImage> = None;
+    loop {
+        let now = Instant::now();
+        let signature = match next_signature(capture, region, &mut last_seen).await? {
+            Some(signature) => signature,
+            None => match &last_signature {
+                Some(signature) => signature.clone(),
+                None => continue,
+            },
+        };
+        last_signature = Some(signature.clone());
+        if tracker.observe(signature, now) {
+            return Ok(ScreenWaitResult {
+                elapsed_ms: start.elapsed().as_millis() as u64,
+                difference: tracker.largest_difference(),
+            });
+        }
+        if start.elapsed() >= timeout {
+            bail!(
+                "Screen did not settle for {} ms within {} ms",
+                stable_for.as_millis(),
+                timeout.as_millis()
+            );
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn screen(width: u32, height: u32, pixel: impl Fn(u32, u32) -> u8) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let v = pixel(x, y);
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    #[test]
+    fn test_region_signature() {
+        let black = screen(640, 360, |_, _| 0);
+        // A white box in the top-left corner only.
+        let dialog = screen(640, 360, |x, y| if x < 100 && y < 50 { 255 } else { 0 });
+        let full = |image| screen_signature(image, None).unwrap();
+        let corner = |image| screen_signature(image, Some("100x50+0+0".parse().unwrap())).unwrap();
+
+        assert!(signature_difference(&full(&black), &full(&dialog)) < 0.05);
+        assert_eq!(signature_difference(&corner(&black), &corner(&dialog)), 1.0);
+        assert!(screen_signature(&black, Some("100x50+600+0".parse().unwrap())).is_err());
+    }
+
+    #[test]
+    fn test_stability_tracker() {
+        let black = screen_signature(&screen(64, 36, |_, _| 0), None).unwrap();
+        let white = screen_signature(&screen(64, 36, |_, _| 255), None).unwrap();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut tracker = StabilityTracker::new(0.01, Duration::from_millis(500));
+        assert!(!tracker.observe(black.clone(), at(0)));
+        assert!(!tracker.observe(black.clone(), at(300)));
+        // A change restarts the stable period.
+        assert!(!tracker.observe(white.clone(), at(400)));
+        assert!(!tracker.observe(white.clone(), at(800)));
+        assert!(tracker.observe(white, at(900)));
+    }
+}
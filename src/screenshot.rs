@@ -0,0 +1,316 @@
+//! Turning a decoded frame into a screenshot file.
+//!
+//! `render_screenshot` crops, downscales and optionally desaturates an RGBA
+//! `VideoFrame`, then encodes it as PNG, JPEG, WebP, PPM or raw RGBA bytes.
+//! The steps run in that order, so a crop region is always given in the
+//! coordinates of the full video frame.
+
+use crate::frame_stream::{FrameFormat, VideoFrame};
+use anyhow::{anyhow, bail, Result as AnyResult};
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use serde::Serialize;
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// File format of a screenshot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreenshotFormat {
+    #[default]
+    Png,
+    Jpeg,
+    /// Lossless WebP.
+    Webp,
+    /// Binary PPM (P6).
+    Ppm,
+    /// Bare RGBA pixels, four bytes per pixel, rows top to bottom.
+    Rgba,
+}
+
+impl ScreenshotFormat {
+    /// Picks the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "png" => Some(ScreenshotFormat::Png),
+            "jpg" | "jpeg" => Some(ScreenshotFormat::Jpeg),
+            "webp" => Some(ScreenshotFormat::Webp),
+            "ppm" => Some(ScreenshotFormat::Ppm),
+            "rgba" | "raw" => Some(ScreenshotFormat::Rgba),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "image/png",
+            ScreenshotFormat::Jpeg => "image/jpeg",
+            ScreenshotFormat::Webp => "image/webp",
+            ScreenshotFormat::Ppm => "image/x-portable-pixmap",
+            ScreenshotFormat::Rgba => "application/octet-stream",
+        }
+    }
+}
+
+/// A rectangle of the video frame, written `WIDTHxHEIGHT+X+Y` as in X11
+/// geometry strings (`640x480+100+50`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> AnyResult<Self> {
+        let invalid = || anyhow!("Invalid region {:?}, expected WIDTHxHEIGHT+X+Y", s);
+        let (size, offset) = s.split_once('+').ok_or_else(invalid)?;
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+        let number = |n: &str| n.trim().parse::<u32>().map_err(|_| invalid());
+        let region = Region {
+            x: number(x)?,
+            y: number(y)?,
+            width: number(width)?,
+            height: number(height)?,
+        };
+        if region.width == 0 || region.height == 0 {
+            bail!("Region {:?} is empty", s);
+        }
+        Ok(region)
+    }
+}
+
+impl Region {
+    /// Fails unless the region lies inside a `width`x`height` frame.
+    pub fn ensure_within(&self, width: u32, height: u32) -> AnyResult<()> {
+        let fits = self
+            .x
+            .checked_add(self.width)
+            .is_some_and(|right| right <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|bottom| bottom <= height);
+        if !fits {
+            bail!(
+                "Region {}x{}+{}+{} is outside the {}x{} frame",
+                self.width,
+                self.height,
+                self.x,
+                self.y,
+                width,
+                height
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScreenshotOptions {
+    pub format: ScreenshotFormat,
+    /// JPEG quality, 1..=100.
+    pub quality: u8,
+    pub crop: Option<Region>,
+    /// Shrink to fit within this size, keeping the aspect ratio. Images are
+    /// never enlarged.
+    pub max_size: Option<(u32, u32)>,
+    pub grayscale: bool,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        Self {
+            format: ScreenshotFormat::Png,
+            quality: DEFAULT_JPEG_QUALITY,
+            crop: None,
+            max_size: None,
+            grayscale: false,
+        }
+    }
+}
+
+/// An encoded screenshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    pub format: ScreenshotFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Size of a `width`x`height` image shrunk to fit within `max`, keeping the
+/// aspect ratio; unchanged if it already fits.
+pub fn fit_within(width: u32, height: u32, (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+    let scale = f64::min(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// Wraps an RGBA frame as an image.
+pub fn frame_image(frame: &VideoFrame) -> AnyResult<RgbaImage> {
+    if frame.format != FrameFormat::Rgba {
+        bail!("Screenshots need an RGBA frame, got {:?}", frame.format);
+    }
+    RgbaImage::from_raw(frame.width, frame.height, frame.data.clone())
+        .ok_or_else(|| anyhow!("Frame data does not match {}x{}", frame.width, frame.height))
+}
+
+/// Crops, scales and encodes `image` as `options` asks.
+pub fn render_screenshot(
+    mut image: RgbaImage,
+    options: &ScreenshotOptions,
+) -> AnyResult<Screenshot> {
+    if let Some(region) = options.crop {
+        region.ensure_within(image.width(), image.height())?;
+        image =
+            imageops::crop_imm(&image, region.x, region.y, region.width, region.height).to_image();
+    }
+    if let Some(max_size) = options.max_size {
+        let (width, height) = fit_within(image.width(), image.height(), max_size);
+        if (width, height) != image.dimensions() {
+            image = imageops::resize(&image, width, height, FilterType::Triangle);
+        }
+    }
+
+    let image = if options.grayscale {
+        DynamicImage::ImageLuma8(imageops::grayscale(&image))
+    } else {
+        DynamicImage::ImageRgba8(image)
+    };
+    let (width, height) = (image.width(), image.height());
+    let data = encode(&image, options)?;
+    Ok(Screenshot {
+        format: options.format,
+        width,
+        height,
+        data,
+    })
+}
+
+fn encode(image: &DynamicImage, options: &ScreenshotOptions) -> AnyResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let failed = |e: image::ImageError| anyhow!("Encoding the screenshot failed: {}", e);
+    match options.format {
+        ScreenshotFormat::Png => image
+            .write_to(&mut Cursor::new(&mut out), image::ImageFormat::Png)
+            .map_err(failed)?,
+        ScreenshotFormat::Webp => image
+            .write_to(&mut Cursor::new(&mut out), image::ImageFormat::WebP)
+            .map_err(failed)?,
+        ScreenshotFormat::Jpeg => {
+            // JPEG has no alpha channel.
+            let image = match image {
+                DynamicImage::ImageLuma8(_) => image.clone(),
+                _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+            };
+            JpegEncoder::new_with_quality(&mut out, options.quality.clamp(1, 100))
+                .encode_image(&image)
+                .map_err(failed)?;
+        }
+        ScreenshotFormat::Ppm => {
+            out.extend_from_slice(
+                format!("P6\n{} {}\n255\n", image.width(), image.height()).as_bytes(),
+            );
+            out.extend_from_slice(image.to_rgb8().as_raw());
+        }
+        ScreenshotFormat::Rgba => out = image.to_rgba8().into_raw(),
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 200, 255]))
+    }
+
+    #[test]
+    fn test_parse_region_and_fit() {
+        assert_eq!(
+            "640x480+100+50".parse::<Region>().unwrap(),
+            Region {
+                x: 100,
+                y: 50,
+                width: 640,
+                height: 480
+            }
+        );
+        assert!("640x480".parse::<Region>().is_err());
+        assert!("0x10+0+0".parse::<Region>().is_err());
+        assert!("ax10+0+0".parse::<Region>().is_err());
+
+        assert_eq!(fit_within(1920, 1080, (960, 960)), (960, 540));
+        assert_eq!(fit_within(1080, 1920, (960, 960)), (540, 960));
+        assert_eq!(fit_within(640, 480, (1920, 1080)), (640, 480));
+    }
+
+    #[test]
+    fn test_crop_scale_and_raw_formats() {
+        let options = ScreenshotOptions {
+            format: ScreenshotFormat::Rgba,
+            crop: Some("4x2+10+20".parse().unwrap()),
+            ..Default::default()
+        };
+        let shot = render_screenshot(gradient(64, 48), &options).unwrap();
+        assert_eq!((shot.width, shot.height), (4, 2));
+        assert_eq!(&shot.data[..8], &[10, 20, 200, 255, 11, 20, 200, 255]);
+
+        let options = ScreenshotOptions {
+            format: ScreenshotFormat::Ppm,
+            max_size: Some((32, 32)),
+            grayscale: true,
+            ..Default::default()
+        };
+        let shot = render_screenshot(gradient(64, 48), &options).unwrap();
+        assert_eq!((shot.width, shot.height), (32, 24));
+        let header = b"P6\n32 24\n255\n";
+        assert_eq!(&shot.data[..header.len()], header);
+        assert_eq!(shot.data.len(), header.len() + 32 * 24 * 3);
+        let pixel = &shot.data[header.len()..header.len() + 3];
+        assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+
+        let outside = ScreenshotOptions {
+            crop: Some("10x10+60+0".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(render_screenshot(gradient(64, 48), &outside).is_err());
+    }
+
+    #[test]
+    fn test_encoded_formats_decode_back() {
+        for (format, image_format) in [
+            (ScreenshotFormat::Png, image::ImageFormat::Png),
+            (ScreenshotFormat::Jpeg, image::ImageFormat::Jpeg),
+            (ScreenshotFormat::Webp, image::ImageFormat::WebP),
+        ] {
+            let options = ScreenshotOptions {
+                format,
+                ..Default::default()
+            };
+            let shot = render_screenshot(gradient(16, 8), &options).unwrap();
+            let decoded = image::load_from_memory_with_format(&shot.data, image_format).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (16, 8), "{:?}", format);
+        }
+    }
+}
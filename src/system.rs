@@ -53,3 +53,8 @@ pub async fn rpc_set_auto_update_state(client: &JetKvmRpcClient, enabled: bool)
 pub async fn rpc_get_timezones(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getTimezones", json!({})).await
 }
+
+/// The IANA timezone names the device knows.
+pub async fn get_timezones(client: &JetKvmRpcClient) -> AnyResult<Vec<String>> {
+    client.call("getTimezones", json!({})).await
+}
@@ -0,0 +1,582 @@
+//! Finding a reference image on the target screen.
+//!
+//! `match_template` slides a template over the frame (or a region of it) and
+//! scores every position by zero-mean normalised cross-correlation of the
+//! grayscale pixels: 1.0 is a perfect match, and the score does not change
+//! when the screen is uniformly brighter or darker than the template.
+//!
+//! Scoring every position at full resolution is too slow for a 1080p frame,
+//! so the search runs coarse-to-fine: both images are shrunk until the
+//! template is about `MIN_COARSE_SIZE` pixels on its short side, or further
+//! while the frame has more than `MAX_COARSE_POSITIONS` pixels, as long as
+//! a small template keeps `MIN_COARSE_TEMPLATE`. The best few positions
+//! there are found exhaustively, and only their neighbourhoods are scored
+//! again at full resolution. Optional extra scales resize the template for
+//! targets whose UI is scaled differently.
+//!
+//! `wait_for_image` and `click_image` poll the screenshot decoder's latest
+//! frame, and `click_image` clicks the centre of the match with the absolute
+//! mouse.
+
+use crate::jetkvm_rpc_client::JetKvmRpcClient;
+use crate::mouse::{click_at, MousePosition};
+use crate::screenshot::{frame_image, Region};
+use crate::video::{FrameFreshness, VideoFrameCapture};
+use anyhow::{anyhow, bail, Result as AnyResult};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use serde::Serialize;
+use std::path::Path;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::debug;
+
+/// Score a match needs to count as found.
+pub const DEFAULT_MATCH_THRESHOLD: f64 = 0.9;
+pub const DEFAULT_IMAGE_TIMEOUT_MS: u64 = 30_000;
+
+/// Short side of the template in the coarse search.
+const MIN_COARSE_SIZE: usize = 8;
+/// Smallest short side a template is shrunk to for a large frame; below
+/// this the coarse match is lost when the template sits off the block grid.
+const MIN_COARSE_TEMPLATE: usize = 4;
+/// Frame pixels the coarse search aims to stay under.
+const MAX_COARSE_POSITIONS: usize = 320 * 180;
+/// Largest shrink factor of the coarse search.
+const MAX_COARSE_FACTOR: usize = 8;
+/// Coarse positions re-scored at full resolution.
+const COARSE_CANDIDATES: usize = 5;
+/// Pause between two looks at the latest frame.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchOptions {
+    /// Lowest score (-1.0..=1.0) that counts as found.
+    pub threshold: f64,
+    /// Only search this region of the frame.
+    pub region: Option<Region>,
+    /// Template scales to try; 1.0 is the template as given.
+    pub scales: Vec<f64>,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_MATCH_THRESHOLD,
+            region: None,
+            scales: vec![1.0],
+        }
+    }
+}
+
+/// Where the template matched best, in frame pixels.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TemplateMatch {
+    /// Whether `confidence` reached the threshold.
+    pub found: bool,
+    pub confidence: f64,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Template scale that matched.
+    pub scale: f64,
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+impl TemplateMatch {
+    /// Centre of the match, in frame pixels.
+    pub fn center(&self) -> (f64, f64) {
+        (
+            self.x as f64 + self.width as f64 / 2.0,
+            self.y as f64 + self.height as f64 / 2.0,
+        )
+    }
+
+    /// Centre of the match as a pointer position. Normalised, so it does
+    /// not depend on the device reporting the same resolution as the frame.
+    pub fn center_position(&self) -> MousePosition {
+        let (x, y) = self.center();
+        MousePosition::Normalized {
+            x: x / self.frame_width.max(1) as f64,
+            y: y / self.frame_height.max(1) as f64,
+        }
+    }
+}
+
+/// Loads a template image from a file.
+pub fn load_template(path: impl AsRef<Path>) -> AnyResult<RgbaImage> {
+    let path = path.as_ref();
+    Ok(image::open(path)
+        .map_err(|e| anyhow!("Failed to load template {}: {}", path.display(), e))?
+        .to_rgba8())
+}
+
+/// Grayscale pixels as floats.
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl Plane {
+    fn from_image(image: &RgbaImage) -> Self {
+        let gray = imageops::grayscale(image);
+        Plane {
+            width: gray.width() as usize,
+            height: gray.height() as usize,
+            data: gray.into_raw().into_iter().map(f32::from).collect(),
+        }
+    }
+
+    /// Shrinks by `factor` on both axes, averaging each block.
+    fn downsample(&self, factor: usize) -> Plane {
+        let (width, height) = (self.width / factor, self.height / factor);
+        let mut data = Vec::with_capacity(width * height);
+        let area = (factor * factor) as f32;
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                for row in y * factor..(y + 1) * factor {
+                    let start = row * self.width + x * factor;
+                    sum += self.data[start..start + factor].iter().sum::<f32>();
+                }
+                data.push(sum / area);
+            }
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// Running sums of a plane and of its squares, for window statistics in
+/// constant time.
+struct Integral {
+    stride: usize,
+    sum: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl Integral {
+    fn new(plane: &Plane) -> Self {
+        let stride = plane.width + 1;
+        let mut sum = vec![0.0; stride * (plane.height + 1)];
+        let mut squares = sum.clone();
+        for y in 0..plane.height {
+            let (mut row_sum, mut row_squares) = (0.0, 0.0);
+            for x in 0..plane.width {
+                let v = plane.data[y * plane.width + x] as f64;
+                row_sum += v;
+                row_squares += v * v;
+                let i = (y + 1) * stride + x + 1;
+                sum[i] = sum[i - stride] + row_sum;
+                squares[i] = squares[i - stride] + row_squares;
+            }
+        }
+        Integral {
+            stride,
+            sum,
+            squares,
+        }
+    }
+
+    /// Sum and sum of squares of the `width`x`height` window at `x`,`y`.
+    fn window(&self, x: usize, y: usize, width: usize, height: usize) -> (f64, f64) {
+        let at = |table: &[f64], x: usize, y: usize| table[y * self.stride + x];
+        let total = |table: &[f64]| {
+            at(table, x + width, y + height) - at(table, x, y + height) - at(table, x + width, y)
+                + at(table, x, y)
+        };
+        (total(&self.sum), total(&self.squares))
+    }
+}
+
+/// A template with its mean removed, ready for correlation.
+struct Template {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+    norm: f64,
+}
+
+impl Template {
+    /// `None` if the template is a flat colour, which matches anywhere.
+    fn new(plane: &Plane) -> Option<Self> {
+        let mean = plane.data.iter().map(|&v| v as f64).sum::<f64>() / plane.data.len() as f64;
+        let data: Vec<f32> = plane.data.iter().map(|&v| v - mean as f32).collect();
+        let norm = data.iter().map(|&v| (v as f64).powi(2)).sum::<f64>().sqrt();
+        (norm > 1e-6).then_some(Template {
+            width: plane.width,
+            height: plane.height,
+            data,
+            norm,
+        })
+    }
+}
+
+/// Normalised cross-correlation of `template` with the window at `x`,`y`.
+fn score_at(image: &Plane, integral: &Integral, template: &Template, x: usize, y: usize) -> f64 {
+    let mut cross = 0.0f64;
+    for row in 0..template.height {
+        let image_row = &image.data[(y + row) * image.width + x..][..template.width];
+        let template_row = &template.data[row * template.width..][..template.width];
+        cross += image_row
+            .iter()
+            .zip(template_row)
+            .map(|(&i, &t)| i * t)
+            .sum::<f32>() as f64;
+    }
+    let (sum, squares) = integral.window(x, y, template.width, template.height);
+    let variance = squares - sum * sum / (template.width * template.height) as f64;
+    if variance <= 1e-6 {
+        return 0.0;
+    }
+    (cross / (variance.sqrt() * template.norm)).clamp(-1.0, 1.0)
+}
+
+/// Best-scoring position among `xs` x `ys`.
+fn best_in(
+    image: &Plane,
+    integral: &Integral,
+    template: &Template,
+    xs: std::ops::RangeInclusive<usize>,
+    ys: std::ops::RangeInclusive<usize>,
+) -> Option<(f64, usize, usize)> {
+    let mut best: Option<(f64, usize, usize)> = None;
+    for y in ys {
+        for x in xs.clone() {
+            let score = score_at(image, integral, template, x, y);
+            if best.is_none_or(|(top, _, _)| score > top) {
+                best = Some((score, x, y));
+            }
+        }
+    }
+    best
+}
+
+/// Shrink factor of the coarse search for an image and a template of the
+/// given sizes.
+fn coarse_factor(image: (usize, usize), template: (usize, usize)) -> usize {
+    let short = template.0.min(template.1);
+    let for_template = short / MIN_COARSE_SIZE;
+    let for_image = ((image.0 * image.1) as f64 / MAX_COARSE_POSITIONS as f64)
+        .sqrt()
+        .ceil() as usize;
+    for_template
+        .max(for_image.min(short / MIN_COARSE_TEMPLATE))
+        .clamp(1, MAX_COARSE_FACTOR)
+}
+
+/// Best position of `template` in `image`, coarse-to-fine.
+fn search(image: &Plane, template: &Plane) -> Option<(f64, usize, usize)> {
+    if template.width > image.width || template.height > image.height {
+        return None;
+    }
+    let (max_x, max_y) = (image.width - template.width, image.height - template.height);
+    let full = Template::new(template)?;
+    let integral = Integral::new(image);
+
+    let factor = coarse_factor(
+        (image.width, image.height),
+        (template.width, template.height),
+    );
+    let coarse_template = (factor > 1)
+        .then(|| Template::new(&template.downsample(factor)))
+        .flatten();
+    let Some(coarse_template) = coarse_template else {
+        return best_in(image, &integral, &full, 0..=max_x, 0..=max_y);
+    };
+
+    let coarse = image.downsample(factor);
+    let coarse_integral = Integral::new(&coarse);
+    let mut scores = Vec::new();
+    for y in 0..=coarse.height - coarse_template.height {
+        for x in 0..=coarse.width - coarse_template.width {
+            let score = score_at(&coarse, &coarse_integral, &coarse_template, x, y);
+            scores.push((score, x, y));
+        }
+    }
+    scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    // Keep the best few positions that are not neighbours of a better one.
+    let (apart_x, apart_y) = (coarse_template.width / 2, coarse_template.height / 2);
+    let mut candidates: Vec<(usize, usize)> = Vec::new();
+    for &(_, x, y) in &scores {
+        if candidates.len() == COARSE_CANDIDATES {
+            break;
+        }
+        if candidates
+            .iter()
+            .all(|&(cx, cy)| cx.abs_diff(x) > apart_x || cy.abs_diff(y) > apart_y)
+        {
+            candidates.push((x, y));
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter_map(|(x, y)| {
+            let (x, y) = (x * factor, y * factor);
+            best_in(
+                image,
+                &integral,
+                &full,
+                x.saturating_sub(factor)..=(x + factor).min(max_x),
+                y.saturating_sub(factor)..=(y + factor).min(max_y),
+            )
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Finds where `template` best matches `image`. Returns `None` only if the
+/// template is larger than the searched area at every scale.
+pub fn match_template(
+    image: &RgbaImage,
+    template: &RgbaImage,
+    options: &MatchOptions,
+) -> AnyResult<Option<TemplateMatch>> {
+    if options.scales.is_empty() {
+        bail!("No template scales to try");
+    }
+    let (offset_x, offset_y, area) = match options.region {
+        Some(region) => {
+            region.ensure_within(image.width(), image.height())?;
+            let area = imageops::crop_imm(image, region.x, region.y, region.width, region.height)
+                .to_image();
+            (region.x, region.y, Plane::from_image(&area))
+        }
+        None => (0, 0, Plane::from_image(image)),
+    };
+    if Template::new(&Plane::from_image(template)).is_none() {
+        bail!("Template is a single flat colour and would match anywhere");
+    }
+
+    let mut best: Option<TemplateMatch> = None;
+    for &scale in &options.scales {
+        if !(scale > 0.0 && scale.is_finite()) {
+            bail!("Invalid template scale {}", scale);
+        }
+        let (width, height) = (
+            (template.width() as f64 * scale).round() as u32,
+            (template.height() as f64 * scale).round() as u32,
+        );
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let scaled = if (width, height) == template.dimensions() {
+            Plane::from_image(template)
+        } else {
+            Plane::from_image(&imageops::resize(
+                template,
+                width,
+                height,
+                FilterType::Triangle,
+            ))
+        };
+        let Some((confidence, x, y)) = search(&area, &scaled) else {
+            continue;
+        };
+        debug!(
+            "Template at scale {}: best {:.3} at {},{}",
+            scale, confidence, x, y
+        );
+        if best.as_ref().is_none_or(|b| confidence > b.confidence) {
+            best = Some(TemplateMatch {
+                found: confidence >= options.threshold,
+                confidence,
+                x: offset_x + x as u32,
+                y: offset_y + y as u32,
+                width,
+                height,
+                scale,
+                frame_width: image.width(),
+                frame_height: image.height(),
+            });
+        }
+    }
+    Ok(best)
+}
+
+/// Best match of `template` in the latest frame.
+pub async fn find_image(
+    capture: &VideoFrameCapture,
+    template: &RgbaImage,
+    options: &MatchOptions,
+) -> AnyResult<Option<TemplateMatch>> {
+    let frame = capture.latest_frame(FrameFreshness::Latest).await?;
+    let image = frame_image(&frame)?;
+    let (template, options) = (template.clone(), options.clone());
+    tokio::task::spawn_blocking(move || match_template(&image, &template, &options)).await?
+}
+
+/// Waits until `template` appears on screen with at least the threshold
+/// score.
+pub async fn wait_for_image(
+    capture: &VideoFrameCapture,
+    template: &RgbaImage,
+    options: &MatchOptions,
+    timeout: Duration,
+) -> AnyResult<TemplateMatch> {
+    let start = Instant::now();
+    let mut last_seen = None;
+    loop {
+        let frame = capture.latest_frame(FrameFreshness::Latest).await?;
+        if last_seen != Some(frame.decoded_at) {
+            last_seen = Some(frame.decoded_at);
+            let image = frame_image(&frame)?;
+            let (template, options) = (template.clone(), options.clone());
+            let best =
+                tokio::task::spawn_blocking(move || match_template(&image, &template, &options))
+                    .await??;
+            match best {
+                Some(best) if best.found => return Ok(best),
+                Some(best) => debug!("Best template match so far: {:.3}", best.confidence),
+                None => bail!("Template is larger than the searched area"),
+            }
+        }
+        if start.elapsed() >= timeout {
+            bail!(
+                "Image not found on screen within {} ms",
+                timeout.as_millis()
+            );
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Waits for `template` to appear and clicks `buttons` (a mask) on its
+/// centre.
+pub async fn click_image(
+    client: &JetKvmRpcClient,
+    template: &RgbaImage,
+    options: &MatchOptions,
+    timeout: Duration,
+    buttons: u64,
+) -> AnyResult<TemplateMatch> {
+    let found = wait_for_image(&client.video_capture, template, options, timeout).await?;
+    click_at(client, found.center_position(), buttons).await?;
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A dark screen with some texture and a "button" at `x`,`y`.
+    fn screen_with_button(x: u32, y: u32) -> RgbaImage {
+        let mut screen = RgbaImage::from_fn(320, 200, |px, py| {
+            let v = ((px * 7 + py * 13) % 40) as u8;
+            Rgba([v, v, v, 255])
+        });
+        imageops::overlay(&mut screen, &button(), x as i64, y as i64);
+        screen
+    }
+
+    fn button() -> RgbaImage {
+        RgbaImage::from_fn(48, 20, |x, y| {
+            let border = x < 2 || y < 2 || x >= 46 || y >= 18;
+            let label = (10..38).contains(&x) && (8..12).contains(&y) && x % 4 != 0;
+            let v = if border {
+                230
+            } else if label {
+                20
+            } else {
+                160
+            };
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    #[test]
+    fn test_finds_template_coarse_to_fine() {
+        let screen = screen_with_button(201, 137);
+        let found = match_template(&screen, &button(), &MatchOptions::default())
+            .unwrap()
+            .unwrap();
+        assert!(found.found);
+        assert!(found.confidence > 0.99, "{}", found.confidence);
+        assert_eq!(
+            (found.x, found.y, found.width, found.height),
+            (201, 137, 48, 20)
+        );
+        assert_eq!(found.center(), (225.0, 147.0));
+
+        let elsewhere = MatchOptions {
+            region: Some("100x100+0+0".parse().unwrap()),
+            ..Default::default()
+        };
+        let best = match_template(&screen, &button(), &elsewhere)
+            .unwrap()
+            .unwrap();
+        assert!(!best.found);
+        assert!(best.x < 100 && best.y < 100);
+    }
+
+    #[test]
+    fn test_small_template_on_large_frame() {
+        assert_eq!(coarse_factor((320, 200), (48, 20)), 2);
+        assert_eq!(coarse_factor((1920, 1080), (12, 12)), 3);
+        assert_eq!(coarse_factor((1920, 1080), (200, 100)), 8);
+        assert_eq!(coarse_factor((1920, 1080), (8, 8)), 2);
+        assert_eq!(coarse_factor((1920, 1080), (7, 7)), 1);
+
+        // A bright arrow on a mid-grey square, off the coarse grid.
+        for size in [8, 12] {
+            let icon = RgbaImage::from_fn(size, size, |x, y| {
+                let v = if x >= 2 && y >= 2 && x.abs_diff(y) <= 2 && x + y <= size + 6 {
+                    250
+                } else {
+                    120
+                };
+                Rgba([v, v, v, 255])
+            });
+            for (x, y) in [(1001, 533), (0, 0), (641, 359), (1267, 707)] {
+                let mut screen = RgbaImage::from_fn(1280, 720, |px, py| {
+                    let v = ((px * 7 + py * 13) % 40) as u8;
+                    Rgba([v, v, v, 255])
+                });
+                imageops::overlay(&mut screen, &icon, x as i64, y as i64);
+                let found = match_template(&screen, &icon, &MatchOptions::default())
+                    .unwrap()
+                    .unwrap();
+                assert!(
+                    found.found,
+                    "{}px at {},{}: {}",
+                    size, x, y, found.confidence
+                );
+                assert_eq!((found.x, found.y), (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_scale_and_invalid_templates() {
+        // The target renders the button at 1.5x.
+        let big = imageops::resize(&button(), 72, 30, FilterType::Triangle);
+        let mut screen = screen_with_button(0, 0);
+        imageops::overlay(&mut screen, &big, 150, 60);
+        let options = MatchOptions {
+            region: Some("200x140+100+40".parse().unwrap()),
+            scales: vec![1.0, 1.25, 1.5],
+            ..Default::default()
+        };
+        let found = match_template(&screen, &button(), &options)
+            .unwrap()
+            .unwrap();
+        assert!(found.found, "{}", found.confidence);
+        assert_eq!(found.scale, 1.5);
+        assert!(found.x.abs_diff(150) <= 1 && found.y.abs_diff(60) <= 1);
+
+        let flat = RgbaImage::from_pixel(10, 10, Rgba([9, 9, 9, 255]));
+        assert!(match_template(&screen, &flat, &MatchOptions::default()).is_err());
+        let huge = RgbaImage::from_fn(400, 10, |x, _| Rgba([x as u8, 0, 0, 255]));
+        assert_eq!(
+            match_template(&screen, &huge, &MatchOptions::default()).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,766 @@
+//! Reading text-mode screens without OCR.
+//!
+//! BIOS setup, boot loaders and the Linux console draw text as a grid of
+//! fixed-size glyphs from a known font, so each cell can be recognised by
+//! comparing it with every glyph of that font. `detect_grid` finds the cell
+//! size (the font's width, or 9 pixels for an 8-pixel VGA font in 720-pixel
+//! modes), an integer scale for upscaled modes, and where the grid starts,
+//! by comparing the frame's edges with those the font's glyphs would make.
+//! `read_grid` then thresholds each cell between its darkest and brightest
+//! pixels and picks the glyph with the fewest differing pixels, trying both
+//! polarities so highlighted (inverse) text reads the same. Cells that match
+//! no glyph well read as U+FFFD.
+//!
+//! `TextReader` keeps the detected grid and detects it again only when it
+//! stops matching, so polling with `wait_for_text` stays cheap.
+
+use crate::screenshot::frame_image;
+use crate::vga_font::{Font, Glyph};
+use crate::video::{FrameFreshness, VideoFrameCapture};
+use anyhow::{bail, Result as AnyResult};
+use image::RgbaImage;
+use serde::Serialize;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::debug;
+
+pub const DEFAULT_TEXT_TIMEOUT_MS: u64 = 30_000;
+
+/// Largest integer upscaling of the text mode that is looked for.
+const MAX_SCALE: u32 = 4;
+const MIN_COLUMNS: u32 = 40;
+const MIN_ROWS: u32 = 12;
+/// Brightness difference between neighbouring pixels that counts as an edge
+/// when looking for the grid.
+const EDGE_THRESHOLD: u8 = 48;
+/// Cells with less contrast than this are blank.
+const MIN_CELL_CONTRAST: f32 = 48.0;
+/// Ink overlap with the best glyph below which a cell is unknown.
+const MIN_GLYPH_SCORE: f64 = 0.8;
+/// Mean glyph score a grid needs to count as a text screen.
+const MIN_GRID_SCORE: f64 = 0.9;
+/// Non-blank cells sampled to score a candidate grid.
+const SAMPLE_CELLS: usize = 160;
+/// Prime step for sampling cells, see `score_grid`.
+const SCATTER_STEP: u64 = 7919;
+/// Grid origins per axis tried for every cell size.
+const ORIGIN_CANDIDATES: usize = 3;
+/// Pause between two looks at the latest frame.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Position and size of a character grid, in frame pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TextGrid {
+    pub x: u32,
+    pub y: u32,
+    pub columns: u32,
+    pub rows: u32,
+    /// Cell size in font pixels; a 9-pixel cell holds an 8-pixel glyph.
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// Frame pixels per font pixel.
+    pub scale: u32,
+}
+
+/// The 16 colours of the VGA text-mode palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VgaColor {
+    Black,
+    Blue,
+    Green,
+    Cyan,
+    Red,
+    Magenta,
+    Brown,
+    LightGray,
+    DarkGray,
+    LightBlue,
+    LightGreen,
+    LightCyan,
+    LightRed,
+    LightMagenta,
+    Yellow,
+    White,
+}
+
+const VGA_PALETTE: [(VgaColor, [f32; 3]); 16] = [
+    (VgaColor::Black, [0.0, 0.0, 0.0]),
+    (VgaColor::Blue, [0.0, 0.0, 170.0]),
+    (VgaColor::Green, [0.0, 170.0, 0.0]),
+    (VgaColor::Cyan, [0.0, 170.0, 170.0]),
+    (VgaColor::Red, [170.0, 0.0, 0.0]),
+    (VgaColor::Magenta, [170.0, 0.0, 170.0]),
+    (VgaColor::Brown, [170.0, 85.0, 0.0]),
+    (VgaColor::LightGray, [170.0, 170.0, 170.0]),
+    (VgaColor::DarkGray, [85.0, 85.0, 85.0]),
+    (VgaColor::LightBlue, [85.0, 85.0, 255.0]),
+    (VgaColor::LightGreen, [85.0, 255.0, 85.0]),
+    (VgaColor::LightCyan, [85.0, 255.0, 255.0]),
+    (VgaColor::LightRed, [255.0, 85.0, 85.0]),
+    (VgaColor::LightMagenta, [255.0, 85.0, 255.0]),
+    (VgaColor::Yellow, [255.0, 255.0, 85.0]),
+    (VgaColor::White, [255.0, 255.0, 255.0]),
+];
+
+impl VgaColor {
+    /// The palette colour closest to `rgb`.
+    pub fn nearest(rgb: [f32; 3]) -> Self {
+        let distance = |color: &[f32; 3]| {
+            color
+                .iter()
+                .zip(rgb)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum::<f32>()
+        };
+        VGA_PALETTE
+            .iter()
+            .min_by(|a, b| distance(&a.1).total_cmp(&distance(&b.1)))
+            .map(|&(color, _)| color)
+            .unwrap_or(VgaColor::Black)
+    }
+}
+
+/// Colours of one cell. Blank cells have the background as foreground.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct CellAttributes {
+    pub foreground: VgaColor,
+    pub background: VgaColor,
+}
+
+/// Text read from a screen.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TextScreen {
+    pub grid: TextGrid,
+    /// One string per row, one character per column.
+    pub lines: Vec<String>,
+    /// Mean glyph score of the non-blank cells, 0.0..=1.0.
+    pub confidence: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Vec<Vec<CellAttributes>>>,
+}
+
+impl TextScreen {
+    /// The screen as text, without trailing spaces.
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Row and column of the first occurrence of `needle` within a line.
+    pub fn find(&self, needle: &str) -> Option<(usize, usize)> {
+        self.lines.iter().enumerate().find_map(|(row, line)| {
+            let byte = line.find(needle)?;
+            Some((row, line[..byte].chars().count()))
+        })
+    }
+}
+
+/// Where `wait_for_text` found its text.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TextMatch {
+    pub row: usize,
+    pub column: usize,
+    pub line: String,
+    pub elapsed_ms: u64,
+}
+
+/// The frame reduced to what grid detection and cell sampling need.
+struct Frame<'a> {
+    image: &'a RgbaImage,
+    luma: Vec<u8>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(image: &'a RgbaImage) -> Self {
+        let luma = image::imageops::grayscale(image).into_raw();
+        Frame { image, luma }
+    }
+
+    fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    /// Counts of horizontal edges per row and vertical edges per column.
+    fn edge_profiles(&self) -> (Vec<f64>, Vec<f64>) {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let mut rows = vec![0.0; height];
+        let mut columns = vec![0.0; width];
+        for (y, line) in self.luma.chunks(width).enumerate() {
+            let below = self.luma.get((y + 1) * width..(y + 2) * width);
+            for (x, &v) in line.iter().enumerate() {
+                if line
+                    .get(x + 1)
+                    .is_some_and(|&w| v.abs_diff(w) > EDGE_THRESHOLD)
+                {
+                    rows[y] += 1.0;
+                }
+                if below.is_some_and(|below| v.abs_diff(below[x]) > EDGE_THRESHOLD) {
+                    columns[x] += 1.0;
+                }
+            }
+        }
+        (rows, columns)
+    }
+
+    /// Frame pixels of the font pixel `fx`,`fy` of a cell.
+    fn block(
+        &self,
+        grid: &TextGrid,
+        (column, row): (u32, u32),
+        (fx, fy): (u32, u32),
+    ) -> impl Iterator<Item = (u32, u32)> {
+        let s = grid.scale;
+        let left = grid.x + (column * grid.cell_width + fx) * s;
+        let top = grid.y + (row * grid.cell_height + fy) * s;
+        (top..top + s).flat_map(move |y| (left..left + s).map(move |x| (x, y)))
+    }
+
+    /// Mean brightness of each font pixel of the glyph area of a cell.
+    fn cell_luma(&self, grid: &TextGrid, font: &Font, cell: (u32, u32)) -> Vec<f32> {
+        let area = (grid.scale * grid.scale) as f32;
+        let mut samples = Vec::with_capacity((font.width * font.height) as usize);
+        for fy in 0..font.height {
+            for fx in 0..font.width {
+                let sum: u32 = self
+                    .block(grid, cell, (fx, fy))
+                    .map(|(x, y)| self.luma[(y * self.width() + x) as usize] as u32)
+                    .sum();
+                samples.push(sum as f32 / area);
+            }
+        }
+        samples
+    }
+
+    /// Mean colour of each font pixel of the glyph area of a cell.
+    fn cell_colors(&self, grid: &TextGrid, font: &Font, cell: (u32, u32)) -> Vec<[f32; 3]> {
+        let area = (grid.scale * grid.scale) as f32;
+        let mut samples = Vec::with_capacity((font.width * font.height) as usize);
+        for fy in 0..font.height {
+            for fx in 0..font.width {
+                let mut sum = [0.0f32; 3];
+                for (x, y) in self.block(grid, cell, (fx, fy)) {
+                    let pixel = self.image.get_pixel(x, y).0;
+                    sum.iter_mut().zip(pixel).for_each(|(s, v)| *s += v as f32);
+                }
+                samples.push(sum.map(|v| v / area));
+            }
+        }
+        samples
+    }
+}
+
+/// The glyph a non-blank cell shows.
+struct Recognised {
+    ch: char,
+    /// Overlap of the cell's ink with the glyph's, 0.0..=1.0.
+    score: f64,
+    /// Brightness between ink and background.
+    middle: f32,
+    /// Whether the ink is darker than the background.
+    dark_ink: bool,
+}
+
+/// Matches one cell's font-pixel brightness against the font, or returns
+/// `None` for a blank cell.
+fn recognise(luma: &[f32], font: &Font) -> Option<Recognised> {
+    let (low, high) = luma.iter().fold((f32::MAX, f32::MIN), |(low, high), &v| {
+        (low.min(v), high.max(v))
+    });
+    if high - low < MIN_CELL_CONTRAST {
+        return None;
+    }
+
+    let middle = (low + high) / 2.0;
+    let rows: Vec<u32> = luma
+        .chunks(font.width as usize)
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .filter(|&(_, &v)| v > middle)
+                .fold(0, |bits, (x, _)| bits | 1 << x)
+        })
+        .collect();
+    let pixels = font.width * font.height;
+    // (differing pixels, dark ink); bright ink wins ties, so a glyph is not
+    // read as the inverse of another (like ▌ and ▐).
+    let mut best = ((u32::MAX, false), 0);
+    for (index, glyph) in font.glyphs.iter().enumerate() {
+        let differing: u32 = rows
+            .iter()
+            .zip(&glyph.rows)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        // Dark text on a bright background differs in every other pixel.
+        for key in [(differing, false), (pixels - differing, true)] {
+            if key < best.0 {
+                best = (key, index);
+            }
+        }
+    }
+    let ((differing, dark_ink), index) = best;
+    let glyph = &font.glyphs[index];
+
+    // Score by overlap of ink rather than by all pixels, so that a mostly
+    // empty cell does not score well against a mostly empty glyph.
+    let mask = u32::MAX >> (32 - font.width);
+    let union: u32 = rows
+        .iter()
+        .zip(&glyph.rows)
+        .map(|(&a, b)| (if dark_ink { !a & mask } else { a } | b).count_ones())
+        .sum();
+    Some(Recognised {
+        ch: glyph.ch,
+        score: 1.0 - differing as f64 / union.max(1) as f64,
+        middle,
+        dark_ink,
+    })
+}
+
+/// Colours of a cell, given its font-pixel colours and brightness.
+fn cell_attributes(
+    colors: &[[f32; 3]],
+    luma: &[f32],
+    recognised: Option<&Recognised>,
+) -> CellAttributes {
+    let mean = |ink: bool| {
+        let (mut sum, mut count) = ([0.0f32; 3], 0);
+        for (color, &v) in colors.iter().zip(luma) {
+            let is_ink = recognised.is_some_and(|r| (v > r.middle) != r.dark_ink);
+            if is_ink == ink {
+                sum.iter_mut().zip(color).for_each(|(s, v)| *s += v);
+                count += 1;
+            }
+        }
+        (count > 0).then(|| VgaColor::nearest(sum.map(|v| v / count as f32)))
+    };
+    let background = mean(false).unwrap_or(VgaColor::Black);
+    CellAttributes {
+        foreground: mean(true).unwrap_or(background),
+        background,
+    }
+}
+
+/// Expected edge counts per font row and per font column of a cell, from
+/// the glyphs of `font` (see `Frame::edge_profiles`).
+fn font_profiles(font: &Font, cell_width: u32) -> (Vec<f64>, Vec<f64>) {
+    let mut rows = vec![0.0; font.height as usize];
+    let mut columns = vec![0.0; cell_width as usize];
+    let bit = |glyph: &Glyph, x: u32, y: u32| {
+        y < font.height && x < font.width && glyph.rows[y as usize] & (1 << x) != 0
+    };
+    for glyph in &font.glyphs {
+        for y in 0..font.height {
+            for x in 0..cell_width {
+                if bit(glyph, x, y) != bit(glyph, x + 1, y) {
+                    rows[y as usize] += 1.0;
+                }
+                if bit(glyph, x, y) != bit(glyph, x, y + 1) {
+                    columns[x as usize] += 1.0;
+                }
+            }
+        }
+    }
+    (rows, columns)
+}
+
+/// Candidate grid origins `0..expected.len() * scale`, best first: the
+/// frame's edge profile, folded by the cell size, is correlated with the
+/// profile the font's glyphs would produce.
+fn rank_origins(profile: &[f64], expected: &[f64], scale: u32) -> Vec<u32> {
+    let period = expected.len() * scale as usize;
+    let mut folded = vec![0.0; period];
+    for (i, &count) in profile.iter().enumerate() {
+        folded[i % period] += count;
+    }
+    let expected: Vec<f64> = (0..period).map(|i| expected[i / scale as usize]).collect();
+    let mut ranked: Vec<(f64, u32)> = (0..period as u32)
+        .map(|origin| {
+            let shifted = (0..period).map(|i| folded[(origin as usize + i) % period]);
+            (correlation(shifted, &expected), origin)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked
+        .into_iter()
+        .take(ORIGIN_CANDIDATES)
+        .map(|(_, origin)| origin)
+        .collect()
+}
+
+/// Pearson correlation of two equally long series.
+fn correlation(a: impl Iterator<Item = f64>, b: &[f64]) -> f64 {
+    let a: Vec<f64> = a.collect();
+    let n = b.len() as f64;
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a <= 0.0 || var_b <= 0.0 {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+/// Mean glyph score of up to `SAMPLE_CELLS` non-blank cells, spread over
+/// the grid, or `None` if the grid is (almost) empty.
+fn score_grid(frame: &Frame, font: &Font, grid: &TextGrid) -> Option<f64> {
+    let cells = (grid.columns * grid.rows) as u64;
+    // Stepping by a prime visits every cell once in a scattered order, so
+    // the sample covers the whole screen.
+    let step = if cells.is_multiple_of(SCATTER_STEP) {
+        1
+    } else {
+        SCATTER_STEP
+    };
+    let mut scores = Vec::new();
+    for k in 0..cells {
+        let index = (k * step % cells) as u32;
+        let luma = frame.cell_luma(grid, font, (index % grid.columns, index / grid.columns));
+        if let Some(recognised) = recognise(&luma, font) {
+            scores.push(recognised.score);
+            if scores.len() == SAMPLE_CELLS {
+                break;
+            }
+        }
+    }
+    (scores.len() >= 4).then(|| scores.iter().sum::<f64>() / scores.len() as f64)
+}
+
+/// Finds the character grid of `font` in `image`.
+pub fn detect_grid(image: &RgbaImage, font: &Font) -> AnyResult<TextGrid> {
+    let frame = Frame::new(image);
+    let (row_profile, column_profile) = frame.edge_profiles();
+    let mut cell_widths = vec![font.width];
+    if font.width == 8 {
+        cell_widths.push(9);
+    }
+
+    let mut best: Option<(f64, TextGrid)> = None;
+    for scale in 1..=MAX_SCALE {
+        for &cell_width in &cell_widths {
+            let (period_x, period_y) = (cell_width * scale, font.height * scale);
+            if frame.width() / period_x < MIN_COLUMNS || frame.height() / period_y < MIN_ROWS {
+                continue;
+            }
+            let (expected_rows, expected_columns) = font_profiles(font, cell_width);
+            let xs = rank_origins(&column_profile, &expected_columns, scale);
+            let ys = rank_origins(&row_profile, &expected_rows, scale);
+            for &x in &xs {
+                for &y in &ys {
+                    let grid = TextGrid {
+                        x,
+                        y,
+                        columns: (frame.width() - x) / period_x,
+                        rows: (frame.height() - y) / period_y,
+                        cell_width,
+                        cell_height: font.height,
+                        scale,
+                    };
+                    let Some(score) = score_grid(&frame, font, &grid) else {
+                        continue;
+                    };
+                    if best.is_none_or(|(top, _)| score > top) {
+                        best = Some((score, grid));
+                    }
+                }
+            }
+        }
+    }
+    match best {
+        Some((score, grid)) if score >= MIN_GRID_SCORE => {
+            debug!("Text grid {:?} scores {:.3}", grid, score);
+            Ok(grid)
+        }
+        Some((score, _)) => bail!(
+            "No text-mode screen found (best grid matched glyphs {:.0}%)",
+            score * 100.0
+        ),
+        None => bail!("No text-mode screen found"),
+    }
+}
+
+/// Reads every cell of `grid`.
+pub fn read_grid(
+    image: &RgbaImage,
+    font: &Font,
+    grid: &TextGrid,
+    attributes: bool,
+) -> AnyResult<TextScreen> {
+    let (width, height) = (
+        grid.x + grid.columns * grid.cell_width * grid.scale,
+        grid.y + grid.rows * grid.cell_height * grid.scale,
+    );
+    if grid.cell_width < font.width || width > image.width() || height > image.height() {
+        bail!(
+            "Text grid does not fit the {}x{} frame",
+            image.width(),
+            image.height()
+        );
+    }
+    let frame = Frame::new(image);
+    let mut lines = Vec::with_capacity(grid.rows as usize);
+    let mut screen_attributes = Vec::new();
+    let (mut total, mut scored) = (0.0, 0);
+    for row in 0..grid.rows {
+        let mut line = String::with_capacity(grid.columns as usize);
+        let mut row_attributes = Vec::new();
+        for column in 0..grid.columns {
+            let luma = frame.cell_luma(grid, font, (column, row));
+            let recognised = recognise(&luma, font);
+            match &recognised {
+                Some(r) if r.score >= MIN_GLYPH_SCORE => line.push(r.ch),
+                Some(_) => line.push(char::REPLACEMENT_CHARACTER),
+                None => line.push(' '),
+            }
+            if let Some(r) = &recognised {
+                total += r.score;
+                scored += 1;
+            }
+            if attributes {
+                let colors = frame.cell_colors(grid, font, (column, row));
+                row_attributes.push(cell_attributes(&colors, &luma, recognised.as_ref()));
+            }
+        }
+        lines.push(line);
+        screen_attributes.push(row_attributes);
+    }
+    Ok(TextScreen {
+        grid: *grid,
+        lines,
+        confidence: if scored == 0 {
+            1.0
+        } else {
+            total / scored as f64
+        },
+        attributes: attributes.then_some(screen_attributes),
+    })
+}
+
+/// Reads text screens with one font, remembering the grid between frames.
+#[derive(Clone, Debug)]
+pub struct TextReader {
+    font: Font,
+    grid: Option<TextGrid>,
+}
+
+impl TextReader {
+    pub fn new(font: Font) -> Self {
+        Self { font, grid: None }
+    }
+
+    /// The grid found last, if any.
+    pub fn grid(&self) -> Option<TextGrid> {
+        self.grid
+    }
+
+    /// Reads `image`, detecting the grid again if the last one no longer
+    /// matches (for example after a mode change).
+    pub fn read(&mut self, image: &RgbaImage, attributes: bool) -> AnyResult<TextScreen> {
+        if let Some(grid) = self.grid {
+            match read_grid(image, &self.font, &grid, attributes) {
+                Ok(screen) if screen.confidence >= MIN_GRID_SCORE => return Ok(screen),
+                _ => self.grid = None,
+            }
+        }
+        let grid = detect_grid(image, &self.font)?;
+        self.grid = Some(grid);
+        read_grid(image, &self.font, &grid, attributes)
+    }
+}
+
+/// Reads the text on the latest frame.
+pub async fn read_screen_text(
+    capture: &VideoFrameCapture,
+    reader: &mut TextReader,
+    attributes: bool,
+) -> AnyResult<TextScreen> {
+    let frame = capture.latest_frame(FrameFreshness::Latest).await?;
+    let image = frame_image(&frame)?;
+    let mut owned = reader.clone();
+    let (owned, screen) = tokio::task::spawn_blocking(move || {
+        let screen = owned.read(&image, attributes);
+        (owned, screen)
+    })
+    .await?;
+    *reader = owned;
+    screen
+}
+
+/// Waits until `text` appears on one line of a text-mode screen.
+pub async fn wait_for_text(
+    capture: &VideoFrameCapture,
+    font: Font,
+    text: &str,
+    timeout: Duration,
+) -> AnyResult<TextMatch> {
+    let start = Instant::now();
+    let mut reader = TextReader::new(font);
+    let mut last_seen = None;
+    loop {
+        let frame = capture.latest_frame(FrameFreshness::Latest).await?;
+        if last_seen != Some(frame.decoded_at) {
+            last_seen = Some(frame.decoded_at);
+            let image = frame_image(&frame)?;
+            let mut owned = reader.clone();
+            let (owned, screen) = tokio::task::spawn_blocking(move || {
+                let screen = owned.read(&image, false);
+                (owned, screen)
+            })
+            .await?;
+            reader = owned;
+            match screen {
+                Ok(screen) => {
+                    if let Some((row, column)) = screen.find(text) {
+                        return Ok(TextMatch {
+                            row,
+                            column,
+                            line: screen.lines[row].trim_end().to_string(),
+                            elapsed_ms: start.elapsed().as_millis() as u64,
+                        });
+                    }
+                }
+                // Graphics modes and blank screens are expected while
+                // waiting; keep looking.
+                Err(e) => debug!("No text on screen yet: {}", e),
+            }
+        }
+        if start.elapsed() >= timeout {
+            bail!(
+                "Text {:?} did not appear within {} ms",
+                text,
+                timeout.as_millis()
+            );
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const BLUE: [u8; 3] = [0, 0, 170];
+    const LIGHT_GRAY: [u8; 3] = [170, 170, 170];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    /// Draws `lines` like VGA text mode would, `scale` times enlarged, with
+    /// the grid starting at `origin`. `highlight` rows are drawn inverted.
+    fn render(
+        lines: &[&str],
+        cell_width: u32,
+        scale: u32,
+        origin: (u32, u32),
+        highlight: usize,
+    ) -> RgbaImage {
+        let font = Font::vga();
+        let (columns, rows) = (80, 25);
+        let width = origin.0 + columns * cell_width * scale;
+        let height = origin.1 + rows * 16 * scale;
+        let mut image = RgbaImage::from_pixel(width, height, Rgba([0, 0, 170, 255]));
+        for (row, line) in lines.iter().enumerate() {
+            let (fg, bg) = if row == highlight {
+                (BLUE, LIGHT_GRAY)
+            } else {
+                (WHITE, BLUE)
+            };
+            for (column, ch) in line.chars().enumerate() {
+                let glyph = font.glyphs.iter().find(|g| g.ch == ch).unwrap();
+                for y in 0..16 * scale {
+                    for x in 0..cell_width * scale {
+                        let (fx, fy) = (x / scale, y / scale);
+                        let ink = fx < 8 && glyph.rows[fy as usize] & (1 << fx) != 0;
+                        let [r, g, b] = if ink { fg } else { bg };
+                        image.put_pixel(
+                            origin.0 + (column as u32 * cell_width) * scale + x,
+                            origin.1 + (row as u32 * 16) * scale + y,
+                            Rgba([r, g, b, 255]),
+                        );
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    const BIOS: &[&str] = &[
+        "\u{2554}\u{2550}\u{2550} Main \u{2550}\u{2550}\u{2557}  Advanced   Boot   Exit",
+        "System Time      [12:34:56]",
+        "System Date      [Tue 01/02/2024]",
+        "Boot Mode        <UEFI>",
+        "",
+        "Press F2 to enter setup, F12 for boot menu",
+    ];
+
+    #[test]
+    fn test_reads_9x16_text_with_highlight() {
+        let image = render(BIOS, 9, 1, (0, 0), 3);
+        let font = Font::vga();
+        let grid = detect_grid(&image, &font).unwrap();
+        assert_eq!(
+            (
+                grid.x,
+                grid.y,
+                grid.columns,
+                grid.rows,
+                grid.cell_width,
+                grid.scale
+            ),
+            (0, 0, 80, 25, 9, 1)
+        );
+        let screen = read_grid(&image, &font, &grid, true).unwrap();
+        let text = screen.text();
+        let expected = BIOS.join("\n");
+        assert_eq!(text.trim_end(), expected);
+        assert!(screen.confidence > 0.99);
+        assert_eq!(screen.find("F2"), Some((5, 6)));
+
+        let attributes = screen.attributes.unwrap();
+        assert_eq!(
+            attributes[1][0],
+            CellAttributes {
+                foreground: VgaColor::White,
+                background: VgaColor::Blue
+            }
+        );
+        assert_eq!(
+            attributes[3][0],
+            CellAttributes {
+                foreground: VgaColor::Blue,
+                background: VgaColor::LightGray
+            }
+        );
+        assert_eq!(attributes[4][0].background, VgaColor::Blue);
+    }
+
+    #[test]
+    fn test_detects_scaled_and_offset_8x16_grid() {
+        let image = render(BIOS, 8, 2, (6, 10), usize::MAX);
+        let mut reader = TextReader::new(Font::vga());
+        let screen = reader.read(&image, false).unwrap();
+        let grid = reader.grid().unwrap();
+        assert_eq!(
+            (grid.x, grid.y, grid.columns, grid.cell_width, grid.scale),
+            (6, 10, 80, 8, 2)
+        );
+        assert_eq!(screen.lines[1].trim_end(), BIOS[1]);
+        assert!(screen.attributes.is_none());
+
+        // A graphical screen is not mistaken for text.
+        let photo = RgbaImage::from_fn(640, 480, |x, y| {
+            let v = ((x * x + y * 3) % 251) as u8;
+            Rgba([v, v / 2, 255 - v, 255])
+        });
+        assert!(detect_grid(&photo, &Font::vga()).is_err());
+    }
+}
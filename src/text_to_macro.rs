@@ -1,4 +1,4 @@
-use crate::keyboard_layout::{layouts, KeyboardLayout};
+use crate::keyboard_layout::{layouts, KeyStroke, KeyboardLayout};
 use crate::keyboard_mappings::{key_name_to_hid, modifier_name_to_mask};
 use anyhow::{anyhow, Result};
 
@@ -27,46 +27,27 @@ pub fn text_to_macro_steps(
     let mut steps = Vec::new();
 
     for c in text.chars() {
-        let key_combo = layout
-            .get_char(c)
-            .ok_or_else(|| anyhow!("Character '{}' not found in layout {}", c, layout.iso_code))?;
-
-        if let Some(accent_key) = &key_combo.accent_key {
-            let accent_hid = key_name_to_hid(&accent_key.key)
-                .ok_or_else(|| anyhow!("Invalid accent key: {}", accent_key.key))?;
-
-            let mut accent_modifier = 0u8;
-            if accent_key.shift {
-                accent_modifier |= modifier_name_to_mask("ShiftLeft").unwrap_or(0);
-            }
-            if accent_key.alt_right {
-                accent_modifier |= modifier_name_to_mask("AltRight").unwrap_or(0);
-            }
-
-            steps.push(MacroStep::new(vec![accent_hid], accent_modifier, delay_ms));
+        for stroke in layout.strokes_for(c)? {
+            steps.push(stroke_to_macro_step(&stroke, delay_ms)?);
         }
+    }
 
-        let key_hid = key_name_to_hid(&key_combo.key)
-            .ok_or_else(|| anyhow!("Invalid key: {}", key_combo.key))?;
-
-        let mut modifier = 0u8;
-        if key_combo.shift {
-            modifier |= modifier_name_to_mask("ShiftLeft").unwrap_or(0);
-        }
-        if key_combo.alt_right {
-            modifier |= modifier_name_to_mask("AltRight").unwrap_or(0);
-        }
+    Ok(steps)
+}
 
-        steps.push(MacroStep::new(vec![key_hid], modifier, delay_ms));
+fn stroke_to_macro_step(stroke: &KeyStroke, delay_ms: u64) -> Result<MacroStep> {
+    let key_hid =
+        key_name_to_hid(&stroke.key).ok_or_else(|| anyhow!("Invalid key: {}", stroke.key))?;
 
-        if key_combo.dead_key {
-            let space_hid =
-                key_name_to_hid("Space").ok_or_else(|| anyhow!("Space key not found"))?;
-            steps.push(MacroStep::new(vec![space_hid], 0, delay_ms));
-        }
+    let mut modifier = 0u8;
+    if stroke.shift {
+        modifier |= modifier_name_to_mask("ShiftLeft").unwrap_or(0);
+    }
+    if stroke.alt_right {
+        modifier |= modifier_name_to_mask("AltRight").unwrap_or(0);
     }
 
-    Ok(steps)
+    Ok(MacroStep::new(vec![key_hid], modifier, delay_ms))
 }
 
 pub fn text_to_macro_steps_with_layout_code(
@@ -81,6 +62,7 @@ pub fn text_to_macro_steps_with_layout_code(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::keyboard_layout::{DeadKeyFinish, KeyCombo};
 
     #[test]
     fn test_en_us_layout_basic_chars() {
@@ -101,8 +83,8 @@ mod tests {
         let lowercase_a = layout.get_char('a').unwrap();
         let uppercase_a = layout.get_char('A').unwrap();
 
-        assert!(!lowercase_a.shift);
-        assert!(uppercase_a.shift);
+        assert!(!lowercase_a.base().shift);
+        assert!(uppercase_a.base().shift);
     }
 
     #[test]
@@ -123,10 +105,26 @@ mod tests {
         let layout = layouts::get_layout("es-ES").unwrap();
 
         let a_acute = layout.get_char('á').unwrap();
-        assert!(a_acute.accent_key.is_some());
+        assert!(!a_acute.accents().is_empty());
 
         let plain_a = layout.get_char('a').unwrap();
-        assert!(plain_a.accent_key.is_none());
+        assert!(plain_a.accents().is_empty());
+    }
+
+    #[test]
+    fn test_es_es_hat_and_grave_are_dead_keys_right_of_p() {
+        let layout = layouts::get_layout("es-ES").unwrap();
+
+        let a_hat = layout.get_char('â').unwrap();
+        assert_eq!(a_hat.accents().len(), 1);
+        assert_eq!(a_hat.accents()[0].key, "BracketLeft");
+        assert!(a_hat.accents()[0].shift);
+        assert!(a_hat.accents()[0].dead_key);
+
+        let grave = layout.get_char('`').unwrap().base();
+        assert_eq!(grave.key, "BracketLeft");
+        assert!(!grave.shift);
+        assert!(grave.dead_key);
     }
 
     #[test]
@@ -162,6 +160,64 @@ mod tests {
         assert!(result.is_err());
     }
 
+    fn stroke_keys(text: &str, layout: &KeyboardLayout) -> Result<Vec<(u8, u8)>> {
+        Ok(text_to_macro_steps(text, layout, 0)?
+            .iter()
+            .map(|step| (step.keys[0], step.modifier))
+            .collect())
+    }
+
+    #[test]
+    fn test_multi_dead_key_and_compose_sequences() {
+        let circumflex = KeyCombo::new("Digit6").with_shift().with_dead_key();
+        let dot_below = KeyCombo::new("Digit9").with_dead_key();
+        let layout = KeyboardLayout::new("xx-XX", "Test")
+            .with_char(
+                'ệ',
+                KeyCombo::new("KeyE")
+                    .with_accent_key(circumflex.clone())
+                    .with_accent_key(dot_below),
+            )
+            .with_char('^', circumflex)
+            .with_char(
+                'é',
+                KeyCombo::compose(vec![KeyStroke::new("Quote"), KeyStroke::new("KeyE")]).unwrap(),
+            );
+
+        assert_eq!(
+            stroke_keys("ệ", &layout).unwrap(),
+            vec![(0x23, 0x02), (0x26, 0), (0x08, 0)]
+        );
+        assert_eq!(
+            stroke_keys("^", &layout).unwrap(),
+            vec![(0x23, 0x02), (0x2c, 0)]
+        );
+        assert!(stroke_keys("é", &layout).is_err());
+
+        let layout = layout
+            .with_compose_key(KeyStroke::new("ContextMenu"))
+            .with_dead_key_finish(DeadKeyFinish::Repeat);
+        assert_eq!(
+            stroke_keys("é", &layout).unwrap(),
+            vec![(0x65, 0), (0x34, 0), (0x08, 0)]
+        );
+        assert_eq!(
+            stroke_keys("^", &layout).unwrap(),
+            vec![(0x23, 0x02), (0x23, 0x02)]
+        );
+    }
+
+    #[test]
+    fn test_key_combo_needs_a_stroke() {
+        assert!(KeyCombo::sequence(vec![]).is_err());
+        assert!(KeyCombo::compose(vec![]).is_err());
+        let combo = KeyCombo::sequence(vec![KeyStroke::new("Quote"), KeyStroke::new("KeyE")])
+            .unwrap()
+            .with_shift();
+        assert_eq!(combo.accents(), &[KeyStroke::new("Quote")]);
+        assert_eq!(combo.base(), &KeyStroke::new("KeyE").with_shift());
+    }
+
     #[test]
     fn test_default_layout_fallback() {
         let layout = layouts::get_layout_or_default("invalid-code");
@@ -0,0 +1,280 @@
+//! Timing profiles for the typing helpers.
+//!
+//! A `TypingProfile` decides how long each key is held and how long the
+//! keyboard stays idle afterwards. Presets cover slow firmware setup screens
+//! (`bios-safe`), the fixed timings `send_text_with_layout` used before
+//! (`standard`) and responsive OS shells (`fast`); any field of a preset can
+//! be overridden, and `TypingProfile::custom` starts from given timings. In
+//! adaptive mode a `LatencyTracker` stretches the timings while the RPC
+//! round-trip time is above its baseline.
+
+use clap::ValueEnum;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Largest factor adaptive mode slows typing down by.
+pub const MAX_SLOWDOWN: f64 = 4.0;
+
+/// Round-trip times below this are treated as equal, so sub-millisecond noise
+/// on a fast link does not trigger a slowdown.
+const MIN_BASELINE_MS: f64 = 5.0;
+
+/// Weight of a new sample in the smoothed round-trip time.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Named typing presets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TypingPreset {
+    /// 20 ms press, 10 ms release: the timings `send_text_with_layout` used
+    /// before profiles existed.
+    #[default]
+    Standard,
+    /// Slow, bursty and adaptive; for BIOS/UEFI screens that drop characters.
+    BiosSafe,
+    /// Short press and release gaps for responsive targets.
+    Fast,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypingProfile {
+    /// How long each key is held down.
+    pub press_ms: u64,
+    /// Idle time after the all-keys-up report.
+    pub release_ms: u64,
+    /// Random extra time of up to this much added to each press and release.
+    pub jitter_ms: u64,
+    /// Number of key presses per burst; `0` disables bursts.
+    pub burst_len: usize,
+    /// Extra pause after each burst.
+    pub burst_pause_ms: u64,
+    /// Stretch timings while RPC latency is above its baseline.
+    pub adaptive: bool,
+}
+
+impl Default for TypingProfile {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl TypingProfile {
+    pub fn standard() -> Self {
+        Self {
+            press_ms: 20,
+            release_ms: 10,
+            jitter_ms: 0,
+            burst_len: 0,
+            burst_pause_ms: 0,
+            adaptive: false,
+        }
+    }
+
+    /// Standard profile with the 10 ms key hold `rpc_sendtext` has always used.
+    pub fn sendtext() -> Self {
+        Self {
+            press_ms: 10,
+            ..Self::standard()
+        }
+    }
+
+    pub fn bios_safe() -> Self {
+        Self {
+            press_ms: 40,
+            release_ms: 40,
+            jitter_ms: 0,
+            burst_len: 16,
+            burst_pause_ms: 250,
+            adaptive: true,
+        }
+    }
+
+    pub fn fast() -> Self {
+        Self {
+            press_ms: 8,
+            release_ms: 4,
+            jitter_ms: 0,
+            burst_len: 0,
+            burst_pause_ms: 0,
+            adaptive: true,
+        }
+    }
+
+    /// Standard profile with the given press and release times.
+    pub fn custom(press_ms: u64, release_ms: u64) -> Self {
+        Self {
+            press_ms,
+            release_ms,
+            ..Self::standard()
+        }
+    }
+
+    pub fn from_preset(preset: TypingPreset) -> Self {
+        match preset {
+            TypingPreset::Standard => Self::standard(),
+            TypingPreset::BiosSafe => Self::bios_safe(),
+            TypingPreset::Fast => Self::fast(),
+        }
+    }
+
+    pub fn with_press_ms(mut self, press_ms: u64) -> Self {
+        self.press_ms = press_ms;
+        self
+    }
+
+    pub fn with_release_ms(mut self, release_ms: u64) -> Self {
+        self.release_ms = release_ms;
+        self
+    }
+
+    pub fn with_jitter_ms(mut self, jitter_ms: u64) -> Self {
+        self.jitter_ms = jitter_ms;
+        self
+    }
+
+    pub fn with_burst(mut self, burst_len: usize, burst_pause_ms: u64) -> Self {
+        self.burst_len = burst_len;
+        self.burst_pause_ms = burst_pause_ms;
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Press and release time for the key press at `index` (0-based),
+    /// including jitter, the burst pause and the adaptive `slowdown` factor.
+    pub fn step_timing(&self, index: usize, slowdown: f64, rng: &mut TimingRng) -> (u64, u64) {
+        let mut press = self.press_ms + rng.up_to(self.jitter_ms);
+        let mut release = self.release_ms + rng.up_to(self.jitter_ms);
+        if self.burst_len > 0 && index % self.burst_len == self.burst_len - 1 {
+            release += self.burst_pause_ms;
+        }
+        if self.adaptive {
+            let slowdown = slowdown.clamp(1.0, MAX_SLOWDOWN);
+            press = (press as f64 * slowdown).round() as u64;
+            release = (release as f64 * slowdown).round() as u64;
+        }
+        (press, release)
+    }
+}
+
+/// Small xorshift generator for timing jitter; not for anything else.
+#[derive(Clone, Debug)]
+pub struct TimingRng(u64);
+
+impl TimingRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(1);
+        Self::new(nanos)
+    }
+
+    /// Uniform value in `0..=max`.
+    pub fn up_to(&mut self, max: u64) -> u64 {
+        if max == 0 {
+            return 0;
+        }
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % (max + 1)
+    }
+}
+
+/// Tracks the keyboard report round-trip time and derives a slowdown factor.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyTracker {
+    baseline_ms: Option<f64>,
+    smoothed_ms: Option<f64>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, rtt_ms: f64) {
+        let smoothed = match self.smoothed_ms {
+            Some(prev) => LATENCY_SMOOTHING * rtt_ms + (1.0 - LATENCY_SMOOTHING) * prev,
+            None => rtt_ms,
+        };
+        self.smoothed_ms = Some(smoothed);
+        self.baseline_ms = Some(self.baseline_ms.map_or(smoothed, |b| b.min(smoothed)));
+    }
+
+    /// Factor (1.0..=`MAX_SLOWDOWN`) to stretch timings by.
+    pub fn slowdown(&self) -> f64 {
+        match (self.baseline_ms, self.smoothed_ms) {
+            (Some(baseline), Some(smoothed)) => {
+                (smoothed / baseline.max(MIN_BASELINE_MS)).clamp(1.0, MAX_SLOWDOWN)
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_profile_matches_previous_timings() {
+        let mut rng = TimingRng::new(1);
+        let profile = TypingProfile::standard();
+        assert_eq!(profile.step_timing(0, 3.0, &mut rng), (20, 10));
+        assert_eq!(
+            TypingProfile::custom(5, 7).step_timing(0, 1.0, &mut rng),
+            (5, 7)
+        );
+    }
+
+    #[test]
+    fn test_burst_pause_and_jitter() {
+        let mut rng = TimingRng::new(42);
+        let profile = TypingProfile::custom(10, 10).with_burst(3, 100);
+        let releases: Vec<u64> = (0..6)
+            .map(|i| profile.step_timing(i, 1.0, &mut rng).1)
+            .collect();
+        assert_eq!(releases, vec![10, 10, 110, 10, 10, 110]);
+
+        let jittery = TypingProfile::custom(10, 10).with_jitter_ms(5);
+        for i in 0..100 {
+            let (press, release) = jittery.step_timing(i, 1.0, &mut rng);
+            assert!((10..=15).contains(&press));
+            assert!((10..=15).contains(&release));
+        }
+    }
+
+    #[test]
+    fn test_adaptive_slowdown_follows_latency() {
+        let mut tracker = LatencyTracker::new();
+        assert_eq!(tracker.slowdown(), 1.0);
+        for _ in 0..10 {
+            tracker.record(10.0);
+        }
+        assert_eq!(tracker.slowdown(), 1.0);
+        for _ in 0..20 {
+            tracker.record(30.0);
+        }
+        let slowdown = tracker.slowdown();
+        assert!(slowdown > 2.5 && slowdown <= 3.0, "{}", slowdown);
+        for _ in 0..20 {
+            tracker.record(1000.0);
+        }
+        assert_eq!(tracker.slowdown(), MAX_SLOWDOWN);
+
+        let mut rng = TimingRng::new(1);
+        let profile = TypingProfile::bios_safe();
+        assert_eq!(profile.step_timing(0, 2.0, &mut rng), (80, 80));
+        assert_eq!(
+            TypingProfile::standard().step_timing(0, 2.0, &mut rng),
+            (20, 10)
+        );
+    }
+}
@@ -0,0 +1,486 @@
+//! Bitmap fonts for reading text-mode screens.
+//!
+//! `Font::vga()` is the IBM VGA 8x16 ROM font that BIOS setup screens, boot
+//! loaders and the Linux VGA console draw with: printable ASCII, the
+//! box-drawing, block and shade characters used to frame BIOS menus, and
+//! the arrows and markers that point at the selected entry. It is the only
+//! built-in font. Other console fonts, such as Terminus (`ter-*.psf.gz`),
+//! are not bundled and have to be loaded from PSF1 or PSF2 files as shipped
+//! in `/usr/share/consolefonts`, gzip-compressed or not.
+
+use anyhow::{anyhow, bail, Result as AnyResult};
+use flate2::read::GzDecoder;
+use std::io::Read;
+use std::path::Path;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_TABLE: u32 = 0x01;
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// One character's bitmap; bit `x` of `rows[y]` is the pixel at `x`,`y`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Glyph {
+    pub ch: char,
+    pub rows: Vec<u32>,
+}
+
+/// A fixed-size bitmap font, up to 32 pixels wide.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Font {
+    pub width: u32,
+    pub height: u32,
+    pub glyphs: Vec<Glyph>,
+}
+
+impl Font {
+    /// The bundled IBM VGA 8x16 font.
+    pub fn vga() -> Self {
+        let ascii = (0x20u8..=0x7E)
+            .zip(VGA_ASCII.iter())
+            .map(|(code, rows)| (code as char, *rows));
+        let extra = VGA_EXTRA
+            .iter()
+            .map(|&(code, rows)| (cp437_char(code), rows));
+        let glyphs = ascii
+            .chain(extra)
+            .map(|(ch, rows)| Glyph {
+                ch,
+                rows: rows.iter().map(|&row| byte_row(&[row], 8)).collect(),
+            })
+            .collect();
+        Font {
+            width: 8,
+            height: 16,
+            glyphs,
+        }
+    }
+
+    /// Loads a PSF1 or PSF2 console font file.
+    pub fn from_psf_file(path: impl AsRef<Path>) -> AnyResult<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .map_err(|e| anyhow!("Failed to read font {}: {}", path.display(), e))?;
+        Self::from_psf(&data).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Parses a PSF1 or PSF2 console font, gzip-compressed or not. Glyphs
+    /// are named by the font's Unicode table, or by code page 437 if it has
+    /// none. Fails if no glyph is left to match against.
+    pub fn from_psf(data: &[u8]) -> AnyResult<Self> {
+        let font = if data.starts_with(&PSF1_MAGIC) {
+            Self::from_psf1(data)?
+        } else if data.starts_with(&PSF2_MAGIC) {
+            Self::from_psf2(data)?
+        } else if data.starts_with(&GZIP_MAGIC) {
+            let mut unpacked = Vec::new();
+            GzDecoder::new(data)
+                .read_to_end(&mut unpacked)
+                .map_err(|e| anyhow!("Invalid gzip-compressed font: {}", e))?;
+            if unpacked.starts_with(&GZIP_MAGIC) {
+                bail!("Not a PSF font");
+            }
+            return Self::from_psf(&unpacked);
+        } else {
+            bail!("Not a PSF font")
+        };
+        if font.glyphs.is_empty() {
+            bail!("Font has no printable glyphs");
+        }
+        Ok(font)
+    }
+
+    fn from_psf1(data: &[u8]) -> AnyResult<Self> {
+        let (mode, height) = match data.get(2..4) {
+            Some(&[mode, height]) if height > 0 => (mode, height as usize),
+            _ => bail!("Truncated PSF1 header"),
+        };
+        let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let bitmaps = data
+            .get(4..4 + count * height)
+            .ok_or_else(|| anyhow!("Truncated PSF1 glyph data"))?;
+        let names = if mode & PSF1_MODE_HAS_TABLE != 0 {
+            psf1_table(&data[4 + count * height..], count)
+        } else {
+            cp437_names(count)
+        };
+        Ok(Self::from_bitmaps(
+            bitmaps.chunks(height),
+            8,
+            height as u32,
+            names,
+        ))
+    }
+
+    fn from_psf2(data: &[u8]) -> AnyResult<Self> {
+        let field = |index: usize| {
+            data.get(4 * index..4 * index + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| anyhow!("Truncated PSF2 header"))
+        };
+        let (header_size, flags, count, glyph_size, height, width) = (
+            field(2)? as usize,
+            field(3)?,
+            field(4)? as usize,
+            field(5)? as usize,
+            field(6)?,
+            field(7)?,
+        );
+        if width == 0
+            || width > 32
+            || height == 0
+            || glyph_size != width.div_ceil(8) as usize * height as usize
+        {
+            bail!("Unsupported PSF2 glyph size {}x{}", width, height);
+        }
+        let end = count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| anyhow!("Truncated PSF2 glyph data"))?;
+        let names = if flags & PSF2_HAS_TABLE != 0 {
+            psf2_table(&data[end..], count)
+        } else {
+            cp437_names(count)
+        };
+        Ok(Self::from_bitmaps(
+            data[header_size..end].chunks(glyph_size),
+            width,
+            height,
+            names,
+        ))
+    }
+
+    /// Builds a font from per-glyph bitmaps, skipping unnamed glyphs and
+    /// glyphs drawn exactly like an earlier one.
+    fn from_bitmaps<'a>(
+        bitmaps: impl Iterator<Item = &'a [u8]>,
+        width: u32,
+        height: u32,
+        names: Vec<Option<char>>,
+    ) -> Self {
+        let row_bytes = width.div_ceil(8) as usize;
+        let mut glyphs: Vec<Glyph> = Vec::new();
+        for (bitmap, name) in bitmaps.zip(names) {
+            let Some(ch) = name.filter(|ch| !ch.is_control()) else {
+                continue;
+            };
+            let rows: Vec<u32> = bitmap
+                .chunks(row_bytes)
+                .map(|row| byte_row(row, width))
+                .collect();
+            if glyphs.iter().all(|glyph| glyph.rows != rows) {
+                glyphs.push(Glyph { ch, rows });
+            }
+        }
+        Font {
+            width,
+            height,
+            glyphs,
+        }
+    }
+}
+
+/// Converts one MSB-first bitmap row to a row with pixel `x` at bit `x`.
+fn byte_row(bytes: &[u8], width: u32) -> u32 {
+    (0..width as usize)
+        .filter(|&x| bytes[x / 8] & (0x80 >> (x % 8)) != 0)
+        .fold(0, |row, x| row | 1 << x)
+}
+
+/// Names from a PSF1 Unicode table: little-endian code points per glyph,
+/// ended by 0xFFFF; 0xFFFE starts combining sequences, which are skipped.
+fn psf1_table(table: &[u8], count: usize) -> Vec<Option<char>> {
+    let mut names = vec![None; count];
+    let mut entries = table
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    for name in names.iter_mut() {
+        let mut in_sequence = false;
+        for entry in entries.by_ref() {
+            match entry {
+                0xFFFF => break,
+                0xFFFE => in_sequence = true,
+                code if !in_sequence && name.is_none() => *name = char::from_u32(code as u32),
+                _ => {}
+            }
+        }
+    }
+    names
+}
+
+/// Names from a PSF2 Unicode table: UTF-8 characters per glyph, ended by
+/// 0xFF; 0xFE starts combining sequences, which are skipped.
+fn psf2_table(table: &[u8], count: usize) -> Vec<Option<char>> {
+    let mut names = vec![None; count];
+    let mut entries = table.split(|&b| b == 0xFF);
+    for name in names.iter_mut() {
+        let Some(entry) = entries.next() else {
+            break;
+        };
+        let singles = entry.split(|&b| b == 0xFE).next().unwrap_or_default();
+        *name = String::from_utf8_lossy(singles)
+            .chars()
+            .find(|&ch| ch != char::REPLACEMENT_CHARACTER);
+    }
+    names
+}
+
+fn cp437_names(count: usize) -> Vec<Option<char>> {
+    (0..count)
+        .map(|code| u8::try_from(code).ok().map(cp437_char))
+        .collect()
+}
+
+/// The character code page 437 puts at `code`. 0x01..=0x1F are the
+/// symbols a text-mode screen draws there (arrows, card suits, ...); NUL and
+/// DEL are left as control characters, so those glyphs are not matched.
+pub fn cp437_char(code: u8) -> char {
+    match code {
+        0x01..=0x1F => CP437_LOW[code as usize - 0x01],
+        0x00..=0x7F => code as char,
+        _ => CP437_HIGH[code as usize - 0x80],
+    }
+}
+
+const CP437_LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// VGA ROM glyphs for 0x20..=0x7E, one byte per row, MSB leftmost.
+#[rustfmt::skip]
+const VGA_ASCII: [[u8; 16]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x18, 0x3C, 0x3C, 0x3C, 0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '!'
+    [0x00, 0x66, 0x66, 0x66, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x00, 0x00, 0x00, 0x6C, 0x6C, 0xFE, 0x6C, 0x6C, 0x6C, 0xFE, 0x6C, 0x6C, 0x00, 0x00, 0x00, 0x00], // '#'
+    [0x18, 0x18, 0x7C, 0xC6, 0xC2, 0xC0, 0x7C, 0x06, 0x06, 0x86, 0xC6, 0x7C, 0x18, 0x18, 0x00, 0x00], // '$'
+    [0x00, 0x00, 0x00, 0x00, 0xC2, 0xC6, 0x0C, 0x18, 0x30, 0x60, 0xC6, 0x86, 0x00, 0x00, 0x00, 0x00], // '%'
+    [0x00, 0x00, 0x38, 0x6C, 0x6C, 0x38, 0x76, 0xDC, 0xCC, 0xCC, 0xCC, 0x76, 0x00, 0x00, 0x00, 0x00], // '&'
+    [0x00, 0x30, 0x30, 0x30, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '\''
+    [0x00, 0x00, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x18, 0x0C, 0x00, 0x00, 0x00, 0x00], // '('
+    [0x00, 0x00, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x18, 0x30, 0x00, 0x00, 0x00, 0x00], // ')'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '*'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x7E, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x30, 0x00, 0x00, 0x00], // ','
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFE, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '.'
+    [0x00, 0x00, 0x00, 0x00, 0x02, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xC0, 0x80, 0x00, 0x00, 0x00, 0x00], // '/'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xCE, 0xDE, 0xF6, 0xE6, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // '0'
+    [0x00, 0x00, 0x18, 0x38, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00, 0x00, 0x00, 0x00], // '1'
+    [0x00, 0x00, 0x7C, 0xC6, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xC0, 0xC6, 0xFE, 0x00, 0x00, 0x00, 0x00], // '2'
+    [0x00, 0x00, 0x7C, 0xC6, 0x06, 0x06, 0x3C, 0x06, 0x06, 0x06, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // '3'
+    [0x00, 0x00, 0x0C, 0x1C, 0x3C, 0x6C, 0xCC, 0xFE, 0x0C, 0x0C, 0x0C, 0x1E, 0x00, 0x00, 0x00, 0x00], // '4'
+    [0x00, 0x00, 0xFE, 0xC0, 0xC0, 0xC0, 0xFC, 0x06, 0x06, 0x06, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // '5'
+    [0x00, 0x00, 0x38, 0x60, 0xC0, 0xC0, 0xFC, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // '6'
+    [0x00, 0x00, 0xFE, 0xC6, 0x06, 0x06, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00], // '7'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xC6, 0x7C, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // '8'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xC6, 0x7E, 0x06, 0x06, 0x06, 0x0C, 0x78, 0x00, 0x00, 0x00, 0x00], // '9'
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // ':'
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x30, 0x00, 0x00, 0x00, 0x00], // ';'
+    [0x00, 0x00, 0x00, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x30, 0x18, 0x0C, 0x06, 0x00, 0x00, 0x00, 0x00], // '<'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '='
+    [0x00, 0x00, 0x00, 0x60, 0x30, 0x18, 0x0C, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x00, 0x00, 0x00, 0x00], // '>'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0x0C, 0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '?'
+    [0x00, 0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xDE, 0xDE, 0xDE, 0xDC, 0xC0, 0x7C, 0x00, 0x00, 0x00, 0x00], // '@'
+    [0x00, 0x00, 0x10, 0x38, 0x6C, 0xC6, 0xC6, 0xFE, 0xC6, 0xC6, 0xC6, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'A'
+    [0x00, 0x00, 0xFC, 0x66, 0x66, 0x66, 0x7C, 0x66, 0x66, 0x66, 0x66, 0xFC, 0x00, 0x00, 0x00, 0x00], // 'B'
+    [0x00, 0x00, 0x3C, 0x66, 0xC2, 0xC0, 0xC0, 0xC0, 0xC0, 0xC2, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'C'
+    [0x00, 0x00, 0xF8, 0x6C, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x6C, 0xF8, 0x00, 0x00, 0x00, 0x00], // 'D'
+    [0x00, 0x00, 0xFE, 0x66, 0x62, 0x68, 0x78, 0x68, 0x60, 0x62, 0x66, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'E'
+    [0x00, 0x00, 0xFE, 0x66, 0x62, 0x68, 0x78, 0x68, 0x60, 0x60, 0x60, 0xF0, 0x00, 0x00, 0x00, 0x00], // 'F'
+    [0x00, 0x00, 0x3C, 0x66, 0xC2, 0xC0, 0xC0, 0xDE, 0xC6, 0xC6, 0x66, 0x3A, 0x00, 0x00, 0x00, 0x00], // 'G'
+    [0x00, 0x00, 0xC6, 0xC6, 0xC6, 0xC6, 0xFE, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'H'
+    [0x00, 0x00, 0x3C, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'I'
+    [0x00, 0x00, 0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0xCC, 0xCC, 0xCC, 0x78, 0x00, 0x00, 0x00, 0x00], // 'J'
+    [0x00, 0x00, 0xE6, 0x66, 0x66, 0x6C, 0x78, 0x78, 0x6C, 0x66, 0x66, 0xE6, 0x00, 0x00, 0x00, 0x00], // 'K'
+    [0x00, 0x00, 0xF0, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x62, 0x66, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'L'
+    [0x00, 0x00, 0xC6, 0xEE, 0xFE, 0xFE, 0xD6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'M'
+    [0x00, 0x00, 0xC6, 0xE6, 0xF6, 0xFE, 0xDE, 0xCE, 0xC6, 0xC6, 0xC6, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'N'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'O'
+    [0x00, 0x00, 0xFC, 0x66, 0x66, 0x66, 0x7C, 0x60, 0x60, 0x60, 0x60, 0xF0, 0x00, 0x00, 0x00, 0x00], // 'P'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xD6, 0xDE, 0x7C, 0x0C, 0x0E, 0x00, 0x00], // 'Q'
+    [0x00, 0x00, 0xFC, 0x66, 0x66, 0x66, 0x7C, 0x6C, 0x66, 0x66, 0x66, 0xE6, 0x00, 0x00, 0x00, 0x00], // 'R'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0x60, 0x38, 0x0C, 0x06, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'S'
+    [0x00, 0x00, 0x7E, 0x7E, 0x5A, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'T'
+    [0x00, 0x00, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'U'
+    [0x00, 0x00, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x6C, 0x38, 0x10, 0x00, 0x00, 0x00, 0x00], // 'V'
+    [0x00, 0x00, 0xC6, 0xC6, 0xC6, 0xC6, 0xD6, 0xD6, 0xD6, 0xFE, 0xEE, 0x6C, 0x00, 0x00, 0x00, 0x00], // 'W'
+    [0x00, 0x00, 0xC6, 0xC6, 0x6C, 0x7C, 0x38, 0x38, 0x7C, 0x6C, 0xC6, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'X'
+    [0x00, 0x00, 0x66, 0x66, 0x66, 0x66, 0x3C, 0x18, 0x18, 0x18, 0x18, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'Y'
+    [0x00, 0x00, 0xFE, 0xC6, 0x86, 0x0C, 0x18, 0x30, 0x60, 0xC2, 0xC6, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'Z'
+    [0x00, 0x00, 0x3C, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x3C, 0x00, 0x00, 0x00, 0x00], // '['
+    [0x00, 0x00, 0x00, 0x80, 0xC0, 0xE0, 0x70, 0x38, 0x1C, 0x0E, 0x06, 0x02, 0x00, 0x00, 0x00, 0x00], // '\\'
+    [0x00, 0x00, 0x3C, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x3C, 0x00, 0x00, 0x00, 0x00], // ']'
+    [0x10, 0x38, 0x6C, 0xC6, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00], // '_'
+    [0x30, 0x30, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x0C, 0x7C, 0xCC, 0xCC, 0xCC, 0x76, 0x00, 0x00, 0x00, 0x00], // 'a'
+    [0x00, 0x00, 0xE0, 0x60, 0x60, 0x78, 0x6C, 0x66, 0x66, 0x66, 0x66, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'b'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7C, 0xC6, 0xC0, 0xC0, 0xC0, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'c'
+    [0x00, 0x00, 0x1C, 0x0C, 0x0C, 0x3C, 0x6C, 0xCC, 0xCC, 0xCC, 0xCC, 0x76, 0x00, 0x00, 0x00, 0x00], // 'd'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7C, 0xC6, 0xFE, 0xC0, 0xC0, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'e'
+    [0x00, 0x00, 0x1C, 0x36, 0x32, 0x30, 0x78, 0x30, 0x30, 0x30, 0x30, 0x78, 0x00, 0x00, 0x00, 0x00], // 'f'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x76, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0x7C, 0x0C, 0xCC, 0x78, 0x00], // 'g'
+    [0x00, 0x00, 0xE0, 0x60, 0x60, 0x6C, 0x76, 0x66, 0x66, 0x66, 0x66, 0xE6, 0x00, 0x00, 0x00, 0x00], // 'h'
+    [0x00, 0x00, 0x18, 0x18, 0x00, 0x38, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'i'
+    [0x00, 0x00, 0x06, 0x06, 0x00, 0x0E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x06, 0x66, 0x66, 0x3C, 0x00], // 'j'
+    [0x00, 0x00, 0xE0, 0x60, 0x60, 0x66, 0x6C, 0x78, 0x78, 0x6C, 0x66, 0xE6, 0x00, 0x00, 0x00, 0x00], // 'k'
+    [0x00, 0x00, 0x38, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'l'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xEC, 0xFE, 0xD6, 0xD6, 0xD6, 0xD6, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'm'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xDC, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00], // 'n'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'o'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xDC, 0x66, 0x66, 0x66, 0x66, 0x66, 0x7C, 0x60, 0x60, 0xF0, 0x00], // 'p'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x76, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0x7C, 0x0C, 0x0C, 0x1E, 0x00], // 'q'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xDC, 0x76, 0x66, 0x60, 0x60, 0x60, 0xF0, 0x00, 0x00, 0x00, 0x00], // 'r'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7C, 0xC6, 0x60, 0x38, 0x0C, 0xC6, 0x7C, 0x00, 0x00, 0x00, 0x00], // 's'
+    [0x00, 0x00, 0x10, 0x30, 0x30, 0xFC, 0x30, 0x30, 0x30, 0x30, 0x36, 0x1C, 0x00, 0x00, 0x00, 0x00], // 't'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0x76, 0x00, 0x00, 0x00, 0x00], // 'u'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x66, 0x66, 0x3C, 0x18, 0x00, 0x00, 0x00, 0x00], // 'v'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xC6, 0xC6, 0xD6, 0xD6, 0xD6, 0xFE, 0x6C, 0x00, 0x00, 0x00, 0x00], // 'w'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xC6, 0x6C, 0x38, 0x38, 0x38, 0x6C, 0xC6, 0x00, 0x00, 0x00, 0x00], // 'x'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x7E, 0x06, 0x0C, 0xF8, 0x00], // 'y'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xFE, 0xCC, 0x18, 0x30, 0x60, 0xC6, 0xFE, 0x00, 0x00, 0x00, 0x00], // 'z'
+    [0x00, 0x00, 0x0E, 0x18, 0x18, 0x18, 0x70, 0x18, 0x18, 0x18, 0x18, 0x0E, 0x00, 0x00, 0x00, 0x00], // '{'
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '|'
+    [0x00, 0x00, 0x70, 0x18, 0x18, 0x18, 0x0E, 0x18, 0x18, 0x18, 0x18, 0x70, 0x00, 0x00, 0x00, 0x00], // '}'
+    [0x00, 0x76, 0xDC, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+/// VGA ROM glyphs of the code page 437 box-drawing, block and shade
+/// characters that BIOS menus are framed with, and of the arrows and
+/// markers next to menu entries and scroll bars.
+#[rustfmt::skip]
+const VGA_EXTRA: [(u8, [u8; 16]); 36] = [
+    (0x10, [0x00, 0x80, 0xC0, 0xE0, 0xF0, 0xF8, 0xFE, 0xF8, 0xF0, 0xE0, 0xC0, 0x80, 0x00, 0x00, 0x00, 0x00]), // '►'
+    (0x11, [0x00, 0x02, 0x06, 0x0E, 0x1E, 0x3E, 0xFE, 0x3E, 0x1E, 0x0E, 0x06, 0x02, 0x00, 0x00, 0x00, 0x00]), // '◄'
+    (0x18, [0x00, 0x00, 0x18, 0x3C, 0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00]), // '↑'
+    (0x19, [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x3C, 0x18, 0x00, 0x00, 0x00, 0x00]), // '↓'
+    (0x1E, [0x00, 0x00, 0x00, 0x00, 0x10, 0x38, 0x38, 0x7C, 0x7C, 0xFE, 0xFE, 0x00, 0x00, 0x00, 0x00, 0x00]), // '▲'
+    (0x1F, [0x00, 0x00, 0x00, 0x00, 0xFE, 0xFE, 0x7C, 0x7C, 0x38, 0x38, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00]), // '▼'
+    (0xB0, [0x11, 0x44, 0x11, 0x44, 0x11, 0x44, 0x11, 0x44, 0x11, 0x44, 0x11, 0x44, 0x11, 0x44, 0x11, 0x44]), // '░'
+    (0xB1, [0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA]), // '▒'
+    (0xB2, [0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77]), // '▓'
+    (0xB3, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '│'
+    (0xB4, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0xF8, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '┤'
+    (0xB9, [0x36, 0x36, 0x36, 0x36, 0x36, 0xF6, 0x06, 0xF6, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36]), // '╣'
+    (0xBA, [0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36]), // '║'
+    (0xBB, [0x00, 0x00, 0x00, 0x00, 0x00, 0xFE, 0x06, 0xF6, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36]), // '╗'
+    (0xBC, [0x36, 0x36, 0x36, 0x36, 0x36, 0xF6, 0x06, 0xFE, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '╝'
+    (0xBF, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF8, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '┐'
+    (0xC0, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '└'
+    (0xC1, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '┴'
+    (0xC2, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '┬'
+    (0xC3, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1F, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '├'
+    (0xC4, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '─'
+    (0xC5, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '┼'
+    (0xC8, [0x36, 0x36, 0x36, 0x36, 0x36, 0x37, 0x30, 0x3F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '╚'
+    (0xC9, [0x00, 0x00, 0x00, 0x00, 0x00, 0x3F, 0x30, 0x37, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36]), // '╔'
+    (0xCA, [0x36, 0x36, 0x36, 0x36, 0x36, 0xF7, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '╩'
+    (0xCB, [0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0xF7, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36]), // '╦'
+    (0xCC, [0x36, 0x36, 0x36, 0x36, 0x36, 0x37, 0x30, 0x37, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36, 0x36]), // '╠'
+    (0xCD, [0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '═'
+    (0xD9, [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0xF8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '┘'
+    (0xDA, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18]), // '┌'
+    (0xDB, [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), // '█'
+    (0xDC, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), // '▄'
+    (0xDD, [0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0]), // '▌'
+    (0xDE, [0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F]), // '▐'
+    (0xDF, [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // '▀'
+    (0xFE, [0x00, 0x00, 0x00, 0x00, 0x00, 0x7C, 0x7C, 0x7C, 0x7C, 0x7C, 0x7C, 0x7C, 0x00, 0x00, 0x00, 0x00]), // '■'
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// A PSF2 font with a Unicode table: an 'A', a blank that is also
+    /// U+00A0, and an unnamed glyph.
+    fn psf2_font() -> Vec<u8> {
+        let mut data = PSF2_MAGIC.to_vec();
+        for field in [0, 32, PSF2_HAS_TABLE, 3, 4, 4, 2] {
+            data.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        data.extend_from_slice(&[0x40, 0xA0, 0xE0, 0xA0]);
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&[0xC0, 0xC0, 0x00, 0x00]);
+        data.extend_from_slice(b"A\xFF \xC2\xA0\xFF\xFF");
+        data
+    }
+
+    #[test]
+    fn test_psf2_with_unicode_table() {
+        let font = Font::from_psf(&psf2_font()).unwrap();
+        assert_eq!((font.width, font.height), (2, 4));
+        let names: Vec<char> = font.glyphs.iter().map(|glyph| glyph.ch).collect();
+        assert_eq!(names, vec!['A', ' ']);
+        // 0x40 is the right pixel of a 2-pixel row: bit 1.
+        assert_eq!(font.glyphs[0].rows, vec![0b10, 0b01, 0b11, 0b01]);
+
+        assert!(Font::from_psf(&psf2_font()[..40]).is_err());
+        assert!(Font::from_psf(&[0x1F, 0x8B, 0x08]).is_err());
+
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&psf2_font()).unwrap();
+        let unpacked = Font::from_psf(&gzipped.finish().unwrap()).unwrap();
+        assert_eq!(unpacked, font);
+    }
+
+    #[test]
+    fn test_font_without_printable_glyphs_is_rejected() {
+        // Two glyphs, both named by the table as control characters.
+        let mut data = PSF2_MAGIC.to_vec();
+        for field in [0, 32, PSF2_HAS_TABLE, 2, 4, 4, 2] {
+            data.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        data.extend_from_slice(&[0x40, 0xA0, 0xE0, 0xA0, 0x00, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(b"\x01\xFF\x7F\xFF");
+        assert!(Font::from_psf(&data).is_err());
+    }
+
+    #[test]
+    fn test_psf1_falls_back_to_cp437() {
+        let mut data = vec![0x36, 0x04, 0x00, 0x02];
+        for code in 0..=255u8 {
+            data.extend_from_slice(&[code, !code]);
+        }
+        let font = Font::from_psf(&data).unwrap();
+        assert_eq!((font.width, font.height), (8, 2));
+        // NUL and DEL are left out; 0x01..=0x1F are symbols.
+        assert_eq!(font.glyphs.len(), 256 - 2);
+        assert!(font.glyphs.iter().any(|glyph| glyph.ch == '►'));
+        let block = font.glyphs.iter().find(|glyph| glyph.ch == '█').unwrap();
+        assert_eq!(
+            block.rows,
+            vec![0xDB_u8.reverse_bits() as u32, 0x24_u8.reverse_bits() as u32]
+        );
+    }
+
+    #[test]
+    fn test_bundled_vga_glyphs_are_distinct() {
+        let font = Font::vga();
+        assert_eq!(font.glyphs.len(), 95 + 36);
+        for (i, a) in font.glyphs.iter().enumerate() {
+            for b in &font.glyphs[i + 1..] {
+                assert_ne!(a.rows, b.rows, "{:?} and {:?}", a.ch, b.ch);
+            }
+        }
+    }
+}
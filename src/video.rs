@@ -1,21 +1,153 @@
+//! Screenshots from the WebRTC video track.
+//!
+//! A single task reads the track's RTP packets and fans them out over a
+//! broadcast channel; recordings, `FrameStream`s and the screenshot decoder
+//! each hold an `RtpSubscription`, so they can all run at once. A
+//! subscriber that falls more than `RTP_BUFFER` packets behind skips ahead
+//! and sees the gap in the sequence numbers like network loss.
+//!
+//! The first screenshot starts a decoder (a `FrameStream` in RGBA) that keeps
+//! running while the client is connected and caches the latest frame, so
+//! later screenshots are served from memory. `FrameFreshness` decides
+//! whether a cached frame is good enough or the call waits for a newer one.
+
+use crate::frame_stream::{FrameFormat, FrameStream, FrameStreamOptions, VideoFrame};
+use crate::keyframe::KeyframeRequester;
+use crate::screenshot::{frame_image, render_screenshot, Screenshot, ScreenshotOptions};
 use anyhow::{anyhow, Result as AnyResult};
-use gstreamer as gst;
-use gstreamer::prelude::*;
-use gstreamer_app as gst_app;
+use futures_util::StreamExt;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::info;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tracing::{debug, info};
+use webrtc::rtp::packet::Packet;
 use webrtc::track::track_remote::TrackRemote;
 
+/// How long a screenshot waits for an acceptable frame.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+/// RTP packets a subscriber may fall behind before it loses some; about a
+/// second of 1080p video.
+const RTP_BUFFER: usize = 1024;
+
+/// How recent a screenshot has to be.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameFreshness {
+    /// The latest decoded frame, however old. The decoder keeps up with the
+    /// stream, so this is the screen as of the last frame the device sent.
+    #[default]
+    Latest,
+    /// A frame decoded at most this long ago.
+    MaxAge(Duration),
+    /// A frame decoded after the request was made.
+    Next,
+}
+
+impl FrameFreshness {
+    /// Whether a frame decoded at `decoded_at` satisfies a request made at
+    /// `requested`, checked at `now`.
+    pub fn accepts(self, decoded_at: Instant, requested: Instant, now: Instant) -> bool {
+        match self {
+            FrameFreshness::Latest => true,
+            FrameFreshness::MaxAge(max_age) => now.saturating_duration_since(decoded_at) <= max_age,
+            FrameFreshness::Next => decoded_at > requested,
+        }
+    }
+}
+
+/// One reader's share of the video track's RTP packets.
+pub struct RtpSubscription {
+    track: Arc<TrackRemote>,
+    packets: broadcast::Receiver<Arc<Packet>>,
+}
+
+impl RtpSubscription {
+    /// The track the packets come from, for keyframe requests.
+    pub fn track(&self) -> &Arc<TrackRemote> {
+        &self.track
+    }
+
+    /// The next packet, or `None` once the track has ended.
+    pub async fn recv(&mut self) -> Option<Arc<Packet>> {
+        loop {
+            match self.packets.recv().await {
+                Ok(packet) => return Some(packet),
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("RTP subscriber fell behind; {} packets skipped", skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// The task reading one track. It owns the only sender, so subscribers see
+/// the channel close when the track ends or is replaced.
+struct TrackFeed {
+    track: Arc<TrackRemote>,
+    packets: broadcast::Receiver<Arc<Packet>>,
+    task: JoinHandle<()>,
+}
+
+impl TrackFeed {
+    fn start(track: Arc<TrackRemote>) -> Self {
+        let (tx, packets) = broadcast::channel(RTP_BUFFER);
+        let reader = Arc::clone(&track);
+        let task = tokio::spawn(async move {
+            while let Ok((packet, _)) = reader.read_rtp().await {
+                // No subscribers is not an error; the packet is dropped.
+                let _ = tx.send(Arc::new(packet));
+            }
+            debug!("Video track ended");
+        });
+        Self {
+            track,
+            packets,
+            task,
+        }
+    }
+
+    fn subscribe(&self) -> RtpSubscription {
+        RtpSubscription {
+            track: Arc::clone(&self.track),
+            packets: self.packets.resubscribe(),
+        }
+    }
+}
+
+impl Drop for TrackFeed {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The screenshot decoder and the track it decodes.
+struct CachedDecoder {
+    track: Arc<TrackRemote>,
+    frames: watch::Receiver<Option<VideoFrame>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for CachedDecoder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
 #[derive(Clone)]
 pub struct VideoFrameCapture {
-    track: Arc<Mutex<Option<Arc<TrackRemote>>>>,
+    feed: Arc<Mutex<Option<TrackFeed>>>,
+    decoder: Arc<Mutex<Option<CachedDecoder>>>,
+    keyframes: KeyframeRequester,
 }
 
 impl VideoFrameCapture {
     pub fn new() -> Self {
         Self {
-            track: Arc::new(Mutex::new(None)),
+            feed: Arc::new(Mutex::new(None)),
+            decoder: Arc::new(Mutex::new(None)),
+            keyframes: KeyframeRequester::new(),
         }
     }
 
@@ -24,157 +156,153 @@ impl VideoFrameCapture {
             "Video track set: codec={}",
             track.codec().capability.mime_type
         );
-        let mut t = self.track.lock().await;
-        *t = Some(track);
+        let mut feed = self.feed.lock().await;
+        *feed = Some(TrackFeed::start(track));
     }
 
     pub async fn has_track(&self) -> bool {
-        let t = self.track.lock().await;
-        t.is_some()
+        self.feed.lock().await.is_some()
     }
 
-    pub async fn capture_screenshot_png(&self) -> AnyResult<Vec<u8>> {
-        gst::init()?;
-
-        let track_guard = self.track.lock().await;
-        let track = track_guard
-            .as_ref()
-            .ok_or_else(|| anyhow!("No video track available"))?
-            .clone();
-        drop(track_guard);
-
-        info!("Building GStreamer pipeline...");
-
-        let pipeline = gst::Pipeline::new();
-
-        let appsrc = gst::ElementFactory::make("appsrc")
-            .name("src")
-            .property("format", gst::Format::Time)
-            .property_from_str("stream-type", "stream")
-            .build()?;
-
-        let capsfilter = gst::ElementFactory::make("capsfilter").build()?;
-
-        let caps = gst::Caps::builder("application/x-rtp")
-            .field("media", "video")
-            .field("clock-rate", 90000)
-            .field("encoding-name", "H264")
-            .field("payload", 102)
-            .build();
-        capsfilter.set_property("caps", &caps);
-
-        let rtph264depay = gst::ElementFactory::make("rtph264depay").build()?;
-        let h264parse = gst::ElementFactory::make("h264parse").build()?;
-        let avdec_h264 = gst::ElementFactory::make("avdec_h264").build()?;
-        let videoconvert = gst::ElementFactory::make("videoconvert").build()?;
-        let pngenc = gst::ElementFactory::make("pngenc").build()?;
-
-        let appsink = gst::ElementFactory::make("appsink")
-            .property("emit-signals", true)
-            .property("sync", false)
-            .build()?;
-
-        pipeline.add_many([
-            &appsrc,
-            &capsfilter,
-            &rtph264depay,
-            &h264parse,
-            &avdec_h264,
-            &videoconvert,
-            &pngenc,
-            &appsink,
-        ])?;
-
-        gst::Element::link_many([
-            &appsrc,
-            &capsfilter,
-            &rtph264depay,
-            &h264parse,
-            &avdec_h264,
-            &videoconvert,
-            &pngenc,
-            &appsink,
-        ])?;
-
-        let appsrc = appsrc.dynamic_cast::<gst_app::AppSrc>().unwrap();
-        let appsink = appsink.dynamic_cast::<gst_app::AppSink>().unwrap();
-
-        let (frame_tx, mut frame_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(1);
-
-        appsink.set_callbacks(
-            gst_app::AppSinkCallbacks::builder()
-                .new_sample(move |appsink| {
-                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Error)?;
-                    let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
-                    let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
-                    let data = map.as_slice().to_vec();
-                    let _ = frame_tx.try_send(data);
-                    Ok(gst::FlowSuccess::Ok)
-                })
-                .build(),
-        );
-
-        info!("Starting pipeline...");
-        pipeline.set_state(gst::State::Playing)?;
-
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-        info!("Feeding RTP packets to pipeline...");
-
-        let appsrc_clone = appsrc.clone();
-        let track_clone = track.clone();
-        let feed_task = tokio::spawn(async move {
-            while let Ok((rtp_packet, _)) = track_clone.read_rtp().await {
-                let header_size = 12 + (rtp_packet.header.csrc.len() * 4);
-                let total_size = header_size + rtp_packet.payload.len();
-                let mut packet_bytes = Vec::with_capacity(total_size);
+    /// The current video track, if the connection has one yet.
+    pub async fn track(&self) -> Option<Arc<TrackRemote>> {
+        let feed = self.feed.lock().await;
+        feed.as_ref().map(|feed| Arc::clone(&feed.track))
+    }
 
-                let b0 = (rtp_packet.header.version << 6)
-                    | ((rtp_packet.header.padding as u8) << 5)
-                    | ((rtp_packet.header.extension as u8) << 4)
-                    | (rtp_packet.header.csrc.len() as u8);
-                packet_bytes.push(b0);
+    /// Waits up to `timeout` for the connection to deliver its video track.
+    pub async fn wait_for_track(&self, timeout: Duration) -> AnyResult<Arc<TrackRemote>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(track) = self.track().await {
+                return Ok(track);
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!("No video track available"));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
 
-                let b1 = ((rtp_packet.header.marker as u8) << 7) | rtp_packet.header.payload_type;
-                packet_bytes.push(b1);
+    /// Subscribes to the track's RTP packets from now on, waiting up to
+    /// `timeout` for the connection to deliver the track.
+    pub async fn subscribe_rtp(&self, timeout: Duration) -> AnyResult<RtpSubscription> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(feed) = self.feed.lock().await.as_ref() {
+                return Ok(feed.subscribe());
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!("No video track available"));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
 
-                packet_bytes.extend_from_slice(&rtp_packet.header.sequence_number.to_be_bytes());
-                packet_bytes.extend_from_slice(&rtp_packet.header.timestamp.to_be_bytes());
-                packet_bytes.extend_from_slice(&rtp_packet.header.ssrc.to_be_bytes());
+    /// The requester used for this track's keyframe requests.
+    pub fn keyframe_requester(&self) -> KeyframeRequester {
+        self.keyframes.clone()
+    }
 
-                for csrc in &rtp_packet.header.csrc {
-                    packet_bytes.extend_from_slice(&csrc.to_be_bytes());
-                }
+    /// Asks the device's encoder for a keyframe on the current track.
+    pub async fn request_keyframe(&self) -> AnyResult<()> {
+        let track = self
+            .track()
+            .await
+            .ok_or_else(|| anyhow!("No video track available"))?;
+        self.keyframes.request(&track).await
+    }
 
-                packet_bytes.extend_from_slice(&rtp_packet.payload);
+    /// Returns the cached frame receiver, starting the decoder if none is
+    /// running on the current track. The decoder shares the track's packets
+    /// with every other subscriber, so it is only replaced when it stopped
+    /// or the connection delivered a new track.
+    async fn decoder_frames(&self) -> AnyResult<watch::Receiver<Option<VideoFrame>>> {
+        let current = self.track().await;
+        let mut decoder = self.decoder.lock().await;
+        if let Some(running) = decoder.as_ref() {
+            let same_track = current
+                .as_ref()
+                .is_some_and(|track| Arc::ptr_eq(track, &running.track));
+            if same_track && !running.task.is_finished() {
+                return Ok(running.frames.clone());
+            }
+        }
 
-                let buffer = gst::Buffer::from_slice(packet_bytes);
-                if appsrc_clone.push_buffer(buffer).is_err() {
+        let options = FrameStreamOptions {
+            format: FrameFormat::Rgba,
+            ..Default::default()
+        };
+        let mut stream = FrameStream::start(self, options).await?;
+        let track = self
+            .track()
+            .await
+            .ok_or_else(|| anyhow!("No video track available"))?;
+        let (tx, rx) = watch::channel(None);
+        let task = tokio::spawn(async move {
+            while let Some(frame) = stream.next().await {
+                if tx.send(Some(frame)).is_err() {
                     break;
                 }
             }
         });
+        info!("Screenshot decoder started");
+        *decoder = Some(CachedDecoder {
+            track,
+            frames: rx.clone(),
+            task,
+        });
+        Ok(rx)
+    }
 
-        let timeout = tokio::time::sleep(tokio::time::Duration::from_secs(10));
-        tokio::pin!(timeout);
-
-        let png_data = tokio::select! {
-            Some(data) = frame_rx.recv() => {
-                info!("First frame captured ({} bytes)", data.len());
-                data
-            }
-            _ = &mut timeout => {
-                return Err(anyhow!("Timeout waiting for first frame"));
+    /// The latest decoded frame (RGBA) that satisfies `freshness`.
+    pub async fn latest_frame(&self, freshness: FrameFreshness) -> AnyResult<VideoFrame> {
+        let mut frames = self.decoder_frames().await?;
+        let requested = Instant::now();
+        let wait = async {
+            loop {
+                if let Some(frame) = frames.borrow_and_update().as_ref() {
+                    if freshness.accepts(frame.decoded_at, requested, Instant::now()) {
+                        return Ok(frame.clone());
+                    }
+                }
+                frames
+                    .changed()
+                    .await
+                    .map_err(|_| anyhow!("Video decoder stopped"))?;
             }
         };
+        timeout(FRAME_TIMEOUT, wait)
+            .await
+            .map_err(|_| anyhow!("Timeout waiting for a video frame"))?
+    }
 
-        let _ = appsrc.end_of_stream();
-        feed_task.abort();
+    /// A screenshot of the latest frame that satisfies `freshness`,
+    /// cropped, scaled and encoded as `options` asks.
+    pub async fn capture_screenshot(
+        &self,
+        freshness: FrameFreshness,
+        options: &ScreenshotOptions,
+    ) -> AnyResult<Screenshot> {
+        let frame = self.latest_frame(freshness).await?;
+        render_screenshot(frame_image(&frame)?, options)
+    }
 
-        pipeline.set_state(gst::State::Null)?;
+    /// A PNG of the latest frame that satisfies `freshness`.
+    pub async fn capture_screenshot_png_with(
+        &self,
+        freshness: FrameFreshness,
+    ) -> AnyResult<Vec<u8>> {
+        let screenshot = self
+            .capture_screenshot(freshness, &ScreenshotOptions::default())
+            .await?;
+        Ok(screenshot.data)
+    }
 
-        info!("Screenshot captured successfully");
-        Ok(png_data)
+    /// A PNG of the latest frame.
+    pub async fn capture_screenshot_png(&self) -> AnyResult<Vec<u8>> {
+        self.capture_screenshot_png_with(FrameFreshness::Latest)
+            .await
     }
 }
 
@@ -185,12 +313,29 @@ impl Default for VideoFrameCapture {
 }
 
 use crate::jetkvm_rpc_client::JetKvmRpcClient;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+/// The device's view of the HDMI input, as returned by `getVideoState`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoState {
+    pub ready: bool,
+    pub error: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
 pub async fn rpc_get_video_state(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getVideoState", json!({})).await
 }
 
+/// Reads the video input state as a typed value.
+pub async fn get_video_state(client: &JetKvmRpcClient) -> AnyResult<VideoState> {
+    client.call("getVideoState", json!({})).await
+}
+
 pub async fn rpc_get_stream_quality_factor(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getStreamQualityFactor", json!({})).await
 }
@@ -198,3 +343,23 @@ pub async fn rpc_get_stream_quality_factor(client: &JetKvmRpcClient) -> AnyResul
 pub async fn rpc_get_video_log_status(client: &JetKvmRpcClient) -> AnyResult<Value> {
     client.send_rpc("getVideoLogStatus", json!({})).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_freshness() {
+        let requested = Instant::now();
+        let before = requested - Duration::from_millis(500);
+        let after = requested + Duration::from_millis(10);
+        let now = requested + Duration::from_millis(100);
+
+        assert!(FrameFreshness::Latest.accepts(before, requested, now));
+        let max_age = FrameFreshness::MaxAge(Duration::from_secs(1));
+        assert!(max_age.accepts(before, requested, now));
+        assert!(!FrameFreshness::MaxAge(Duration::from_millis(200)).accepts(before, requested, now));
+        assert!(!FrameFreshness::Next.accepts(before, requested, now));
+        assert!(FrameFreshness::Next.accepts(after, requested, now));
+    }
+}
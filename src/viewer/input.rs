@@ -2,97 +2,127 @@
 //! HID reports that the JetKVM RPCs (`keyboardReport`, `absMouseReport`,
 //! `wheelReport`) expect.
 
-use arrayvec::ArrayVec;
+use crate::keyboard_state::HidKeyboardState;
+use crate::mouse::{normalized_to_abs, WheelAccumulator};
 use winit::event::{ElementState, MouseButton, MouseScrollDelta};
 use winit::keyboard::KeyCode;
 
-/// JetKVM's USB-HID absolute pointer descriptor uses Logical Maximum 0x7FFF
-/// for both X and Y. The firmware does not scale this to the EDID resolution;
-/// the host's HID driver maps 0..32767 → full screen. We therefore always send
-/// coordinates in this range, regardless of the remote frame resolution.
-pub const ABS_MOUSE_MAX: i64 = 0x7FFF;
+pub use crate::mouse::ABS_MOUSE_MAX;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InputEvent {
     Keyboard { modifier: u8, keys: Vec<u8> },
     AbsMouse { x: i64, y: i64, buttons: u8 },
-    Wheel { dy: i64 },
+    RelMouse { dx: i64, dy: i64, buttons: u8 },
+    Wheel { dy: i64, dx: i64 },
 }
 
 /// Tracks the current set of held keys + active modifier byte.
-/// Mirrors a real boot-protocol USB HID keyboard report (1 modifier byte +
-/// up to 6 keys).
+/// Backed by the shared `HidKeyboardState`, so it mirrors a real
+/// boot-protocol USB HID keyboard report (1 modifier byte + up to 6 keys in
+/// press order, `ErrorRollOver` when more are held).
+#[derive(Default)]
 pub struct KeyboardState {
-    pub modifier: u8,
-    pub keys: ArrayVec<u8, 6>,
+    state: HidKeyboardState,
 }
 
-impl Default for KeyboardState {
-    fn default() -> Self {
-        Self {
+impl KeyboardState {
+    /// Whether this key event is the host hotkey that toggles relative mouse
+    /// mode (Ctrl+Alt+G). The viewer handles it locally and never forwards
+    /// the G press; the Ctrl and Alt presses have already gone out, so it
+    /// releases them with `release_all`.
+    pub fn is_relative_mode_hotkey(&self, code: KeyCode, state: ElementState) -> bool {
+        let modifier = self.state.modifier();
+        code == KeyCode::KeyG
+            && state == ElementState::Pressed
+            && modifier & (CTRL_LEFT | CTRL_RIGHT) != 0
+            && modifier & (ALT_LEFT | ALT_RIGHT) != 0
+    }
+
+    /// Forgets every held key and returns the all-up report. Keys released
+    /// afterwards produce no further reports.
+    pub fn release_all(&mut self) -> InputEvent {
+        self.state.clear();
+        InputEvent::Keyboard {
             modifier: 0,
-            keys: ArrayVec::new(),
+            keys: vec![],
         }
     }
-}
 
-impl KeyboardState {
     /// Returns Some(InputEvent::Keyboard) if the report has changed.
     pub fn handle(&mut self, code: KeyCode, state: ElementState) -> Option<InputEvent> {
         let pressed = state == ElementState::Pressed;
+        let before = self.state.report();
+
         if let Some(mask) = modifier_mask(code) {
-            let new_mod = if pressed {
-                self.modifier | mask
-            } else {
-                self.modifier & !mask
-            };
-            if new_mod == self.modifier {
-                return None;
+            // A physical modifier key is either down or up; don't let a
+            // duplicate press/release skew the reference count.
+            let held = self.state.modifier() & mask != 0;
+            if pressed && !held {
+                self.state.press_modifiers(mask);
+            } else if !pressed && held {
+                self.state.release_modifiers(mask);
             }
-            self.modifier = new_mod;
-            return Some(self.emit());
-        }
-
-        let Some(hid) = keycode_to_hid(code) else {
-            return None;
-        };
-        let already = self.keys.contains(&hid);
-        let changed = if pressed {
-            if !already && self.keys.try_push(hid).is_ok() {
-                true
+        } else {
+            let hid = keycode_to_hid(code)?;
+            if pressed {
+                self.state.press_key(hid);
             } else {
-                false
+                self.state.release_key(hid);
             }
-        } else if let Some(idx) = self.keys.iter().position(|&k| k == hid) {
-            self.keys.remove(idx);
-            true
-        } else {
-            false
-        };
-
-        if changed {
-            Some(self.emit())
-        } else {
-            None
         }
-    }
 
-    fn emit(&self) -> InputEvent {
-        InputEvent::Keyboard {
-            modifier: self.modifier,
-            keys: self.keys.iter().copied().collect(),
+        let report = self.state.report();
+        if report == before {
+            return None;
         }
+        Some(InputEvent::Keyboard {
+            modifier: report.modifier,
+            keys: report.keys,
+        })
     }
 }
 
+/// Trackpad pixels that make one wheel detent.
+const PIXELS_PER_DETENT: f64 = 30.0;
+/// Largest movement in one relative report; the HID fields are signed bytes.
+const REL_MOUSE_MAX: f64 = 127.0;
+
 #[derive(Default)]
 pub struct MouseState {
     pub buttons: u8,
     pub last_x: i64,
     pub last_y: i64,
+    /// Relative mode: buttons go out as `RelMouse` reports and motion comes
+    /// from raw device deltas instead of the cursor position.
+    pub relative: bool,
+    wheel: WheelAccumulator,
+    /// Sub-count motion and anything beyond one report, carried over.
+    rel_rest: (f64, f64),
 }
 
 impl MouseState {
+    /// Turns raw device motion into a relative report. Fractional counts and
+    /// movement beyond the HID range are carried over to the next call.
+    /// Returns `None` outside relative mode or when nothing is due yet.
+    pub fn handle_device_motion(&mut self, dx: f64, dy: f64) -> Option<InputEvent> {
+        if !self.relative {
+            return None;
+        }
+        let (rest_x, rest_y) = (self.rel_rest.0 + dx, self.rel_rest.1 + dy);
+        let send_x = rest_x.trunc().clamp(-REL_MOUSE_MAX, REL_MOUSE_MAX);
+        let send_y = rest_y.trunc().clamp(-REL_MOUSE_MAX, REL_MOUSE_MAX);
+        self.rel_rest = (rest_x - send_x, rest_y - send_y);
+        if send_x == 0.0 && send_y == 0.0 {
+            return None;
+        }
+        Some(InputEvent::RelMouse {
+            dx: send_x as i64,
+            dy: send_y as i64,
+            buttons: self.buttons,
+        })
+    }
+
     pub fn handle_motion(
         &mut self,
         widget_x: f64,
@@ -115,6 +145,8 @@ impl MouseState {
             MouseButton::Left => 0b001,
             MouseButton::Right => 0b010,
             MouseButton::Middle => 0b100,
+            MouseButton::Back => 0b1000,
+            MouseButton::Forward => 0b10000,
             _ => 0,
         };
         if state == ElementState::Pressed {
@@ -122,6 +154,13 @@ impl MouseState {
         } else {
             self.buttons &= !mask;
         }
+        if self.relative {
+            return InputEvent::RelMouse {
+                dx: 0,
+                dy: 0,
+                buttons: self.buttons,
+            };
+        }
         InputEvent::AbsMouse {
             x: self.last_x,
             y: self.last_y,
@@ -129,21 +168,18 @@ impl MouseState {
         }
     }
 
-    pub fn handle_wheel(&self, delta: MouseScrollDelta) -> Option<InputEvent> {
-        let dy = match delta {
-            MouseScrollDelta::LineDelta(_, y) => y as f64,
-            MouseScrollDelta::PixelDelta(p) => p.y / 30.0,
+    /// Accumulates fractional scrolling and returns an event once at least
+    /// one whole detent is due on either axis. Positive `dy` scrolls up,
+    /// positive `dx` right.
+    pub fn handle_wheel(&mut self, delta: MouseScrollDelta) -> Option<InputEvent> {
+        let (dx, dy) = match delta {
+            MouseScrollDelta::LineDelta(x, y) => (x as f64, y as f64),
+            MouseScrollDelta::PixelDelta(p) => (p.x / PIXELS_PER_DETENT, p.y / PIXELS_PER_DETENT),
         };
-        if dy.abs() < 0.01 {
-            return None;
-        }
-        // JetKVM wheel report convention: positive = scroll up.
-        let v = dy.round() as i64;
-        if v == 0 {
-            None
-        } else {
-            Some(InputEvent::Wheel { dy: v })
-        }
+        // winit's positive x moves the content right, i.e. scrolls left;
+        // positive y already matches the wheel report's "scroll up".
+        let (dy, dx) = self.wheel.add(dy, -dx)?;
+        Some(InputEvent::Wheel { dy, dx })
     }
 }
 
@@ -151,23 +187,23 @@ fn map_to_abs(x: f64, y: f64, w: f64, h: f64) -> (i64, i64) {
     if w <= 0.0 || h <= 0.0 {
         return (0, 0);
     }
-    let nx = (x.max(0.0).min(w) / w) * ABS_MOUSE_MAX as f64;
-    let ny = (y.max(0.0).min(h) / h) * ABS_MOUSE_MAX as f64;
-    (
-        (nx.round() as i64).clamp(0, ABS_MOUSE_MAX),
-        (ny.round() as i64).clamp(0, ABS_MOUSE_MAX),
-    )
+    normalized_to_abs(x / w, y / h)
 }
 
+const CTRL_LEFT: u8 = 0x01;
+const ALT_LEFT: u8 = 0x04;
+const CTRL_RIGHT: u8 = 0x10;
+const ALT_RIGHT: u8 = 0x40;
+
 fn modifier_mask(code: KeyCode) -> Option<u8> {
     Some(match code {
-        KeyCode::ControlLeft => 0x01,
+        KeyCode::ControlLeft => CTRL_LEFT,
         KeyCode::ShiftLeft => 0x02,
-        KeyCode::AltLeft => 0x04,
+        KeyCode::AltLeft => ALT_LEFT,
         KeyCode::SuperLeft => 0x08,
-        KeyCode::ControlRight => 0x10,
+        KeyCode::ControlRight => CTRL_RIGHT,
         KeyCode::ShiftRight => 0x20,
-        KeyCode::AltRight => 0x40,
+        KeyCode::AltRight => ALT_RIGHT,
         KeyCode::SuperRight => 0x80,
         _ => return None,
     })
@@ -289,3 +325,76 @@ pub fn keycode_to_hid(code: KeyCode) -> Option<u8> {
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rel(dx: i64, dy: i64) -> Option<InputEvent> {
+        Some(InputEvent::RelMouse { dx, dy, buttons: 0 })
+    }
+
+    #[test]
+    fn test_device_motion_accumulates_sub_count_movement() {
+        let mut mouse = MouseState::default();
+        assert_eq!(mouse.handle_device_motion(5.0, 0.0), None);
+
+        mouse.relative = true;
+        assert_eq!(mouse.handle_device_motion(0.4, -0.4), None);
+        assert_eq!(mouse.handle_device_motion(0.4, -0.4), None);
+        assert_eq!(mouse.handle_device_motion(0.4, -0.4), rel(1, -1));
+        // 0.2 is left over on each axis.
+        assert_eq!(mouse.handle_device_motion(0.7, 0.0), None);
+        assert_eq!(mouse.handle_device_motion(0.1, 0.0), rel(1, 0));
+    }
+
+    #[test]
+    fn test_device_motion_clamps_and_carries_over() {
+        let mut mouse = MouseState {
+            relative: true,
+            ..Default::default()
+        };
+        assert_eq!(mouse.handle_device_motion(300.0, -200.0), rel(127, -127));
+        assert_eq!(mouse.handle_device_motion(0.0, 0.0), rel(127, -73));
+        assert_eq!(mouse.handle_device_motion(0.0, 0.0), rel(46, 0));
+        assert_eq!(mouse.handle_device_motion(0.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_relative_mode_hotkey_is_ctrl_alt_g_press() {
+        let mut keyboard = KeyboardState::default();
+        assert!(!keyboard.is_relative_mode_hotkey(KeyCode::KeyG, ElementState::Pressed));
+
+        keyboard.handle(KeyCode::ControlLeft, ElementState::Pressed);
+        assert!(!keyboard.is_relative_mode_hotkey(KeyCode::KeyG, ElementState::Pressed));
+
+        keyboard.handle(KeyCode::AltRight, ElementState::Pressed);
+        assert!(keyboard.is_relative_mode_hotkey(KeyCode::KeyG, ElementState::Pressed));
+        assert!(!keyboard.is_relative_mode_hotkey(KeyCode::KeyG, ElementState::Released));
+        assert!(!keyboard.is_relative_mode_hotkey(KeyCode::KeyH, ElementState::Pressed));
+    }
+
+    #[test]
+    fn test_release_all_lets_go_of_the_hotkey_modifiers() {
+        let mut keyboard = KeyboardState::default();
+        keyboard.handle(KeyCode::ControlLeft, ElementState::Pressed);
+        keyboard.handle(KeyCode::AltLeft, ElementState::Pressed);
+        assert_eq!(
+            keyboard.release_all(),
+            InputEvent::Keyboard {
+                modifier: 0,
+                keys: vec![]
+            }
+        );
+        // Letting go of the physical keys afterwards sends nothing.
+        assert_eq!(keyboard.handle(KeyCode::KeyG, ElementState::Released), None);
+        assert_eq!(
+            keyboard.handle(KeyCode::AltLeft, ElementState::Released),
+            None
+        );
+        assert_eq!(
+            keyboard.handle(KeyCode::ControlLeft, ElementState::Released),
+            None
+        );
+    }
+}
@@ -4,5 +4,7 @@
 
 pub mod edid;
 pub mod input;
+#[cfg(feature = "gstreamer")]
 pub mod pipeline;
+#[cfg(feature = "gstreamer")]
 pub mod render;